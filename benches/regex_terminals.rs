@@ -0,0 +1,48 @@
+//! Generation time of a regex-heavy grammar with thousands of terminals,
+//! where every regex expansion checks its output against the terminal set.
+//!
+//! Run with `cargo bench --bench regex_terminals`.
+
+use std::fmt::Write;
+use std::time::Instant;
+
+use bnfgen::generator::{Generator, GeneratorSettings};
+use bnfgen::grammar::raw::RawGrammar;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+const KEYWORDS: usize = 5000;
+const ITERS: usize = 200;
+
+fn grammar() -> String {
+    let mut text = String::from("<S> ::= <Ident> <S> | <Ident> {1, 20} ;\n<Ident> ::= re(\"[a-z]{3}\") | <Keyword> ;\n<Keyword> ::= ");
+    let keywords = (0..KEYWORDS)
+        .map(|i| format!("\"kw{}\"", i))
+        .collect::<Vec<_>>();
+    writeln!(text, "{} ;", keywords.join(" | ")).unwrap();
+    text
+}
+
+fn bench(name: &str, settings: GeneratorSettings) {
+    let grammar = RawGrammar::parse(grammar()).unwrap().to_checked().unwrap();
+    let gen = Generator::builder()
+        .grammar(grammar)
+        .settings(settings)
+        .build();
+    let mut rng = StdRng::seed_from_u64(42);
+
+    let start = Instant::now();
+    for _ in 0..ITERS {
+        std::hint::black_box(gen.generate("S", &mut rng).unwrap());
+    }
+    let elapsed = start.elapsed();
+    println!("{:<24} {:>10.2?} / string", name, elapsed / ITERS as u32);
+}
+
+fn main() {
+    bench("avoid terminals", GeneratorSettings::default());
+    bench(
+        "allow collisions",
+        GeneratorSettings::builder().avoid_terminals(false).build(),
+    );
+}
@@ -2,7 +2,6 @@ use std::sync::Arc;
 
 use bnfgen::error::Result;
 use bnfgen::generator::Generator;
-use bnfgen::grammar::raw::RawGrammar;
 use bnfgen::report::Style;
 use miette::Report;
 
@@ -18,9 +17,8 @@ fn main() {
 }
 
 fn run(input: &str) -> Result<()> {
-    let grammar = RawGrammar::parse(input)?.to_checked()?;
-    let gen = Generator::builder().grammar(grammar).build();
-    let out = gen.generate("Program", &mut rand::thread_rng());
+    let gen = input.parse::<Generator>()?;
+    let out = gen.generate("Program", &mut rand::thread_rng())?;
     println!("{}", out);
     Ok(())
 }
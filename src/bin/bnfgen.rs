@@ -1,69 +1,810 @@
+use bnfgen::coverage::CoverageTracker;
+use bnfgen::error::Error;
+use bnfgen::generator::{Generator, GeneratorSettings, TreeGenerator};
 use bnfgen::grammar::raw::RawGrammar;
+use bnfgen::grammar::state::State;
+use bnfgen::grammar::symbol::SymbolKind;
+use bnfgen::parse_tree::tree::ParseTree;
 use bnfgen::report::{Reporter, Style};
-use clap::Parser;
-use miette::Report;
-use std::path::PathBuf;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use miette::{MietteError, MietteSpanContents, Report, SourceCode, SourceSpan, SpanContents};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
 
 #[derive(Parser, Debug, Clone)]
 pub struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum Command {
+    /// Check the grammar for errors
+    Check(CheckArgs),
+    /// Generate random strings from the grammar
+    Gen(GenArgs),
+    /// Generate random parse trees from the grammar
+    Tree(TreeArgs),
+    /// Report how often each alternative is chosen over many generations
+    Coverage(CoverageArgs),
+    /// Export the dependency graph of the rules
+    Graph(GraphArgs),
+    /// Print size metrics of the grammar, one per line
+    Stats(StatsArgs),
+    #[cfg(feature = "serde")]
+    /// Dump the parsed grammar as JSON
+    Ast(AstArgs),
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum Format {
+    /// Human readable diagnostics
+    #[default]
+    Human,
+    #[cfg(feature = "serde")]
+    /// One JSON object per diagnostic and per line
+    Json,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum TreeFormat {
+    /// The `Debug` representation
+    Debug,
+    /// S-expressions, e.g. `(S (E "a") "+" (E "b"))`
+    Sexpr,
+    #[cfg(feature = "serde")]
+    /// One JSON object per tree and per line
+    Json,
+}
+
+impl TreeFormat {
+    fn render(self, tree: &ParseTree<SymbolKind>) -> String {
+        match self {
+            TreeFormat::Debug => format!("{:#?}", tree),
+            TreeFormat::Sexpr => tree.to_sexpr(),
+            #[cfg(feature = "serde")]
+            TreeFormat::Json => serde_json::to_string(tree).unwrap(),
+        }
+    }
+}
+
+#[derive(Args, Debug, Clone)]
+struct CheckArgs {
     #[arg(short, long)]
-    /// Path to the BNF grammar file
+    /// Path to the BNF grammar file, `-` to read from stdin
     grammar: PathBuf,
+    #[arg(long, value_enum, default_value_t)]
+    /// Format of the diagnostics
+    format: Format,
     #[arg(long)]
-    /// Check for unreachable rules (need to give the starting rule)
+    /// Check for unreachable rules and for references whose declarations are all unreachable
+    /// (need to give the starting rule)
     check_unused: Option<String>,
+    #[arg(long)]
+    /// Check if the starting rule can derive the empty string
+    check_nullable: Option<String>,
+}
+
+#[derive(Args, Debug, Clone)]
+struct GenArgs {
+    #[arg(short, long)]
+    /// Path to the BNF grammar file, `-` to read from stdin
+    grammar: PathBuf,
+    #[arg(long, value_enum, default_value_t)]
+    /// Format of the diagnostics
+    format: Format,
+    #[arg(short, long)]
+    /// The starting rule of the generation
+    start: String,
+    #[arg(short, long, default_value_t = 1)]
+    /// Number of strings to generate, `0` to stream them until killed,
+    /// flushing every string, e.g. to pipe them into a fuzzer
+    count: usize,
+    #[arg(long)]
+    /// Seed of the random number generator
+    seed: Option<u64>,
+    #[arg(long, conflicts_with_all = ["seed", "unique"])]
+    /// Seed the `i`-th string with `seed_base + i`, so that any of them can be reproduced
+    seed_base: Option<u64>,
+    #[arg(long, requires = "seed_base")]
+    /// Prefix each generated string with its seed and a tab
+    show_seeds: bool,
+    #[arg(long)]
+    /// Only output distinct strings, may output less than `count` strings
+    /// if the grammar can not produce enough of them within `max_attempts`
+    unique: bool,
+    #[arg(long, default_value_t = 1000)]
+    /// Maximum number of generation attempts when `unique` is set,
+    /// or of a string whose generation exceeds `max_steps` or `timeout`
+    max_attempts: usize,
+    #[arg(long)]
+    /// Maximum number of steps of a single generation
+    max_steps: Option<usize>,
+    #[arg(long, value_name = "MS")]
+    /// Maximum duration (in milliseconds) of a single generation
+    timeout: Option<u64>,
+    #[arg(long)]
+    /// Maximum length (in chars) of a single generated string
+    max_output_len: Option<usize>,
+    #[arg(long, default_value_t = 5)]
+    /// Maximum count of an unbounded repetition in regex, e.g. `a+` or `a{2,}`,
+    /// unless set by `re("...", max = N)`
+    repeat_max: u32,
+    #[arg(long, default_value_t = 1 << 20)]
+    /// Maximum length (in bytes) of a string generated by a single regex
+    max_regex_len: usize,
+    #[arg(long)]
+    /// Randomly flip the case of the letters produced by regex classes
+    randomize_case: bool,
+    #[arg(long)]
+    /// Let a regex generate a string equal to a terminal of the grammar
+    allow_terminal_collisions: bool,
+    #[arg(short, long)]
+    /// Write the generated strings to the file instead of stdout
+    output: Option<PathBuf>,
+    #[arg(long)]
+    /// Separate the generated strings with `\0` instead of newlines
+    null_sep: bool,
+    #[arg(long, conflicts_with_all = ["unique", "tree"])]
+    /// Print the derivation steps of every generated string to stderr
+    explain: bool,
+    #[arg(long, value_enum, conflicts_with = "unique")]
+    /// Output the parse trees in the given format instead of the strings
+    tree: Option<TreeFormat>,
+}
+
+#[derive(Args, Debug, Clone)]
+struct TreeArgs {
+    #[arg(short, long)]
+    /// Path to the BNF grammar file, `-` to read from stdin
+    grammar: PathBuf,
+    #[arg(long, value_enum, default_value_t)]
+    /// Format of the diagnostics
+    format: Format,
+    #[arg(short, long)]
+    /// The starting rule of the generation
+    start: String,
+    #[arg(short, long, default_value_t = 1)]
+    /// Number of trees to generate
+    count: usize,
+    #[arg(long)]
+    /// Seed of the random number generator
+    seed: Option<u64>,
+    #[arg(long, value_enum, default_value_t = TreeFormat::Sexpr)]
+    /// Format of the parse trees
+    tree_format: TreeFormat,
+}
+
+#[derive(Args, Debug, Clone)]
+struct CoverageArgs {
+    #[arg(short, long)]
+    /// Path to the BNF grammar file, `-` to read from stdin
+    grammar: PathBuf,
+    #[arg(long, value_enum, default_value_t)]
+    /// Format of the diagnostics
+    format: Format,
+    #[arg(short, long)]
+    /// The starting rule of the generation
+    start: String,
+    #[arg(short, long, default_value_t = 1000)]
+    /// Number of strings to generate
+    count: usize,
+    #[arg(long)]
+    /// Seed of the random number generator
+    seed: Option<u64>,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum GraphFormat {
+    /// Graphviz DOT, e.g. to be piped into `dot -Tsvg`
+    #[default]
+    Dot,
+}
+
+#[derive(Args, Debug, Clone)]
+struct GraphArgs {
+    #[arg(short, long)]
+    /// Path to the BNF grammar file, `-` to read from stdin
+    grammar: PathBuf,
+    #[arg(long, value_enum, default_value_t)]
+    /// Format of the diagnostics
+    format: Format,
+    #[arg(long, value_enum, default_value_t)]
+    /// Format of the graph
+    graph_format: GraphFormat,
+    #[arg(short, long)]
+    /// The starting rule, the rules unreachable from it are grayed out
+    start: Option<String>,
+}
+
+#[derive(Args, Debug, Clone)]
+struct StatsArgs {
+    #[arg(short, long)]
+    /// Path to the BNF grammar file, `-` to read from stdin
+    grammar: PathBuf,
+    #[arg(long, value_enum, default_value_t)]
+    /// Format of the diagnostics
+    format: Format,
+}
+
+#[cfg(feature = "serde")]
+#[derive(Args, Debug, Clone)]
+struct AstArgs {
+    #[arg(short, long)]
+    /// Path to the BNF grammar file, `-` to read from stdin
+    grammar: PathBuf,
+}
+
+/// a grammar file, either the one given on the command line or an included one
+#[derive(Debug, Clone)]
+struct SourceFile {
+    /// the path as written, relative to the working directory
+    name: String,
+    /// used to detect include cycles, `None` for stdin
+    canonical: Option<PathBuf>,
+    /// the range of the file in `Sources::text`
+    range: Range<usize>,
+}
+
+/// All the grammar files concatenated into one text, so that spans of different files
+/// never overlap and a single `RawGrammar` can be parsed from them, while diagnostics
+/// are still rendered against the file containing the span.
+#[derive(Debug, Clone, Default)]
+struct Sources {
+    text: String,
+    files: Vec<SourceFile>,
+}
+
+impl Sources {
+    /// append a file, return its index
+    fn push(&mut self, name: String, canonical: Option<PathBuf>, text: &str) -> usize {
+        let start = self.text.len();
+        self.text.push_str(text);
+        self.files.push(SourceFile {
+            name,
+            canonical,
+            range: start..self.text.len(),
+        });
+        self.files.len() - 1
+    }
+
+    /// the file containing the byte offset `offset` of the text
+    fn file(&self, offset: usize) -> &SourceFile {
+        self.files
+            .iter()
+            .rev()
+            .find(|file| file.range.start <= offset)
+            .unwrap_or(&self.files[0])
+    }
+
+    /// Parse the file of index `index` and, recursively, the files it includes, relative to
+    /// its directory. A file included twice is only spliced in once, unless it includes itself
+    /// (directly or not), which is reported as an include cycle.
+    fn load(
+        &mut self,
+        index: usize,
+        stack: &mut Vec<PathBuf>,
+    ) -> bnfgen::error::Result<RawGrammar> {
+        let file = &self.files[index];
+        let dir = match file.canonical {
+            Some(_) => Path::new(&file.name)
+                .parent()
+                .unwrap_or(Path::new(""))
+                .to_path_buf(),
+            None => PathBuf::new(),
+        };
+        let grammar = RawGrammar::parse_at(&self.text, file.range.start)?;
+        grammar.resolve_includes(|path, span| {
+            let path = dir.join(path);
+            let invalid = |e: std::io::Error| Error::InvalidInclude {
+                span,
+                path: path.display().to_string(),
+                reason: e.to_string(),
+            };
+            let canonical = path.canonicalize().map_err(invalid)?;
+            if stack.contains(&canonical) {
+                return Err(Error::IncludeCycle {
+                    span,
+                    path: path.display().to_string(),
+                });
+            }
+            if self
+                .files
+                .iter()
+                .any(|f| f.canonical.as_ref() == Some(&canonical))
+            {
+                return Ok(RawGrammar::default());
+            }
+            let text = std::fs::read_to_string(&canonical).map_err(invalid)?;
+            let index = self.push(path.display().to_string(), Some(canonical.clone()), &text);
+            stack.push(canonical);
+            let grammar = self.load(index, stack);
+            stack.pop();
+            grammar
+        })
+    }
+}
+
+impl SourceCode for Sources {
+    fn read_span<'a>(
+        &'a self,
+        span: &SourceSpan,
+        context_lines_before: usize,
+        context_lines_after: usize,
+    ) -> Result<Box<dyn SpanContents<'a> + 'a>, MietteError> {
+        let file = self.file(span.offset());
+        let start = file.range.start;
+        let local = SourceSpan::new((span.offset() - start).into(), span.len());
+        let contents = self.text[file.range.clone()].read_span(
+            &local,
+            context_lines_before,
+            context_lines_after,
+        )?;
+        // the lines and columns are relative to the file, the span to the whole text
+        Ok(Box::new(MietteSpanContents::new_named(
+            file.name.clone(),
+            contents.data(),
+            SourceSpan::new(
+                (contents.span().offset() + start).into(),
+                contents.span().len(),
+            ),
+            contents.line(),
+            contents.column(),
+            contents.line_count(),
+        )))
+    }
+}
+
+struct App {
+    sources: Arc<Sources>,
+    reporter: Reporter,
+    format: Format,
+    /// whether an error has been reported, the warnings alone do not fail
+    failed: bool,
+    /// diagnostics serialized as JSON, used instead of the reporter in the `json` format
+    #[cfg(feature = "serde")]
+    json: Vec<String>,
+}
+
+impl App {
+    fn new(grammar: &PathBuf, format: Format) -> Self {
+        let mut app = Self {
+            sources: Arc::new(Sources::default()),
+            reporter: Reporter::new(Style::NoColor),
+            format,
+            failed: false,
+            #[cfg(feature = "serde")]
+            json: Vec::new(),
+        };
+        let (name, canonical, text) = match grammar.to_str() {
+            Some("-") => (
+                "<stdin>".to_string(),
+                None,
+                std::io::read_to_string(std::io::stdin()),
+            ),
+            _ => (
+                grammar.display().to_string(),
+                grammar.canonicalize().ok(),
+                std::fs::read_to_string(grammar),
+            ),
+        };
+        match text {
+            Ok(text) => {
+                let mut sources = Sources::default();
+                sources.push(name, canonical, &text);
+                app.sources = Arc::new(sources);
+            }
+            Err(e) => {
+                let message = format!("{}: {}", grammar.display(), e);
+                app.report(Error::Io { message });
+                app.shutdown();
+            }
+        }
+        app
+    }
+
+    fn report(&mut self, e: Error) {
+        self.failed |= !e.is_warning();
+        match self.format {
+            Format::Human => {
+                let diagnostic = Report::from(e).with_source_code(self.sources.clone());
+                self.reporter.push(diagnostic);
+            }
+            #[cfg(feature = "serde")]
+            Format::Json => {
+                // make the offsets relative to the file containing them
+                let mut json = serde_json::to_value(&e).unwrap();
+                if let Some(labels) = json["labels"].as_array_mut() {
+                    for label in labels {
+                        let start = label["start"].as_u64().unwrap() as usize;
+                        let end = label["end"].as_u64().unwrap() as usize;
+                        let file = self.sources.file(start);
+                        label["file"] = file.name.clone().into();
+                        label["start"] = (start - file.range.start).into();
+                        label["end"] = (end - file.range.start).into();
+                    }
+                }
+                self.json.push(json.to_string());
+            }
+        }
+    }
+
+    /// parse the grammar file and the files it includes into one grammar
+    fn load(&mut self) -> Option<RawGrammar> {
+        let sources = Arc::make_mut(&mut self.sources);
+        let mut stack = sources.files[0].canonical.iter().cloned().collect();
+        match sources
+            .load(0, &mut stack)
+            .and_then(RawGrammar::resolve_likes)
+        {
+            Ok(grammar) => Some(grammar),
+            Err(e) => {
+                self.report(e);
+                None
+            }
+        }
+    }
+
+    /// report an error which is not caused by the grammar, e.g. an io error
+    fn fail(&mut self, msg: String) {
+        self.failed = true;
+        match self.format {
+            Format::Human => self.reporter.push(Report::msg(msg)),
+            #[cfg(feature = "serde")]
+            Format::Json => {
+                let json = serde_json::json!({
+                    "kind": "Io",
+                    "severity": "error",
+                    "message": msg,
+                    "labels": [],
+                });
+                self.json.push(json.to_string());
+            }
+        }
+    }
+
+    fn shutdown(&self) -> ! {
+        #[cfg(feature = "serde")]
+        if !self.json.is_empty() {
+            let mut stderr = std::io::stderr().lock();
+            for json in &self.json {
+                let _ = writeln!(stderr, "{}", json);
+            }
+        }
+        if self.reporter.has_diagnostics() {
+            let _ = self.reporter.report_io(&mut std::io::stderr().lock());
+        }
+        std::process::exit(self.failed as i32);
+    }
+
+    /// parse the grammar and run the lints, return `None` if any error is found,
+    /// the warnings are reported but do not stop
+    fn parse(&mut self, start: Option<&str>) -> Option<RawGrammar> {
+        let grammar = self.load()?;
+
+        let errors = [
+            grammar.check_undefined().err(),
+            grammar.check_duplicate().err(),
+            grammar.check_duplicate_alternatives().err(),
+            grammar.check_labels().err(),
+            grammar.check_references().err(),
+            grammar.check_repeats().err(),
+            grammar.check_weights().err(),
+            grammar.check_regex_collisions().err(),
+        ];
+        let mut has_error = false;
+        for e in errors.into_iter().flatten() {
+            has_error |= !e.is_warning();
+            self.report(e);
+        }
+
+        if !has_error {
+            let graph = grammar.graph();
+            if let Err(e) = graph.check_trap_loop() {
+                self.report(e);
+                has_error = true;
+            }
+            if let Err(e) = graph.check_invoke_limits() {
+                // a warning, the limit may be satisfied by a state shared across generations
+                has_error |= !e.is_warning();
+                self.report(e);
+            }
+            if let Some(start) = start {
+                let unused = graph.check_unused(start).err();
+                // an undefined start symbol is reported once
+                let references = match unused {
+                    Some(Error::UndefinedStartSymbol { .. }) => None,
+                    _ => graph.check_references(start).err(),
+                };
+                for e in [unused, references].into_iter().flatten() {
+                    self.report(e);
+                    has_error = true;
+                }
+            }
+        }
+
+        (!has_error).then_some(grammar)
+    }
+
+    fn check(&mut self, args: &CheckArgs) {
+        let grammar = self.parse(args.check_unused.as_deref());
+        if let (Some(grammar), Some(start)) = (grammar, &args.check_nullable) {
+            if let Err(e) = grammar.graph().check_nullable(start) {
+                self.report(e);
+            }
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    fn ast(&mut self) {
+        if let Some(grammar) = self.load() {
+            println!("{}", serde_json::to_string_pretty(&grammar).unwrap());
+        }
+    }
+
+    fn tree(&mut self, args: &TreeArgs) {
+        let checked = |g: RawGrammar| {
+            let g = g.to_checked()?;
+            g.validate_start(&args.start).map(|_| g)
+        };
+        let grammar = match self.parse(None).map(checked) {
+            Some(Ok(g)) => g,
+            Some(Err(e)) => return self.report(e),
+            None => return,
+        };
+        let gen = TreeGenerator::builder().grammar(grammar).build();
+        let mut rng = match args.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        let mut stdout = std::io::stdout().lock();
+        for _ in 0..args.count {
+            let tree = match gen.generate(args.start.as_str(), &mut rng) {
+                Ok(tree) => tree,
+                Err(e) => return self.report(e),
+            };
+            if let Err(e) = writeln!(stdout, "{}", args.tree_format.render(&tree)) {
+                let msg = format!("Failed to write output: {}", e);
+                return self.fail(msg);
+            }
+        }
+    }
+
+    fn coverage(&mut self, args: &CoverageArgs) {
+        let checked = |g: RawGrammar| {
+            let g = g.to_checked()?;
+            g.validate_start(&args.start).map(|_| g)
+        };
+        let grammar = match self.parse(None).map(checked) {
+            Some(Ok(g)) => g,
+            Some(Err(e)) => return self.report(e),
+            None => return,
+        };
+        let gen = Generator::builder().grammar(grammar).build();
+        let mut tracker = CoverageTracker::new(&gen.grammar);
+        let mut rng = match args.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        for _ in 0..args.count {
+            let mut observer = |nt: &_, alt| tracker.observe(nt, alt);
+            if let Err(e) = gen.generate_with_observer(args.start.as_str(), &mut rng, &mut observer)
+            {
+                return self.report(e);
+            }
+        }
+        print!("{}", tracker.report());
+    }
+
+    /// the dead loops are not reported but highlighted in the graph
+    fn graph(&mut self, args: &GraphArgs) {
+        let Some(grammar) = self.load() else { return };
+        if let Err(e) = grammar.check_undefined() {
+            return self.report(e);
+        }
+        match args.graph_format {
+            GraphFormat::Dot => print!("{}", grammar.graph().to_dot(args.start.as_deref())),
+        }
+    }
+
+    /// the lints are not run, the metrics of a grammar with mistakes are still meaningful
+    fn stats(&mut self) {
+        let Some(grammar) = self.load() else { return };
+        if let Err(e) = grammar.check_undefined() {
+            return self.report(e);
+        }
+        print!("{}", grammar.graph().stats());
+    }
+
+    fn generate(&mut self, args: &GenArgs) {
+        let checked = |g: RawGrammar| {
+            let g = g.to_checked()?;
+            g.validate_start(&args.start).map(|_| g)
+        };
+        let grammar = match self.parse(None).map(checked) {
+            Some(Ok(g)) => g,
+            Some(Err(e)) => return self.report(e),
+            None => return,
+        };
+        let settings = GeneratorSettings {
+            max_steps: args.max_steps,
+            max_output_len: args.max_output_len,
+            randomize_case: args.randomize_case,
+            max_regex_len: args.max_regex_len,
+            repeat_max: args.repeat_max,
+            avoid_terminals: !args.allow_terminal_collisions,
+            timeout: args.timeout.map(Duration::from_millis),
+            ..Default::default()
+        };
+        let gen = Generator::builder()
+            .grammar(grammar)
+            .settings(settings)
+            .build();
+        // always seeded by a known seed, which is the value of `$SEED`
+        let seed = args.seed.unwrap_or_else(rand::random);
+        let mut rng = StdRng::seed_from_u64(seed);
+
+        let mut writer: Box<dyn Write> = match &args.output {
+            Some(path) => match File::create(path) {
+                Ok(file) => Box::new(BufWriter::new(file)),
+                Err(e) => {
+                    let msg = format!("Failed to create output {}: {}", path.display(), e);
+                    return self.fail(msg);
+                }
+            },
+            None => Box::new(BufWriter::new(std::io::stdout().lock())),
+        };
+        let sep = match args.null_sep {
+            true => b"\0",
+            false => b"\n",
+        };
+        let forever = args.count == 0;
+        if forever && args.unique {
+            return self.fail("`--unique` requires a positive `--count`".to_string());
+        }
+        let tree_gen = args.tree.map(|format| {
+            let gen = TreeGenerator::builder()
+                .grammar(gen.grammar.clone())
+                .settings(gen.settings.clone())
+                .build();
+            (gen, format)
+        });
+        // `$INDEX` and `$SEED` are the number of the string and the seed it is generated from
+        let attempt = |rng: &mut StdRng, index: usize, seed: u64| {
+            let mut state = State::new(rng).with_index(index).with_seed(seed);
+            match &tree_gen {
+                Some((tree_gen, format)) => tree_gen
+                    .generate_with_state(args.start.as_str(), &mut state)
+                    .map(|tree| format.render(&tree)),
+                None if args.explain => gen
+                    .generate_explained_with_state(args.start.as_str(), &mut state)
+                    .map(|(out, steps)| {
+                        for step in steps {
+                            eprintln!("{}", step);
+                        }
+                        out
+                    }),
+                None => gen.generate_with_state(args.start.as_str(), &mut state),
+            }
+        };
+        // a generation running out of steps or time is retried afresh
+        let generate = |rng: &mut StdRng, index: usize, seed: u64| {
+            let mut result = attempt(rng, index, seed);
+            for _ in 1..args.max_attempts {
+                match result {
+                    Err(Error::MaxDepthExceeded { .. } | Error::Timeout { .. }) => {
+                        result = attempt(rng, index, seed)
+                    }
+                    _ => break,
+                }
+            }
+            result
+        };
+
+        // unique outputs have to be collected for deduplication,
+        // otherwise the outputs are streamed one by one
+        let outputs: Box<dyn Iterator<Item = _>> = match args.unique {
+            true => {
+                match gen.generate_unique(
+                    args.start.as_str(),
+                    &mut rng,
+                    args.count,
+                    args.max_attempts,
+                ) {
+                    Ok(outputs) => Box::new(outputs.into_iter().map(|o| Ok((None, o)))),
+                    Err(e) => Box::new(std::iter::once(Err(e))),
+                }
+            }
+            false => {
+                let indices: Box<dyn Iterator<Item = usize>> = match forever {
+                    true => Box::new(0..),
+                    false => Box::new(0..args.count),
+                };
+                Box::new(indices.map(|i| match args.seed_base {
+                    Some(base) => {
+                        let seed = base.wrapping_add(i as u64);
+                        let mut rng = StdRng::seed_from_u64(seed);
+                        generate(&mut rng, i, seed).map(|o| (Some(seed), o))
+                    }
+                    None => generate(&mut rng, i, seed).map(|o| (None, o)),
+                }))
+            }
+        };
+        for output in outputs {
+            let output = match output {
+                Ok((Some(seed), output)) if args.show_seeds => format!("{}\t{}", seed, output),
+                Ok((_, output)) => output,
+                Err(e) => return self.report(e),
+            };
+            let written = writer
+                .write_all(output.as_bytes())
+                .and_then(|_| writer.write_all(sep))
+                .and_then(|_| match forever {
+                    true => writer.flush(),
+                    false => Ok(()),
+                });
+            match written {
+                Ok(()) => {}
+                // the reader of a stream stopped, e.g. `bnfgen gen --count 0 ... | head`
+                Err(e) if forever && e.kind() == std::io::ErrorKind::BrokenPipe => return,
+                Err(e) => {
+                    let msg = format!("Failed to write output: {}", e);
+                    return self.fail(msg);
+                }
+            }
+        }
+        if let Err(e) = writer.flush() {
+            let msg = format!("Failed to write output: {}", e);
+            self.fail(msg);
+        }
+    }
 }
 
 fn main() {
     let args = Cli::parse();
 
-    let text = std::fs::read_to_string(&args.grammar).unwrap();
-    let text = Arc::new(text);
-    let mut reporter = Reporter::new(Style::NoColor);
-
-    let report_and = |reporter: &mut Reporter, e, v| {
-        let diagnostic = Report::from(e).with_source_code(text.clone());
-        reporter.push(diagnostic);
-        v
-    };
-
-    let shutdown = |reporter: &Reporter| -> ! {
-        let msg = reporter.report_to_string();
-        if !msg.is_empty() {
-            eprintln!("{}", msg);
-            std::process::exit(1);
-        }
-        std::process::exit(0);
-    };
-
-    let grammar = match RawGrammar::parse(text.as_str()) {
-        Ok(g) => g,
-        Err(e) => {
-            reporter.push(e);
-            shutdown(&reporter);
-        }
-    };
-
-    #[rustfmt::skip]
-    let continue_check = grammar.check_repeats()
-        .map_or_else(|e| report_and(&mut reporter, e, false), |_| true) &&
-        grammar.check_duplicate()
-        .map_or_else(|e| report_and(&mut reporter, e, false), |_| true) &&
-        grammar.check_repeats()
-        .map_or_else(|e| report_and(&mut reporter, e, false), |_| true) ;
-
-    if continue_check {
-        let graph = grammar.graph();
-        let _ = graph
-            .check_trap_loop()
-            .map_err(|e| report_and(&mut reporter, e, false));
-        if let Some(start) = &args.check_unused {
-            let _ = graph
-                .check_unused(start)
-                .map_err(|e| report_and(&mut reporter, e, false));
-        }
-    }
-
-    shutdown(&reporter);
+    match &args.command {
+        Command::Check(args) => {
+            let mut app = App::new(&args.grammar, args.format);
+            app.check(args);
+            app.shutdown();
+        }
+        Command::Gen(args) => {
+            let mut app = App::new(&args.grammar, args.format);
+            app.generate(args);
+            app.shutdown();
+        }
+        Command::Tree(args) => {
+            let mut app = App::new(&args.grammar, args.format);
+            app.tree(args);
+            app.shutdown();
+        }
+        Command::Coverage(args) => {
+            let mut app = App::new(&args.grammar, args.format);
+            app.coverage(args);
+            app.shutdown();
+        }
+        Command::Graph(args) => {
+            let mut app = App::new(&args.grammar, args.format);
+            app.graph(args);
+            app.shutdown();
+        }
+        Command::Stats(args) => {
+            let mut app = App::new(&args.grammar, args.format);
+            app.stats();
+            app.shutdown();
+        }
+        #[cfg(feature = "serde")]
+        Command::Ast(args) => {
+            let mut app = App::new(&args.grammar, Format::Human);
+            app.ast();
+            app.shutdown();
+        }
+    }
 }
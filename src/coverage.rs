@@ -0,0 +1,155 @@
+use crate::grammar::alt::AltId;
+use crate::grammar::checked::CheckedGrammar;
+use crate::grammar::symbol::{NonTerminal, Ty};
+use indexmap::IndexMap;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::Arc;
+
+/// the index of a rule in the grammar and the index of an alternative in the rule
+type Position = (usize, usize);
+
+/// Hit counts of the alternatives of every rule, accumulated over many generations,
+/// to find the alternatives which are never chosen in practice (e.g. because of tight invoke
+/// limits) or chosen far more often than the others.
+#[derive(Debug, Clone)]
+pub struct CoverageTracker {
+    /// hit count of every alternative of every rule, in the order of definition
+    hits: IndexMap<NonTerminal, Vec<usize>>,
+    /// the label of every alternative of every rule, in the same order as `hits`
+    labels: Vec<Vec<Option<String>>>,
+    /// the rules and the indices of the alternatives of a name and an id, since an untyped
+    /// non-terminal chooses among the alternatives of all the rules of that name
+    index: HashMap<(Arc<String>, AltId), Vec<Position>>,
+}
+
+impl CoverageTracker {
+    pub fn new(grammar: &CheckedGrammar) -> Self {
+        let mut hits = IndexMap::new();
+        let mut index: HashMap<_, Vec<_>> = HashMap::new();
+        let mut labels = Vec::new();
+        for (i, (nt, production)) in grammar.rules.iter().enumerate() {
+            for (j, alt) in production.alts.iter().enumerate() {
+                index
+                    .entry((nt.name.clone(), alt.id()))
+                    .or_default()
+                    .push((i, j));
+            }
+            hits.insert(nt.clone(), vec![0; production.alts.len()]);
+            labels.push(
+                production
+                    .alts
+                    .iter()
+                    .map(|alt| alt.label.clone())
+                    .collect(),
+            );
+        }
+        CoverageTracker {
+            hits,
+            labels,
+            index,
+        }
+    }
+
+    /// Record that `nt` was expanded with the alternative `alt`, to be called from
+    /// [`Generator::generate_with_observer`](crate::generator::Generator::generate_with_observer).
+    ///
+    /// Identical alternatives of the rules of the same name can not be told apart,
+    /// they are counted on the first of them.
+    pub fn observe(&mut self, nt: &NonTerminal, alt: AltId) {
+        let Some(candidates) = self.index.get(&(nt.name.clone(), alt)) else {
+            return;
+        };
+        // a typed non-terminal only chooses among the alternatives of its own rule
+        let found = candidates.iter().find(|(i, _)| match nt.ty {
+            Ty::Untyped => true,
+            Ty::Typed(_) => self.hits.get_index(*i).is_some_and(|(k, _)| k == nt),
+        });
+        if let Some((i, j)) = found {
+            self.hits[*i][*j] += 1;
+        }
+    }
+
+    /// the hit count of every alternative of the rule `nt`, by the index of the alternative
+    pub fn hits(&self, nt: &NonTerminal) -> Option<&[usize]> {
+        self.hits.get(nt).map(Vec::as_slice)
+    }
+
+    /// the rules and the indices of the alternatives which were never chosen
+    pub fn uncovered(&self) -> Vec<(&NonTerminal, usize)> {
+        self.hits
+            .iter()
+            .flat_map(|(nt, hits)| {
+                hits.iter()
+                    .enumerate()
+                    .filter(|(_, n)| **n == 0)
+                    .map(move |(i, _)| (nt, i))
+            })
+            .collect()
+    }
+
+    /// Render the hit count and the share of every alternative, rule by rule,
+    /// along with the label of the alternative if any, e.g.
+    ///
+    /// ```text
+    /// <S>
+    ///     0 @lit: 7 (70.0%)
+    ///     1: 3 (30.0%)
+    ///     2: 0 (never)
+    /// ```
+    pub fn report(&self) -> String {
+        let mut buf = String::new();
+        for ((nt, hits), labels) in self.hits.iter().zip(&self.labels) {
+            writeln!(buf, "{}", nt).unwrap();
+            let total = hits.iter().sum::<usize>();
+            for (i, (n, label)) in hits.iter().zip(labels).enumerate() {
+                let name = match label {
+                    Some(label) => format!("{} @{}", i, label),
+                    None => i.to_string(),
+                };
+                match n {
+                    0 => writeln!(buf, "    {}: 0 (never)", name).unwrap(),
+                    n => {
+                        let share = *n as f64 / total as f64 * 100.0;
+                        writeln!(buf, "    {}: {} ({:.1}%)", name, n, share).unwrap();
+                    }
+                }
+            }
+        }
+        buf
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::coverage::CoverageTracker;
+    use crate::generator::Generator;
+    use crate::grammar::symbol::NonTerminal;
+    use rand::SeedableRng;
+
+    #[test]
+    fn coverage() {
+        let text = r#"
+            <S> ::= <E> <E: "str"> @pair | 0 "never" @never ;
+            <E: "int"> ::= "1" | "2" ;
+            <E: "str"> ::= "1" | 0 "a" ;
+        "#;
+        let gen: Generator = text.parse().unwrap();
+        let mut tracker = CoverageTracker::new(&gen.grammar);
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+        for _ in 0..100 {
+            gen.generate_with_observer("S", &mut rng, &mut |nt, alt| tracker.observe(nt, alt))
+                .unwrap();
+        }
+        let hits = |nt: NonTerminal| tracker.hits(&nt).unwrap().to_vec();
+        assert_eq!(hits(NonTerminal::untyped("S")), vec![100, 0]);
+        let int = hits(NonTerminal::typed("E", "int".to_string().into()));
+        let str = hits(NonTerminal::typed("E", "str".to_string().into()));
+        // `<E>` chooses among the alternatives of both rules, `<E: "str">` only of its own
+        assert_eq!(int.iter().sum::<usize>() + str.iter().sum::<usize>(), 200);
+        assert!(int.iter().all(|n| *n > 0));
+        assert_eq!(str[1], 0);
+        assert_eq!(tracker.uncovered().len(), 2);
+        insta::assert_snapshot!(tracker.report());
+    }
+}
@@ -33,11 +33,78 @@ pub enum Error {
         #[label("previous defined here")]
         prev: Span,
     },
+    #[error("Duplicated alternatives found")]
+    #[diagnostic(severity(Warning))]
+    DuplicateAlternative {
+        #[label("this alternative is duplicated")]
+        span: Span,
+        #[label("previous defined here")]
+        prev: Span,
+    },
+    #[error("Reference without any declaration")]
+    NoDeclaredVariable {
+        #[label("no `decl` declares this non-terminal")]
+        span: Span,
+    },
+    #[error("Duplicated labels found")]
+    DuplicateLabel {
+        #[label("this label is duplicated")]
+        span: Span,
+        #[label("previous defined here")]
+        prev: Span,
+    },
+    #[error("Unsatisfiable invoke limit")]
+    #[diagnostic(severity(Warning))]
+    UnsatisfiableInvokeLimit {
+        #[label("this alternative should be invoked at least {min} times, but its rule can be expanded at most {max} times")]
+        span: Span,
+        min: usize,
+        max: usize,
+    },
+    #[error("Percentages and weights are mixed")]
+    MixedWeights {
+        #[label("percentages should be given to either all or none of the alternatives")]
+        span: Span,
+    },
+    #[error("Percentages do not sum up to 100%")]
+    InvalidPercentages {
+        #[label("the percentages sum up to {sum}%")]
+        span: Span,
+        sum: String,
+    },
+    #[error("All the alternatives weigh 0")]
+    ZeroWeights {
+        #[label("at least one alternative should have a positive weight")]
+        span: Span,
+    },
+    #[error("Weights are too large")]
+    WeightOverflow {
+        #[label("the sum of the weights of <{name}> overflows here")]
+        span: Span,
+        name: String,
+    },
+    #[error("Invalid char range")]
+    InvalidCharRange {
+        #[label("start should be less than or equal to end")]
+        span: Span,
+    },
     #[error("Invalid repeat range")]
     InvalidRepeatRange {
         #[label("min should be less than or equal to max")]
         span: Span,
     },
+    #[error("The start symbol <{name}> never derives only terminals")]
+    UnproductiveStartSymbol {
+        name: String,
+        #[label("every derivation of this rule expands forever")]
+        span: Span,
+    },
+    #[error("The start symbol <{name}> can derive the empty string")]
+    NullableStartSymbol {
+        name: String,
+        #[label(collection, "this rule can derive the empty string")]
+        spans: Vec<Span>,
+    },
     #[error("Found unreachable rules")]
     UnreachableRules {
         #[label(collection, "this rule is unreachable")]
@@ -48,12 +115,208 @@ pub enum Error {
         #[label(collection, "this rule may be trapped in a dead loop")]
         spans: Vec<Span>,
     },
+    #[error("Invalid ABNF")]
+    InvalidAbnf {
+        #[label("{reason}")]
+        span: Span,
+        reason: String,
+    },
+    #[error("Unsupported ABNF construct")]
+    UnsupportedAbnf {
+        #[label("this construct is not supported")]
+        span: Span,
+    },
+    #[error("Include cycle through {path}")]
+    IncludeCycle {
+        #[label("this file is already being included")]
+        span: Span,
+        path: String,
+    },
+    #[error("Undefined rule in `like`")]
+    UndefinedLike {
+        #[label("no rule of this name is defined")]
+        span: Span,
+    },
+    #[error("Cycle of `like`")]
+    LikeCycle {
+        #[label("this rule is already being copied")]
+        span: Span,
+    },
+    #[error("Failed to include {path}")]
+    InvalidInclude {
+        #[label("{reason}")]
+        span: Span,
+        path: String,
+        reason: String,
+    },
+    #[error("Invalid decay")]
+    InvalidDecay {
+        #[label("expect `decay = B` where B is between 0 and 1")]
+        span: Span,
+    },
+    #[error("Unknown builtin")]
+    UnknownBuiltin {
+        #[label("expect `$INDEX` or `$SEED`")]
+        span: Span,
+    },
+    #[error("Invalid regex option")]
+    InvalidRegexOption {
+        #[label("only `max = N` is supported")]
+        span: Span,
+    },
+    #[error("Regex always collides with terminals")]
+    RegexAlwaysCollides {
+        #[label("every string of this regex is also a terminal")]
+        span: Span,
+    },
     #[error("Invalid regex")]
     InvalidRegex {
         #[label("this regex is invalid")]
         span: Span,
     },
+    #[error("Undefined start symbol <{name}>")]
+    UndefinedStartSymbol { name: String },
+    #[error("No rule found for {name}")]
+    UndefinedRule { name: String },
+    #[error("No candidates available")]
+    NoCandidatesAvailable {
+        name: String,
+        #[label("no alternative of <{name}> can be chosen")]
+        span: Span,
+    },
+    #[error("The language of <{name}> is not finite")]
+    InfiniteGrammar { name: String },
+    #[error("The regex `{pattern}` generates more than {limit} bytes")]
+    RegexTooLarge { pattern: String, limit: usize },
+    #[error("The regex `{pattern}` collides with a terminal {attempts} times in a row")]
+    RegexCollisionLimit { pattern: String, attempts: usize },
+    #[error("Generation exceeds the maximum of {max_steps} steps")]
+    MaxDepthExceeded { max_steps: usize },
+    #[error("Failed to read the grammar: {message}")]
+    Io { message: String },
+    #[error("Generated bytes are not valid UTF-8, use `generate_bytes` instead")]
+    InvalidUtf8,
+    #[error("Generation exceeds the timeout of {timeout:?}")]
+    Timeout { timeout: std::time::Duration },
+    #[error("Generated string exceeds the maximum of {max_len} chars")]
+    OutputTooLong { max_len: usize },
+    #[error("Probability {p} of the geometric repetition is not within 0..=1")]
+    InvalidProbability { p: String },
+    #[error("Alternative {index} of <{name}> does not exist")]
+    InvalidOverride { name: String, index: usize },
+    #[error("Alternative @{label} of <{name}> does not exist")]
+    UnknownLabel { name: String, label: String },
+    #[error("No declaration found for {name}")]
+    UndeclaredReference { name: String },
+    #[error("{builtin} is not set for this generation")]
+    UnsetBuiltin {
+        builtin: crate::grammar::symbol::Builtin,
+    },
     #[error(transparent)]
     #[diagnostic(transparent)]
     LexicalError(#[from] crate::token::LexicalError),
 }
+
+impl Error {
+    /// a warning is reported without failing, e.g. a duplicated alternative
+    /// which only adds up the weights
+    pub fn is_warning(&self) -> bool {
+        use miette::Diagnostic;
+        self.severity() == Some(miette::Severity::Warning)
+    }
+
+    /// name of the error variant, e.g. `DuplicatedRules`
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::UnrecognizedToken { .. } => "UnrecognizedToken",
+            Error::ExtraToken { .. } => "ExtraToken",
+            Error::UnrecognizedEof { .. } => "UnrecognizedEof",
+            Error::UndefinedNonTerminal { .. } => "UndefinedNonTerminal",
+            Error::DuplicatedRules { .. } => "DuplicatedRules",
+            Error::DuplicateAlternative { .. } => "DuplicateAlternative",
+            Error::DuplicateLabel { .. } => "DuplicateLabel",
+            Error::NoDeclaredVariable { .. } => "NoDeclaredVariable",
+            Error::UnsatisfiableInvokeLimit { .. } => "UnsatisfiableInvokeLimit",
+            Error::MixedWeights { .. } => "MixedWeights",
+            Error::InvalidPercentages { .. } => "InvalidPercentages",
+            Error::ZeroWeights { .. } => "ZeroWeights",
+            Error::WeightOverflow { .. } => "WeightOverflow",
+            Error::InvalidCharRange { .. } => "InvalidCharRange",
+            Error::InvalidRepeatRange { .. } => "InvalidRepeatRange",
+            Error::UnproductiveStartSymbol { .. } => "UnproductiveStartSymbol",
+            Error::NullableStartSymbol { .. } => "NullableStartSymbol",
+            Error::UnreachableRules { .. } => "UnreachableRules",
+            Error::TrapLoop { .. } => "TrapLoop",
+            Error::InvalidAbnf { .. } => "InvalidAbnf",
+            Error::UnsupportedAbnf { .. } => "UnsupportedAbnf",
+            Error::IncludeCycle { .. } => "IncludeCycle",
+            Error::UndefinedLike { .. } => "UndefinedLike",
+            Error::LikeCycle { .. } => "LikeCycle",
+            Error::InvalidInclude { .. } => "InvalidInclude",
+            Error::InvalidDecay { .. } => "InvalidDecay",
+            Error::InvalidRegexOption { .. } => "InvalidRegexOption",
+            Error::RegexAlwaysCollides { .. } => "RegexAlwaysCollides",
+            Error::InvalidRegex { .. } => "InvalidRegex",
+            Error::UndefinedStartSymbol { .. } => "UndefinedStartSymbol",
+            Error::UndefinedRule { .. } => "UndefinedRule",
+            Error::NoCandidatesAvailable { .. } => "NoCandidatesAvailable",
+            Error::InfiniteGrammar { .. } => "InfiniteGrammar",
+            Error::RegexTooLarge { .. } => "RegexTooLarge",
+            Error::RegexCollisionLimit { .. } => "RegexCollisionLimit",
+            Error::MaxDepthExceeded { .. } => "MaxDepthExceeded",
+            Error::Io { .. } => "Io",
+            Error::InvalidUtf8 => "InvalidUtf8",
+            Error::Timeout { .. } => "Timeout",
+            Error::OutputTooLong { .. } => "OutputTooLong",
+            Error::InvalidProbability { .. } => "InvalidProbability",
+            Error::InvalidOverride { .. } => "InvalidOverride",
+            Error::UnknownLabel { .. } => "UnknownLabel",
+            Error::UndeclaredReference { .. } => "UndeclaredReference",
+            Error::UnsetBuiltin { .. } => "UnsetBuiltin",
+            Error::UnknownBuiltin { .. } => "UnknownBuiltin",
+            Error::LexicalError(_) => "LexicalError",
+        }
+    }
+}
+
+/// a label of the diagnostic, `start` and `end` are byte offsets into the grammar
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize)]
+struct Label {
+    message: Option<String>,
+    start: usize,
+    end: usize,
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Error {
+    /// serialized as `{ "kind": ..., "severity": ..., "message": ..., "labels": [{ "message": ..., "start": ..., "end": ... }] }`
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        use miette::Diagnostic;
+        use serde::ser::SerializeStruct;
+
+        let labels = self
+            .labels()
+            .into_iter()
+            .flatten()
+            .map(|label| Label {
+                message: label.label().map(String::from),
+                start: label.offset(),
+                end: label.offset() + label.len(),
+            })
+            .collect::<Vec<_>>();
+        let severity = match self.is_warning() {
+            true => "warning",
+            false => "error",
+        };
+        let mut state = serializer.serialize_struct("Error", 4)?;
+        state.serialize_field("kind", self.kind())?;
+        state.serialize_field("severity", severity)?;
+        state.serialize_field("message", &self.to_string())?;
+        state.serialize_field("labels", &labels)?;
+        state.end()
+    }
+}
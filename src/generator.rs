@@ -1,43 +1,649 @@
-use crate::grammar::checked::{CheckedGrammar, ReduceOutput};
+use crate::error::{Error, Result};
+use crate::grammar::alt::AltId;
+use crate::grammar::checked::{utf8, CheckedGrammar, Cost, ReduceOutput};
+use crate::grammar::raw::RawGrammar;
 use crate::grammar::state::State;
 use crate::grammar::symbol::SymbolKind::Terminal;
 use crate::grammar::symbol::{NonTerminal, SymbolKind};
 use crate::parse_tree::tree::ParseTree;
+use indexmap::{IndexMap, IndexSet};
 use rand::Rng;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
 
-#[derive(typed_builder::TypedBuilder)]
+/// distribution of the repetition count of a regex repetition `{min,max}`
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum RepeatDist {
+    /// every count in `min..=max` is equally likely
+    #[default]
+    Uniform,
+    /// `min + k` where `k` is the number of failures before the first success
+    /// of a trial with probability `p`, truncated at `max`
+    Geometric { p: f64 },
+}
+
+impl RepeatDist {
+    /// the probability of a geometric distribution must be within `0..=1`
+    pub(crate) fn check(&self) -> Result<()> {
+        match self {
+            RepeatDist::Geometric { p } if !(0.0..=1.0).contains(p) => {
+                Err(Error::InvalidProbability { p: p.to_string() })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn sample<R: Rng>(&self, rng: &mut R, min: u32, max: u32) -> u32 {
+        match self {
+            RepeatDist::Uniform => rng.gen_range(min..=max),
+            RepeatDist::Geometric { p } => {
+                let mut count = min;
+                while count < max && !rng.gen_bool(*p) {
+                    count += 1;
+                }
+                count
+            }
+        }
+    }
+}
+
+/// number of steps between two checks of the timeout
+const TIMEOUT_CHECK_INTERVAL: usize = 64;
+
+/// the steps of a generation so far, the derivations of its declarations included
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Progress {
+    steps: usize,
+    started: Instant,
+}
+
+impl Progress {
+    pub(crate) fn new() -> Self {
+        Progress {
+            steps: 0,
+            started: Instant::now(),
+        }
+    }
+
+    /// count a step, fail if it exceeds the maximum steps or the timeout
+    // `is_multiple_of` requires Rust 1.87
+    #[allow(clippy::manual_is_multiple_of)]
+    pub(crate) fn step(&mut self, settings: &GeneratorSettings) -> Result<()> {
+        self.steps += 1;
+        if let Some(max_steps) = settings.max_steps {
+            if self.steps > max_steps {
+                return Err(Error::MaxDepthExceeded { max_steps });
+            }
+        }
+        // reading the clock at every step would be a waste
+        if let Some(timeout) = settings.timeout {
+            if self.steps % TIMEOUT_CHECK_INTERVAL == 0 && self.started.elapsed() > timeout {
+                return Err(Error::Timeout { timeout });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// length in chars (or bytes) of an output so far, separators included
+#[derive(Debug, Default)]
+pub(crate) struct OutputLen(usize);
+
+impl OutputLen {
+    /// count a terminal of `n` chars along with the separator, fail if the output gets too long
+    pub(crate) fn push(&mut self, settings: &GeneratorSettings, n: usize) -> Result<()> {
+        if self.0 > 0 {
+            self.0 += settings.separator.chars().count();
+        }
+        self.0 += n;
+        match settings.max_output_len {
+            Some(max_len) if self.0 > max_len => Err(Error::OutputTooLong { max_len }),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// called with the expanded non-terminal and the id of the chosen alternative
+pub type Observer<'a> = dyn FnMut(&NonTerminal, AltId) + 'a;
+
+/// called with the expanded non-terminal and the index of the chosen alternative
+/// in its production, which tells apart the alternatives of the same id
+type Chosen<'a> = dyn FnMut(&NonTerminal, usize) + 'a;
+
+/// a step of a leftmost derivation, see [`Generator::generate_explained`]
+#[derive(Debug, Clone)]
+pub struct DerivationStep {
+    /// the expanded non-terminal
+    pub non_terminal: NonTerminal,
+    /// the index of the chosen alternative among the ones of `non_terminal`
+    pub alt: usize,
+    /// the symbols of the chosen alternative
+    pub symbols: Vec<SymbolKind>,
+}
+
+impl fmt::Display for DerivationStep {
+    /// render as a rule with the chosen alternative only, e.g. `<E> ::= <E> "+" <E>`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ::=", self.non_terminal)?;
+        for sym in &self.symbols {
+            write!(f, " {}", sym)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, typed_builder::TypedBuilder)]
+pub struct GeneratorSettings {
+    /// separator inserted between two adjacent terminals, default to a single space
+    #[builder(default = String::from(" "), setter(into))]
+    pub separator: String,
+    /// distribution of the repetition count in regex, default to uniform
+    #[builder(default)]
+    pub repeat_dist: RepeatDist,
+    /// randomly flip the case of the letters produced by regex classes, default to false
+    #[builder(default)]
+    pub randomize_case: bool,
+    /// maximum number of symbols reduced in a single generation, default to unlimited
+    #[builder(default, setter(strip_option))]
+    pub max_steps: Option<usize>,
+    /// maximum length (in chars, separators included) of a generated string, default to unlimited
+    #[builder(default, setter(strip_option))]
+    pub max_output_len: Option<usize>,
+    /// maximum duration of a single generation, checked every few steps so that a single
+    /// step (e.g. a huge regex) may exceed it, default to unlimited
+    #[builder(default, setter(strip_option))]
+    pub timeout: Option<Duration>,
+    /// maximum count of an unbounded repetition in regex, e.g. `a*` or `a{2,}`,
+    /// unless overridden by `re("...", max = N)`, default to 5
+    #[builder(default = 5)]
+    pub repeat_max: u32,
+    /// maximum length (in bytes) of a string generated by a single regex, default to 1 MiB
+    #[builder(default = 1 << 20)]
+    pub max_regex_len: usize,
+    /// re-generate a regex whose output equals a terminal of the grammar, default to true
+    #[builder(default = true)]
+    pub avoid_terminals: bool,
+    /// maximum number of attempts of a regex to avoid the terminals, default to 1000
+    #[builder(default = 1000)]
+    pub max_regex_attempts: usize,
+}
+
+impl Default for GeneratorSettings {
+    fn default() -> Self {
+        GeneratorSettings::builder().build()
+    }
+}
+
+#[derive(Clone, typed_builder::TypedBuilder)]
 pub struct Generator {
     pub grammar: CheckedGrammar,
+    #[builder(default)]
+    pub settings: GeneratorSettings,
 }
 
 impl Generator {
-    pub fn generate<R: Rng, S: Into<String>>(&self, start: S, rng: &mut R) -> String {
+    /// Generate a string from `start`.
+    ///
+    /// The generation is reproducible: for the same grammar and the same seeded `rng`,
+    /// the output is identical across runs, platforms and the order in which the rules are
+    /// defined. The hash maps on the way are only used for lookups, every choice is made
+    /// over an ordered list (e.g. the alternatives of same-named rules are merged by type).
+    pub fn generate<R: Rng, S: Into<String>>(&self, start: S, rng: &mut R) -> Result<String> {
+        self.generate_with_state(start, &mut State::new(rng))
+    }
+
+    /// Generate a string from `start` continuing from `state` rather than a fresh one,
+    /// so that the invoke limits and the names declared by `decl(<Id>)` carry over
+    /// from the previous generations with the same state, e.g. statement by statement.
+    pub fn generate_with_state<R: Rng, S: Into<String>>(
+        &self,
+        start: S,
+        state: &mut State<R>,
+    ) -> Result<String> {
+        self.generate_by_state(start, state, None)
+    }
+
+    /// Generate a string like [`generate`](Self::generate), calling `observer` with the
+    /// non-terminal and the id of the chosen alternative every time a non-terminal is expanded,
+    /// e.g. to build a coverage map of the grammar.
+    ///
+    /// The non-terminal of a `decl(<Id>)` is derived on its own and is not observed.
+    pub fn generate_with_observer<R: Rng, S: Into<String>>(
+        &self,
+        start: S,
+        rng: &mut R,
+        observer: &mut Observer,
+    ) -> Result<String> {
+        let mut chosen = |nt: &NonTerminal, alt: usize| {
+            // the production exists, the non-terminal has just been expanded
+            if let Ok(production) = self.grammar.require_production(nt) {
+                observer(nt, production.alts[alt].id());
+            }
+        };
+        self.generate_by_state(start, &mut State::new(rng), Some(&mut chosen))
+    }
+
+    /// Generate a string like [`generate`](Self::generate), along with the steps of its
+    /// leftmost derivation, i.e. the non-terminals in the order they are expanded.
+    ///
+    /// The non-terminal of a `decl(<Id>)` is derived on its own and is not explained.
+    pub fn generate_explained<R: Rng, S: Into<String>>(
+        &self,
+        start: S,
+        rng: &mut R,
+    ) -> Result<(String, Vec<DerivationStep>)> {
+        self.generate_explained_with_state(start, &mut State::new(rng))
+    }
+
+    /// Generate a string along with its derivation like
+    /// [`generate_explained`](Self::generate_explained), continuing from `state`.
+    pub fn generate_explained_with_state<R: Rng, S: Into<String>>(
+        &self,
+        start: S,
+        state: &mut State<R>,
+    ) -> Result<(String, Vec<DerivationStep>)> {
+        let mut chosen = Vec::new();
+        let mut observer = |nt: &NonTerminal, alt| chosen.push((nt.clone(), alt));
+        let out = self.generate_by_state(start, state, Some(&mut observer))?;
+        let mut steps = Vec::with_capacity(chosen.len());
+        for (non_terminal, alt) in chosen {
+            let alts = &self.grammar.require_production(&non_terminal)?.alts;
+            steps.push(DerivationStep {
+                alt,
+                symbols: alts[alt].symbols.iter().map(|s| s.kind.clone()).collect(),
+                non_terminal,
+            });
+        }
+        Ok((out, steps))
+    }
+
+    /// Generate a string like [`generate`](Self::generate), adding to `stats` the count chosen
+    /// for every regex repetition on the way, e.g. to check over a batch whether
+    /// [`GeneratorSettings::repeat_max`] skews the data.
+    pub fn generate_with_repetition_stats<R: Rng, S: Into<String>>(
+        &self,
+        start: S,
+        rng: &mut R,
+        stats: &mut RepetitionStats,
+    ) -> Result<String> {
+        let mut state = State::new(rng);
+        state.repetitions = Some(std::mem::take(stats));
+        let out = self.generate_by_state(start, &mut state, None);
+        *stats = state.repetitions.take().unwrap_or_default();
+        out
+    }
+
+    /// Generate `n` strings sharing a single generation state, as parts of one document.
+    ///
+    /// Calling [`generate`](Self::generate) in a loop starts afresh every time, while here
+    /// the invoke limits count the invocations across all the `n` strings, and names declared
+    /// by `decl(<Id>)` in a string can be referred to by `ref(<Id>)` in the following ones.
+    /// `$INDEX` is the number of the string, counting from 0.
+    pub fn generate_many<R: Rng, S: Into<String>>(
+        &self,
+        start: S,
+        rng: &mut R,
+        n: usize,
+    ) -> Result<Vec<String>> {
+        let start = start.into();
+        let mut state = State::new(rng);
+        (0..n)
+            .map(|i| {
+                state.index = Some(i);
+                self.generate_by_state(start.as_str(), &mut state, None)
+            })
+            .collect()
+    }
+
+    /// Generate a string like [`generate`](Self::generate), but the non-terminals named in
+    /// `overrides` always take the alternative at the given index (counting from 0).
+    pub fn generate_with_overrides<R: Rng, S: Into<String>>(
+        &self,
+        start: S,
+        rng: &mut R,
+        overrides: &HashMap<String, usize>,
+    ) -> Result<String> {
+        let mut state = State::new(rng).with_overrides(overrides.clone());
+        self.generate_by_state(start, &mut state, None)
+    }
+
+    /// Generate a string like [`generate_with_overrides`](Self::generate_with_overrides),
+    /// but the alternatives are referred to by their labels, e.g. `"add"` for `<E> "+" <E> @add`.
+    pub fn generate_with_label_overrides<R: Rng, S: Into<String>>(
+        &self,
+        start: S,
+        rng: &mut R,
+        overrides: &HashMap<String, String>,
+    ) -> Result<String> {
+        let mut state = State::new(rng).with_overrides(overrides.clone());
+        self.generate_by_state(start, &mut state, None)
+    }
+
+    /// Generate raw bytes from `start`, the grammar may contain `re_bytes("...")` generating
+    /// bytes which are not valid UTF-8, e.g. to fuzz a binary format.
+    ///
+    /// The other generation methods fail with [`Error::InvalidUtf8`] on such bytes.
+    /// The `max_output_len` counts the bytes of `re_bytes` and the chars of everything else.
+    pub fn generate_bytes<R: Rng, S: Into<String>>(
+        &self,
+        start: S,
+        rng: &mut R,
+    ) -> Result<Vec<u8>> {
+        self.generate_bytes_by_state(start, &mut State::new(rng), None)
+    }
+
+    fn generate_by_state<R: Rng, S: Into<String>>(
+        &self,
+        start: S,
+        state: &mut State<R>,
+        observer: Option<&mut Chosen>,
+    ) -> Result<String> {
+        let bytes = self.generate_bytes_by_state(start, state, observer)?;
+        String::from_utf8(bytes).map_err(|_| Error::InvalidUtf8)
+    }
+
+    /// Generate strings from `start` until one satisfies `pred`, trying at most `max_tries`
+    /// times, `None` if none of them does.
+    ///
+    /// A generation exceeding the maximum steps, length or duration of the settings counts
+    /// as a failed try, while the other errors are returned at once.
+    pub fn generate_until<R, S, P>(
+        &self,
+        start: S,
+        rng: &mut R,
+        max_tries: usize,
+        mut pred: P,
+    ) -> Result<Option<String>>
+    where
+        R: Rng,
+        S: Into<String>,
+        P: FnMut(&str) -> bool,
+    {
+        let start = start.into();
+        let mut out = String::new();
+        for _ in 0..max_tries {
+            match self.generate_into(start.as_str(), rng, &mut out) {
+                Ok(()) if pred(&out) => return Ok(Some(out)),
+                Ok(())
+                | Err(
+                    Error::MaxDepthExceeded { .. }
+                    | Error::Timeout { .. }
+                    | Error::OutputTooLong { .. },
+                ) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Generate a string like [`generate`](Self::generate) into `out`, which is cleared first,
+    /// reusing its allocation, e.g. to avoid the allocation churn of generating millions of strings.
+    ///
+    /// `out` is left empty if the generation fails.
+    pub fn generate_into<R: Rng, S: Into<String>>(
+        &self,
+        start: S,
+        rng: &mut R,
+        out: &mut String,
+    ) -> Result<()> {
+        let mut buf = std::mem::take(out).into_bytes();
+        let result = self.generate_bytes_into(start, &mut State::new(rng), None, &mut buf);
+        if result.is_err() {
+            buf.clear();
+        }
+        match String::from_utf8(buf) {
+            Ok(s) => {
+                *out = s;
+                result
+            }
+            Err(_) => Err(Error::InvalidUtf8),
+        }
+    }
+
+    fn generate_bytes_by_state<R: Rng, S: Into<String>>(
+        &self,
+        start: S,
+        state: &mut State<R>,
+        observer: Option<&mut Chosen>,
+    ) -> Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        self.generate_bytes_into(start, state, observer, &mut buf)?;
+        Ok(buf)
+    }
+
+    /// the core of the generation, `buf` is cleared first
+    fn generate_bytes_into<R: Rng, S: Into<String>>(
+        &self,
+        start: S,
+        state: &mut State<R>,
+        mut observer: Option<&mut Chosen>,
+        buf: &mut Vec<u8>,
+    ) -> Result<()> {
+        buf.clear();
+        let mut len = OutputLen::default();
+        // append a terminal of `n` chars (or bytes) along with the separator
+        let mut push = |buf: &mut Vec<u8>, bytes: &[u8], n: usize| {
+            if !buf.is_empty() {
+                buf.extend_from_slice(self.settings.separator.as_bytes());
+            }
+            buf.extend_from_slice(bytes);
+            len.push(&self.settings, n)
+        };
+
+        let start = SymbolKind::NonTerminal(self.grammar.start_symbol(start)?);
+        // the stack is reversed, the next symbol to reduce is at the back,
+        // it may be left over by a failed generation
+        state.stack.clear();
+        state.stack.push((start, 0));
+        state.progress = Progress::new();
+
+        while let Some((symbol, depth)) = state.stack.pop() {
+            state.depth = depth;
+            state.progress.step(&self.settings)?;
+            // only keep the non-terminal around if it is observed
+            let observed = match (&symbol, &observer) {
+                (SymbolKind::NonTerminal(nt), Some(_)) => Some(nt.clone()),
+                _ => None,
+            };
+            match self.grammar.reduce(symbol, state, &self.settings)? {
+                ReduceOutput::Terminal(s) => push(buf, s.as_bytes(), s.chars().count())?,
+                ReduceOutput::Bytes(bytes) => push(buf, &bytes, bytes.len())?,
+                ReduceOutput::NonTerminal { syms, alt, .. } => {
+                    if let (Some(nt), Some(observer)) = (observed, observer.as_mut()) {
+                        observer(&nt, alt);
+                    }
+                    // syms :: stack
+                    state
+                        .stack
+                        .extend(syms.into_iter().rev().map(|sym| (sym, depth + 1)));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Generate a string of roughly at most `budget` terminals.
+    ///
+    /// Alternatives are chosen as [`generate`](Self::generate) does as long as the shortest
+    /// string the remaining symbols can derive fits in the budget,
+    /// afterwards the shortest alternatives are taken, so that the generation always terminates
+    /// even for heavily recursive grammars.
+    ///
+    /// The invoke limits and the maximum steps, timeout and output length of the settings apply
+    /// as in [`generate`](Self::generate), the budget only steers the choice of the alternatives.
+    pub fn generate_bounded<R: Rng, S: Into<String>>(
+        &self,
+        start: S,
+        rng: &mut R,
+        budget: usize,
+    ) -> Result<String> {
+        let costs = self.grammar.min_costs();
+        let len = |cost: Option<Cost>| cost.map_or(0, |(len, _)| len);
         let mut buf = Vec::new();
+        let mut out_len = OutputLen::default();
         let mut state = State::new(rng);
 
-        let start = SymbolKind::NonTerminal(NonTerminal::untyped(start));
-        let mut stack = vec![start];
+        let start = SymbolKind::NonTerminal(self.grammar.start_symbol(start)?);
+        // the minimum number of terminals of the current sentential form
+        let mut committed = len(self.grammar.symbol_cost(&start, &costs));
+        // the stack is reversed, the next symbol to reduce is at the back
+        state.stack.push((start, 0));
 
-        while !stack.is_empty() {
-            // pop out the first symbol
-            match self.grammar.reduce(stack.remove(0), &mut state) {
-                ReduceOutput::Terminal(s) => {
+        while let Some((symbol, depth)) = state.stack.pop() {
+            state.depth = depth;
+            state.progress.step(&self.settings)?;
+            let nt = match symbol {
+                SymbolKind::NonTerminal(nt) => nt,
+                symbol => {
+                    let s = match self.grammar.reduce(symbol, &mut state, &self.settings)? {
+                        ReduceOutput::Terminal(s) => s,
+                        ReduceOutput::Bytes(bytes) => utf8(bytes)?,
+                        ReduceOutput::NonTerminal { .. } => unreachable!(),
+                    };
+                    out_len.push(&self.settings, s.chars().count())?;
                     buf.push(s);
+                    continue;
                 }
-                ReduceOutput::NonTerminal { mut syms, .. } => {
-                    // syms :: stack
-                    syms.extend(stack);
-                    stack = syms;
-                }
+            };
+            let production = self.grammar.require_production(&nt)?;
+            let current = SymbolKind::NonTerminal(nt.clone());
+            let current = len(self.grammar.symbol_cost(&current, &costs));
+            let alt = production
+                .choose_bounded(
+                    &mut state,
+                    |alt| {
+                        self.grammar
+                            .alt_cost(alt, &costs)
+                            .is_some_and(|(l, _)| committed - current + l <= budget)
+                    },
+                    |alt| self.grammar.alt_cost(alt, &costs),
+                )
+                .ok_or_else(|| Error::NoCandidatesAvailable {
+                    name: nt.as_str().to_string(),
+                    span: production.span,
+                })?;
+            committed = committed - current + len(self.grammar.alt_cost(alt, &costs));
+            state.stack.extend(
+                alt.symbols
+                    .iter()
+                    .rev()
+                    .map(|s| (s.kind.clone(), depth + 1)),
+            );
+        }
+
+        Ok(buf
+            .iter()
+            .map(|s| s.as_str())
+            .collect::<Vec<_>>()
+            .join(&self.settings.separator))
+    }
+
+    /// Generate at most `count` distinct strings, giving up after `max_attempts` generations.
+    ///
+    /// For grammars that can produce fewer than `count` strings, fewer results
+    /// are returned rather than looping forever.
+    pub fn generate_unique<R: Rng, S: Into<String>>(
+        &self,
+        start: S,
+        rng: &mut R,
+        count: usize,
+        max_attempts: usize,
+    ) -> Result<Vec<String>> {
+        let start = start.into();
+        let mut outputs = IndexSet::new();
+        for _ in 0..max_attempts {
+            if outputs.len() >= count {
+                break;
             }
+            outputs.insert(self.generate(start.as_str(), rng)?);
         }
+        Ok(outputs.into_iter().collect())
+    }
+}
+
+impl FromStr for Generator {
+    type Err = Error;
+
+    /// parse and check the grammar, then build a generator with the default settings
+    fn from_str(s: &str) -> Result<Self> {
+        let grammar = RawGrammar::parse(s)?.to_checked()?;
+        Ok(Generator::builder().grammar(grammar).build())
+    }
+}
+
+/// size statistics of a generated parse tree
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GenerationStats {
+    /// number of nodes on the longest path from the root, a single leaf has a depth of 1
+    pub depth: usize,
+    /// number of terminals
+    pub leaves: usize,
+    /// number of expanded non-terminals
+    pub branches: usize,
+}
+
+/// The counts chosen for the repetitions of the regexes (e.g. `a{2,100}` or `[0-9]+`),
+/// collected over a batch by [`Generator::generate_with_repetition_stats`].
+///
+/// The repetitions are keyed by the pattern of their regex and by the repetition as printed
+/// by `regex-syntax`, so the same repetition written twice in a pattern is counted once.
+/// A regex output re-generated for colliding with a terminal is not counted.
+#[derive(Debug, Clone, Default)]
+pub struct RepetitionStats {
+    counts: IndexMap<String, IndexMap<String, BTreeMap<u32, usize>>>,
+}
+
+impl RepetitionStats {
+    pub(crate) fn record(&mut self, pattern: &str, repetition: String, count: u32) {
+        let histogram = self
+            .counts
+            .entry(pattern.to_string())
+            .or_default()
+            .entry(repetition)
+            .or_default();
+        *histogram.entry(count).or_insert(0) += 1;
+    }
+
+    /// how many times each count was chosen for `repetition` of the regex `pattern`
+    pub fn histogram(&self, pattern: &str, repetition: &str) -> Option<&BTreeMap<u32, usize>> {
+        self.counts.get(pattern)?.get(repetition)
+    }
+
+    /// the fewest and the most repetitions generated for `repetition` of the regex `pattern`
+    pub fn range(&self, pattern: &str, repetition: &str) -> Option<(u32, u32)> {
+        let histogram = self.histogram(pattern, repetition)?;
+        let min = *histogram.first_key_value()?.0;
+        let max = *histogram.last_key_value()?.0;
+        Some((min, max))
+    }
 
-        buf.iter().map(|s| s.as_str()).collect::<Vec<_>>().join(" ")
+    /// every repetition met so far, as `(pattern, repetition, histogram)`
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str, &BTreeMap<u32, usize>)> {
+        self.counts.iter().flat_map(|(pattern, reps)| {
+            reps.iter()
+                .map(move |(rep, histogram)| (pattern.as_str(), rep.as_str(), histogram))
+        })
     }
 }
 
+#[derive(Clone, typed_builder::TypedBuilder)]
 pub struct TreeGenerator {
     pub grammar: CheckedGrammar,
+    #[builder(default)]
+    pub settings: GeneratorSettings,
+}
+
+impl FromStr for TreeGenerator {
+    type Err = Error;
+
+    /// parse and check the grammar, then build a tree generator with the default settings
+    fn from_str(s: &str) -> Result<Self> {
+        let grammar = RawGrammar::parse(s)?.to_checked()?;
+        Ok(TreeGenerator::builder().grammar(grammar).build())
+    }
 }
 
 impl TreeGenerator {
@@ -45,25 +651,71 @@ impl TreeGenerator {
         &self,
         start: S,
         rng: &mut R,
-    ) -> ParseTree<SymbolKind> {
-        let start = SymbolKind::NonTerminal(NonTerminal::untyped(start));
-        let mut state = State::new(rng);
-        self.generate_tree(start, &mut state)
+    ) -> Result<ParseTree<SymbolKind>> {
+        self.generate_with_stats(start, rng).map(|(tree, _)| tree)
+    }
+
+    /// Generate a tree like [`generate`](Self::generate), along with its size statistics.
+    pub fn generate_with_stats<R: Rng, S: Into<String>>(
+        &self,
+        start: S,
+        rng: &mut R,
+    ) -> Result<(ParseTree<SymbolKind>, GenerationStats)> {
+        let start = SymbolKind::NonTerminal(self.grammar.start_symbol(start)?);
+        let mut stats = GenerationStats::default();
+        let mut len = OutputLen::default();
+        let tree = self.generate_tree(start, &mut State::new(rng), &mut stats, &mut len, 1)?;
+        Ok((tree, stats))
     }
 
+    /// Generate a tree like [`generate`](Self::generate) continuing from `state`,
+    /// see [`Generator::generate_with_state`].
+    pub fn generate_with_state<R: Rng, S: Into<String>>(
+        &self,
+        start: S,
+        state: &mut State<R>,
+    ) -> Result<ParseTree<SymbolKind>> {
+        let start = SymbolKind::NonTerminal(self.grammar.start_symbol(start)?);
+        state.depth = 0;
+        state.progress = Progress::new();
+        let mut stats = GenerationStats::default();
+        self.generate_tree(start, state, &mut stats, &mut OutputLen::default(), 1)
+    }
+
+    /// The limits of the settings apply as in [`Generator::generate`], every symbol
+    /// being a step and the output length being that of the flattened tree.
     fn generate_tree<R: Rng>(
         &self,
         symbol: SymbolKind,
         state: &mut State<R>,
-    ) -> ParseTree<SymbolKind> {
-        match self.grammar.reduce(symbol, state) {
-            ReduceOutput::Terminal(s) => ParseTree::leaf(Terminal(s)),
-            ReduceOutput::NonTerminal { name, syms } => {
-                let subtrees = syms
+        stats: &mut GenerationStats,
+        len: &mut OutputLen,
+        depth: usize,
+    ) -> Result<ParseTree<SymbolKind>> {
+        state.progress.step(&self.settings)?;
+        stats.depth = stats.depth.max(depth);
+        match self.grammar.reduce(symbol, state, &self.settings)? {
+            ReduceOutput::Terminal(s) => {
+                stats.leaves += 1;
+                len.push(&self.settings, s.chars().count())?;
+                Ok(ParseTree::leaf(Terminal(s)))
+            }
+            ReduceOutput::Bytes(bytes) => {
+                stats.leaves += 1;
+                let s = utf8(bytes)?;
+                len.push(&self.settings, s.chars().count())?;
+                Ok(ParseTree::leaf(Terminal(s)))
+            }
+            ReduceOutput::NonTerminal { name, alt, syms } => {
+                stats.branches += 1;
+                state.depth += 1;
+                let children = syms
                     .into_iter()
-                    .map(|sym| self.generate_tree(sym, state))
-                    .collect::<Vec<_>>();
-                ParseTree::branch(name.to_string(), subtrees)
+                    .map(|sym| self.generate_tree(sym, state, stats, len, depth + 1))
+                    .collect::<Result<Vec<_>>>();
+                // restored on failure too, the state may be reused
+                state.depth -= 1;
+                Ok(ParseTree::branch(name.to_string(), Some(alt), children?))
             }
         }
     }
@@ -71,8 +723,13 @@ impl TreeGenerator {
 
 #[cfg(test)]
 mod test {
-    use crate::generator::{Generator, TreeGenerator};
+    use crate::error::Error;
+    use crate::generator::{
+        Generator, GeneratorSettings, RepeatDist, RepetitionStats, TreeGenerator,
+    };
+    use crate::grammar::checked::CheckedGrammar;
     use crate::grammar::raw::RawGrammar;
+    use crate::grammar::state::State;
     use rand::SeedableRng;
 
     #[test]
@@ -83,10 +740,868 @@ mod test {
         "#;
         let grammar = RawGrammar::parse(text).unwrap().to_checked().unwrap();
         let gen = Generator::builder().grammar(grammar).build();
-        let out = gen.generate("S", &mut rand::thread_rng());
+        let out = gen.generate("S", &mut rand::thread_rng()).unwrap();
         assert!(out.split(" ").count() >= 100);
     }
 
+    #[test]
+    fn custom_separator() {
+        let text = r#"
+            <Num> ::= <D> <D> <D> ;
+            <D> ::= "1" ;
+        "#;
+        let grammar = RawGrammar::parse(text).unwrap().to_checked().unwrap();
+        let settings = GeneratorSettings::builder().separator("").build();
+        let gen = Generator::builder()
+            .grammar(grammar)
+            .settings(settings)
+            .build();
+        assert_eq!(gen.generate("Num", &mut rand::thread_rng()).unwrap(), "111");
+    }
+
+    #[test]
+    fn generate_unique() {
+        let text = r#"
+            <S> ::= "a" | "b" | "c" ;
+        "#;
+        let grammar = RawGrammar::parse(text).unwrap().to_checked().unwrap();
+        let gen = Generator::builder().grammar(grammar).build();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+        let mut out = gen.generate_unique("S", &mut rng, 10, 1000).unwrap();
+        out.sort();
+        assert_eq!(out, vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn fractional_weights() {
+        let text = r#"
+            <S> ::= 0.75 "a" | 0.25 "b" ;
+        "#;
+        let grammar = RawGrammar::parse(text).unwrap().to_checked().unwrap();
+        let gen = Generator::builder().grammar(grammar).build();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+        let a = (0..10000)
+            .filter(|_| gen.generate("S", &mut rng).unwrap() == "a")
+            .count();
+        assert!((7000..8000).contains(&a));
+    }
+
+    #[test]
+    fn zero_weight_never_chosen() {
+        let text = r#"
+            <S> ::= 0 "b" | "c" off | "a" | <L> ;
+            <L> ::= 0 "e" {0, 1000} | "d" {0, 1000} ;
+        "#;
+        let grammar = RawGrammar::parse(text).unwrap().to_checked().unwrap();
+        let gen = Generator::builder().grammar(grammar).build();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+        for _ in 0..1000 {
+            let out = gen.generate("S", &mut rng).unwrap();
+            assert!(["a", "d"].contains(&out.as_str()), "{}", out);
+            let out = gen.generate_bounded("S", &mut rng, 0).unwrap();
+            assert!(["a", "d"].contains(&out.as_str()), "{}", out);
+        }
+    }
+
+    #[test]
+    fn all_zero_weights() {
+        let text = r#"
+            <S> ::= 0 "a" | 0.0 "b" ;
+        "#;
+        let raw = RawGrammar::parse(text).unwrap();
+        assert!(matches!(
+            raw.check_weights(),
+            Err(Error::ZeroWeights { .. })
+        ));
+        // the generator still fails gracefully if the check is bypassed
+        let rules = raw.rules.into_iter().map(|r| (r.lhs, r.production));
+        let grammar = CheckedGrammar::new(rules.collect());
+        let gen = Generator::builder().grammar(grammar).build();
+        assert!(matches!(
+            gen.generate("S", &mut rand::thread_rng()),
+            Err(Error::NoCandidatesAvailable { .. })
+        ));
+    }
+
+    #[test]
+    fn undefined_start_symbol() {
+        let text = r#"
+            <S> ::= "a" ;
+        "#;
+        let grammar = RawGrammar::parse(text).unwrap().to_checked().unwrap();
+        assert!(grammar.contains("S"));
+        assert!(!grammar.contains("E"));
+        assert_eq!(grammar.non_terminals().len(), 1);
+        let gen = Generator::builder().grammar(grammar).build();
+        assert!(matches!(
+            gen.generate("E", &mut rand::thread_rng()),
+            Err(Error::UndefinedStartSymbol { .. })
+        ));
+    }
+
+    #[test]
+    fn undefined_typed_rule() {
+        let text = r#"
+            <S> ::= <E: "float"> ;
+            <E: "int"> ::= "1" ;
+        "#;
+        // bypass the checks to reach the generation
+        let raw = RawGrammar::parse(text).unwrap();
+        let rules = raw.rules.into_iter().map(|r| (r.lhs, r.production));
+        let grammar = CheckedGrammar::new(rules.collect());
+        let gen = Generator::builder().grammar(grammar).build();
+        match gen.generate("S", &mut rand::thread_rng()) {
+            Err(Error::UndefinedRule { name }) => assert_eq!(name, r#"<E: "float">"#),
+            _ => panic!("expect an undefined rule error"),
+        }
+    }
+
+    #[test]
+    fn seeded_determinism() {
+        let rules = [
+            r#"<S> ::= <E> "=" <E> | decl(<Id>) ":" ref(<Id>) | <S> ";" <S> {2} ;"#,
+            r#"<E> ::= <E: "int"> | re("[a-z]{1,3}") {1, 3} ;"#,
+            r#"<E: "int"> ::= re("[0-9]{1,2}") | 2 "0" ;"#,
+            r#"<E: "str"> ::= "\"s\"" | 'a'..'z' ;"#,
+            r#"<Id> ::= 'a'..'c' ;"#,
+        ];
+        // the output only depends on the seed, not on the order of the rules
+        let outputs = (0..rules.len())
+            .map(|i| {
+                let mut rotated = rules.to_vec();
+                rotated.rotate_left(i);
+                let gen = rotated.join("\n").parse::<Generator>().unwrap();
+                let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+                (0..10)
+                    .map(|_| gen.generate("S", &mut rng).unwrap())
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        assert!(outputs.iter().all(|out| out == &outputs[0]));
+        insta::assert_debug_snapshot!(outputs[0]);
+    }
+
+    #[test]
+    fn repetition_stats() {
+        let gen = r#"<S> ::= re("x{2,4}[0-9]+") ;"#.parse::<Generator>().unwrap();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+        let mut stats = RepetitionStats::default();
+        for _ in 0..200 {
+            gen.generate_with_repetition_stats("S", &mut rng, &mut stats)
+                .unwrap();
+        }
+        let reps = stats.iter().map(|(_, rep, _)| rep).collect::<Vec<_>>();
+        assert_eq!(reps, ["x{2,4}", "[0-9]+"]);
+        assert_eq!(stats.range("x{2,4}[0-9]+", "x{2,4}"), Some((2, 4)));
+        // unbounded, so capped by the default `repeat_max`
+        assert_eq!(stats.range("x{2,4}[0-9]+", "[0-9]+"), Some((1, 5)));
+        let total = stats
+            .histogram("x{2,4}[0-9]+", "x{2,4}")
+            .unwrap()
+            .values()
+            .sum::<usize>();
+        assert_eq!(total, 200);
+
+        // the outputs colliding with a terminal are re-generated and not counted
+        let gen = r#"<S> ::= re("a{1,2}") "a" ;"#.parse::<Generator>().unwrap();
+        let mut stats = RepetitionStats::default();
+        for _ in 0..50 {
+            gen.generate_with_repetition_stats("S", &mut rng, &mut stats)
+                .unwrap();
+        }
+        let histogram = stats.histogram("a{1,2}", "a{1,2}").unwrap();
+        assert_eq!(histogram.iter().collect::<Vec<_>>(), [(&2, &50)]);
+    }
+
+    #[test]
+    fn generation_stats() {
+        let text = r#"
+            <S> ::= <E> "+" <E> ;
+            <E> ::= "a" | <F> ;
+            <F> ::= "b" ;
+        "#;
+        let gen = text.parse::<TreeGenerator>().unwrap();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+        for _ in 0..10 {
+            let (tree, stats) = gen.generate_with_stats("S", &mut rng).unwrap();
+            assert_eq!(stats.leaves, 3);
+            assert_eq!(stats.leaves, tree.flatten(" ").split(' ').count());
+            // `<S>`, both `<E>`, and a `<F>` for every "b"
+            let bs = tree.flatten(" ").matches('b').count();
+            assert_eq!(stats.branches, 3 + bs);
+            assert_eq!(stats.depth, if bs > 0 { 4 } else { 3 });
+        }
+    }
+
+    #[test]
+    fn from_str() {
+        let gen: Generator = r#"<S> ::= "a" "b" ;"#.parse().unwrap();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+        assert_eq!(gen.generate("S", &mut rng).unwrap(), "a b");
+
+        let tree_gen: TreeGenerator = r#"<S> ::= "a" "b" ;"#.parse().unwrap();
+        let tree = tree_gen.generate("S", &mut rng).unwrap();
+        assert_eq!(tree.flatten(" "), "a b");
+
+        assert!(matches!(
+            r#"<S> ::= <A> ;"#.parse::<Generator>(),
+            Err(Error::UndefinedNonTerminal { .. })
+        ));
+    }
+
+    #[test]
+    fn generate_in_threads() {
+        let gen = r#"<S> ::= "a" | "b" ;"#.parse::<Generator>().unwrap();
+        let handles = (0..4)
+            .map(|seed| {
+                let gen = gen.clone();
+                std::thread::spawn(move || {
+                    let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+                    gen.generate("S", &mut rng).unwrap()
+                })
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            let out = handle.join().unwrap();
+            assert!(out == "a" || out == "b");
+        }
+    }
+
+    #[test]
+    fn long_output() {
+        // 2^17 terminals
+        let mut text = String::from(r#"<N0> ::= "a" ;"#);
+        for i in 1..=17 {
+            text.push_str(&format!("<N{}> ::= <N{}> <N{}> ;", i, i - 1, i - 1));
+        }
+        let gen = text.parse::<Generator>().unwrap();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+        let out = gen.generate("N17", &mut rng).unwrap();
+        assert_eq!(out.split(' ').count(), 1 << 17);
+    }
+
+    #[test]
+    fn generate_bounded() {
+        let text = r#"
+            <S> ::= 10 <S> "+" <S> | 10 "(" <S> ")" | <N> ;
+            <N> ::= re("[0-9]") ;
+        "#;
+        let gen = text.parse::<Generator>().unwrap();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+        for _ in 0..100 {
+            let out = gen.generate_bounded("S", &mut rng, 20).unwrap();
+            assert!(out.split(' ').count() <= 20, "{}", out);
+        }
+
+        // the limits apply as in `generate`
+        let limits = [
+            (
+                GeneratorSettings::builder().max_steps(5).build(),
+                Error::MaxDepthExceeded { max_steps: 5 },
+            ),
+            (
+                GeneratorSettings::builder().max_output_len(3).build(),
+                Error::OutputTooLong { max_len: 3 },
+            ),
+        ];
+        for (settings, err) in limits {
+            let limited = Generator::builder()
+                .grammar(gen.grammar.clone())
+                .settings(settings)
+                .build();
+            let errs = (0..100)
+                .filter_map(|_| limited.generate_bounded("S", &mut rng, 20).err())
+                .collect::<Vec<_>>();
+            assert!(errs.contains(&err), "{:?}", errs);
+        }
+        // the first two are forced by the minimum
+        let gen = r#"<S> ::= <A> <A> <A> ; <A> ::= "a" {2, 5} | "b" ;"#
+            .parse::<Generator>()
+            .unwrap();
+        for _ in 0..100 {
+            let out = gen.generate_bounded("S", &mut rng, 3).unwrap();
+            assert!(out.starts_with("a a "), "{}", out);
+        }
+    }
+
+    #[test]
+    fn max_steps() {
+        let text = r#"
+            <S> ::= <S> "a" | "a" ;
+        "#;
+        let grammar = RawGrammar::parse(text).unwrap().to_checked().unwrap();
+        let gen = Generator::builder()
+            .grammar(grammar)
+            .settings(GeneratorSettings::builder().max_steps(10).build())
+            .build();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+        let results = (0..100)
+            .map(|_| gen.generate("S", &mut rng))
+            .collect::<Vec<_>>();
+        assert!(results
+            .iter()
+            .any(|r| matches!(r, Err(Error::MaxDepthExceeded { max_steps: 10 }))));
+        // every successful generation fits in 10 steps, i.e. at most 5 `a`
+        assert!(results.iter().flatten().all(|s| s.split(' ').count() <= 5));
+    }
+
+    #[test]
+    fn timeout() {
+        let text = r#"
+            <S> ::= <S> "a" ;
+        "#;
+        let grammar = RawGrammar::parse(text).unwrap().to_checked().unwrap();
+        let timeout = std::time::Duration::from_millis(10);
+        let gen = Generator::builder()
+            .grammar(grammar)
+            .settings(GeneratorSettings::builder().timeout(timeout).build())
+            .build();
+        let out = gen.generate("S", &mut rand::thread_rng());
+        assert_eq!(out, Err(Error::Timeout { timeout }));
+    }
+
+    #[test]
+    fn generate_bytes() {
+        let text = r#"
+            <S> ::= "MAGIC" re_bytes("[\\x80-\\xff]{2}") ;
+        "#;
+        let grammar = RawGrammar::parse(text).unwrap().to_checked().unwrap();
+        let gen = Generator::builder().grammar(grammar).build();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+        let bytes = gen.generate_bytes("S", &mut rng).unwrap();
+        assert_eq!(&bytes[..6], b"MAGIC ");
+        assert!(bytes[6..].iter().all(|b| *b >= 0x80));
+        assert_eq!(bytes.len(), 8);
+        // no valid UTF-8 string can be generated
+        assert_eq!(gen.generate("S", &mut rng), Err(Error::InvalidUtf8));
+    }
+
+    #[test]
+    fn max_output_len() {
+        let text = r#"
+            <S> ::= "abc" "de" ;
+        "#;
+        let grammar = RawGrammar::parse(text).unwrap().to_checked().unwrap();
+        // "abc de" has 6 chars
+        for (max_len, ok) in [(6, true), (5, false)] {
+            let settings = GeneratorSettings::builder().max_output_len(max_len).build();
+            let gen = Generator::builder()
+                .grammar(grammar.clone())
+                .settings(settings)
+                .build();
+            let out = gen.generate("S", &mut rand::thread_rng());
+            match ok {
+                true => assert_eq!(out.unwrap(), "abc de"),
+                false => assert_eq!(out, Err(Error::OutputTooLong { max_len })),
+            }
+        }
+    }
+
+    #[test]
+    fn decl_limits() {
+        // the declaration never terminates
+        let text = r#"
+            <S> ::= decl(<E>) ;
+            <E> ::= "a" <E> ;
+        "#;
+        let grammar = RawGrammar::parse(text).unwrap().to_checked().unwrap();
+        let timeout = std::time::Duration::ZERO;
+        let limits = [
+            (
+                GeneratorSettings::builder().max_steps(50).build(),
+                Error::MaxDepthExceeded { max_steps: 50 },
+            ),
+            (
+                GeneratorSettings::builder().timeout(timeout).build(),
+                Error::Timeout { timeout },
+            ),
+            (
+                GeneratorSettings::builder().max_output_len(10).build(),
+                Error::OutputTooLong { max_len: 10 },
+            ),
+        ];
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+        for (settings, err) in limits {
+            let gen = Generator::builder()
+                .grammar(grammar.clone())
+                .settings(settings.clone())
+                .build();
+            assert_eq!(gen.generate("S", &mut rng).unwrap_err(), err);
+            let tree_gen = TreeGenerator::builder()
+                .grammar(grammar.clone())
+                .settings(settings)
+                .build();
+            assert_eq!(tree_gen.generate("S", &mut rng).unwrap_err(), err);
+        }
+    }
+
+    #[test]
+    fn tree_limits() {
+        // a deep recursion fails on the maximum steps instead of overflowing the stack
+        let text = r#"<S> ::= 3 <S> <S> | "a" ;"#;
+        let grammar = RawGrammar::parse(text).unwrap().to_checked().unwrap();
+        let tree_gen = TreeGenerator::builder()
+            .grammar(grammar.clone())
+            .settings(GeneratorSettings::builder().max_steps(50).build())
+            .build();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(2);
+        let results = (0..100)
+            .map(|_| tree_gen.generate_with_stats("S", &mut rng))
+            .collect::<Vec<_>>();
+        assert!(results
+            .iter()
+            .any(|r| matches!(r, Err(Error::MaxDepthExceeded { max_steps: 50 }))));
+        // every symbol of a successful tree is a step
+        assert!(results
+            .iter()
+            .flatten()
+            .all(|(_, stats)| stats.leaves + stats.branches <= 50));
+
+        let text = r#"
+            <S> ::= <T> <T> <T> <T> <T> <T> <T> <T> ;
+            <T> ::= <U> <U> <U> <U> <U> <U> <U> <U> ;
+            <U> ::= "a" ;
+        "#;
+        let grammar = RawGrammar::parse(text).unwrap().to_checked().unwrap();
+        let timeout = std::time::Duration::ZERO;
+        let tree_gen = TreeGenerator::builder()
+            .grammar(grammar.clone())
+            .settings(GeneratorSettings::builder().timeout(timeout).build())
+            .build();
+        assert_eq!(
+            tree_gen.generate("S", &mut rng).unwrap_err(),
+            Error::Timeout { timeout }
+        );
+
+        // "abc de" has 6 chars
+        let grammar = RawGrammar::parse(r#"<S> ::= "abc" "de" ;"#)
+            .unwrap()
+            .to_checked()
+            .unwrap();
+        for (max_len, ok) in [(6, true), (5, false)] {
+            let settings = GeneratorSettings::builder().max_output_len(max_len).build();
+            let tree_gen = TreeGenerator::builder()
+                .grammar(grammar.clone())
+                .settings(settings)
+                .build();
+            let out = tree_gen.generate("S", &mut rng);
+            match ok {
+                true => assert_eq!(out.unwrap().flatten(" "), "abc de"),
+                false => assert_eq!(out.unwrap_err(), Error::OutputTooLong { max_len }),
+            }
+        }
+    }
+
+    #[test]
+    fn decl_and_ref() {
+        let text = r#"
+            <S> ::= <Decl> <Decl> <Use> ;
+            <Decl> ::= "let" decl(<Id>) ";" ;
+            <Use> ::= "print" ref(<Id>) ";" ;
+            <Id> ::= re("[a-z]{8}") ;
+        "#;
+        let grammar = RawGrammar::parse(text).unwrap().to_checked().unwrap();
+        let gen = Generator::builder().grammar(grammar).build();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+        for _ in 0..100 {
+            let out = gen.generate("S", &mut rng).unwrap();
+            let tokens = out.split(' ').collect::<Vec<_>>();
+            // let <a> ; let <b> ; print <ref> ;
+            assert!(tokens[7] == tokens[1] || tokens[7] == tokens[4]);
+        }
+    }
+
+    #[test]
+    fn typed_decl_and_ref() {
+        let text = r#"
+            <S> ::= <Int> <Str> <Num> "use" ref(: "int") ref(<Id>) ;
+            <Int> ::= "int" decl(<Id>: "int") ";" ;
+            <Str> ::= "str" decl(<Id>: "str") ";" ;
+            <Num> ::= "int" decl(<N>: "int") ";" ;
+            <Id> ::= re("[a-z]{8}") ;
+            <N> ::= re("n[0-9]{8}") ;
+        "#;
+        let gen = text.parse::<Generator>().unwrap();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+        let mut used = std::collections::HashSet::new();
+        for _ in 0..100 {
+            let out = gen.generate("S", &mut rng).unwrap();
+            let tokens = out.split(' ').collect::<Vec<_>>();
+            // int <a> ; str <b> ; int <c> ; use <int> <id>
+            let (int, id) = (tokens[10], tokens[11]);
+            assert!(int == tokens[1] || int == tokens[7], "{}", out);
+            used.insert(int == tokens[1]);
+            // declared as an "int" or a "str", an <Id> is still an <Id>
+            assert!(id == tokens[1] || id == tokens[4], "{}", out);
+        }
+        assert_eq!(used.len(), 2);
+
+        // referred to before any declaration
+        let gen = r#"<S> ::= ref(: "int") decl(<Id>: "int") ; <Id> ::= "x" ;"#
+            .parse::<Generator>()
+            .unwrap();
+        let err = gen.generate("S", &mut rng).unwrap_err();
+        assert_eq!(
+            err,
+            Error::UndeclaredReference {
+                name: r#": "int""#.to_string()
+            }
+        );
+        assert_eq!(err.to_string(), r#"No declaration found for : "int""#);
+    }
+
+    #[test]
+    fn decay() {
+        // the recursive alternative can only be taken by the start symbol
+        let text = r#"<E> ::= "1" | <E> "+" <E> {decay = 0} ;"#;
+        let gen = text.parse::<Generator>().unwrap();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+        let outputs = (0..100)
+            .map(|_| gen.generate("E", &mut rng).unwrap())
+            .collect::<std::collections::HashSet<_>>();
+        assert_eq!(outputs, ["1", "1 + 1"].map(String::from).into());
+
+        // the depth is tracked by every way of generation
+        let text = r#"
+            <S> ::= decl(<E>) ;
+            <E> ::= "1" | <E> "+" <E> {decay = 0} ;
+        "#;
+        let tree_gen = text.parse::<TreeGenerator>().unwrap();
+        let gen = text.parse::<Generator>().unwrap();
+        for _ in 0..100 {
+            let (_, stats) = tree_gen.generate_with_stats("E", &mut rng).unwrap();
+            assert!(stats.depth <= 3);
+            let out = gen.generate_bounded("E", &mut rng, 100).unwrap();
+            assert!(out == "1" || out == "1 + 1");
+            let out = gen.generate("S", &mut rng).unwrap();
+            assert!(out.len() <= "1 + 1 + 1".len());
+        }
+
+        // a failed tree generation leaves the depth of a reused state intact
+        let text = r#"
+            <S> ::= <B> ;
+            <B> ::= $INDEX ;
+            <E> ::= "1" | <E> "+" <E> {decay = 0} ;
+        "#;
+        let tree_gen = text.parse::<TreeGenerator>().unwrap();
+        let mut state = State::new(&mut rng);
+        let err = tree_gen.generate_with_state("S", &mut state).unwrap_err();
+        assert_eq!(
+            err,
+            Error::UnsetBuiltin {
+                builtin: crate::grammar::symbol::Builtin::Index
+            }
+        );
+        assert_eq!(state.depth(), 0);
+        // and a new one starts from the root whatever the depth left over
+        state.depth = 5;
+        let outputs = (0..100)
+            .map(|_| tree_gen.generate_with_state("E", &mut state).unwrap())
+            .map(|tree| tree.flatten(" "))
+            .collect::<std::collections::HashSet<_>>();
+        assert_eq!(outputs, ["1", "1 + 1"].map(String::from).into());
+
+        // an unbiased binary recursion rarely terminates, unless it decays
+        let text = r#"<E> ::= "1" | <E> "+" <E> {decay = 0.5} ;"#;
+        let gen = text.parse::<Generator>().unwrap();
+        for _ in 0..100 {
+            assert!(gen.generate("E", &mut rng).unwrap().len() < 1000);
+        }
+    }
+
+    #[test]
+    fn generate_explained() {
+        let text = r#"
+            <S> ::= <E> ";" ;
+            <E> ::= <T> "+" <E> | <T> ;
+            <T: "int"> ::= re("[0-9]") ;
+            <T: "str"> ::= "'" 'a'..'z' "'" ;
+        "#;
+        let gen = text.parse::<Generator>().unwrap();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+        let (out, steps) = gen.generate_explained("S", &mut rng).unwrap();
+        let steps = steps.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        insta::assert_snapshot!(format!("{}\n{}", out, steps.join("\n")));
+    }
+
+    #[test]
+    fn explained_identical_alternatives() {
+        let text = r#"<S> ::= "x" @one | "x" @two ;"#;
+        let gen = text.parse::<Generator>().unwrap();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+        let mut alts = std::collections::HashSet::new();
+        for _ in 0..100 {
+            let (_, steps) = gen.generate_explained("S", &mut rng).unwrap();
+            alts.insert(steps[0].alt);
+        }
+        assert_eq!(alts, std::collections::HashSet::from([0, 1]));
+    }
+
+    #[test]
+    fn generate_into() {
+        let text = r#"
+            <S> ::= <E> | <E> "+" <S> ;
+            <E> ::= re("[0-9]{1,3}") ;
+        "#;
+        let gen = text.parse::<Generator>().unwrap();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+        let expected = (0..10)
+            .map(|_| gen.generate("S", &mut rng).unwrap())
+            .collect::<Vec<_>>();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+        let mut out = String::with_capacity(1024);
+        for expected in expected {
+            gen.generate_into("S", &mut rng, &mut out).unwrap();
+            assert_eq!(out, expected);
+            assert!(out.capacity() >= 1024);
+        }
+        assert!(gen.generate_into("T", &mut rng, &mut out).is_err());
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn builtins() {
+        let text = r#"<S> ::= "no." $INDEX "of" $SEED "$SEED" ;"#;
+        let gen = text.parse::<Generator>().unwrap();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+        let err = gen.generate("S", &mut rng).unwrap_err();
+        assert!(matches!(err, Error::UnsetBuiltin { .. }));
+        assert_eq!(err.to_string(), "$INDEX is not set for this generation");
+
+        let mut state = State::new(&mut rng).with_index(7).with_seed(42);
+        let out = gen.generate_with_state("S", &mut state).unwrap();
+        assert_eq!(out, "no. 7 of 42 $SEED");
+        let tree = TreeGenerator::builder()
+            .grammar(gen.grammar.clone())
+            .build()
+            .generate_with_state("S", &mut state)
+            .unwrap();
+        assert_eq!(tree.to_string(), r#"(S "no." "7" "of" "42" "$SEED")"#);
+
+        let gen = r#"<S> ::= $INDEX ;"#.parse::<Generator>().unwrap();
+        let outs = gen.generate_many("S", &mut rng, 3).unwrap();
+        assert_eq!(outs, vec!["0", "1", "2"]);
+    }
+
+    #[test]
+    fn generate_until() {
+        let text = r#"
+            <S> ::= <E> | <E> "+" <S> ;
+            <E> ::= re("[0-9]{1,3}") ;
+        "#;
+        let gen = text.parse::<Generator>().unwrap();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+        let out = gen
+            .generate_until("S", &mut rng, 1000, |s| s.matches('+').count() == 3)
+            .unwrap()
+            .unwrap();
+        assert_eq!(out.matches('+').count(), 3);
+        let never = gen.generate_until("S", &mut rng, 100, |s| s.contains('-'));
+        assert_eq!(never, Ok(None));
+        assert!(gen.generate_until("T", &mut rng, 100, |_| true).is_err());
+        // the predicate may keep a state, e.g. the rejected outputs
+        let mut rejected = Vec::new();
+        let out = gen
+            .generate_until("S", &mut rng, 1000, |s| {
+                rejected.push(s.to_string());
+                rejected.len() == 5
+            })
+            .unwrap();
+        assert_eq!(out.as_ref(), rejected.last());
+        assert_eq!(rejected.len(), 5);
+
+        // the generations exceeding the steps are only failed tries
+        let settings = GeneratorSettings::builder().max_steps(10).build();
+        let gen = Generator::builder()
+            .grammar(gen.grammar)
+            .settings(settings)
+            .build();
+        for _ in 0..10 {
+            assert!(gen
+                .generate_until("S", &mut rng, 10, |_| true)
+                .unwrap()
+                .is_some());
+        }
+    }
+
+    #[test]
+    fn generate_with_state() {
+        let text = r#"
+            <Decl> ::= "let" decl(<Id>) ";" ;
+            <Use> ::= "print" ref(<Id>) ";" ;
+            <Id> ::= re("[a-z]{8}") ;
+        "#;
+        let gen = text.parse::<Generator>().unwrap();
+        let mut state = State::new(rand::rngs::StdRng::seed_from_u64(42));
+        // a fresh state has nothing declared yet
+        assert!(gen.generate("Use", state.rng()).is_err());
+        let decl = gen.generate_with_state("Decl", &mut state).unwrap();
+        for _ in 0..10 {
+            let used = gen.generate_with_state("Use", &mut state).unwrap();
+            assert_eq!(used.split(' ').nth(1), decl.split(' ').nth(1));
+        }
+    }
+
+    #[test]
+    fn undeclared_ref() {
+        let text = r#"
+            <S> ::= ref(<Id>) decl(<Id>) ;
+            <Id> ::= "x" ;
+        "#;
+        let grammar = RawGrammar::parse(text).unwrap().to_checked().unwrap();
+        let gen = Generator::builder().grammar(grammar).build();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+        assert_eq!(
+            gen.generate("S", &mut rng),
+            Err(Error::UndeclaredReference {
+                name: "<Id>".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn note() {
+        let text = r#"
+            <S> ::= note("x") <R> ;
+            <R> ::= re("x|y") ;
+        "#;
+        let gen = text.parse::<Generator>().unwrap();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+        let outputs = (0..100)
+            .map(|_| gen.generate("S", &mut rng).unwrap())
+            .collect::<std::collections::HashSet<_>>();
+        // a note is not a terminal, the regex is free to produce the same text
+        assert_eq!(outputs, ["x x", "x y"].map(String::from).into());
+    }
+
+    #[test]
+    fn generate_many() {
+        let text = r#"
+            <S> ::= "x" {1} | "y" ;
+        "#;
+        let gen = text.parse::<Generator>().unwrap();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+        // the invoke limit is reset for every call of `generate`
+        for _ in 0..10 {
+            assert_eq!(gen.generate("S", &mut rng).unwrap(), "x");
+        }
+        // while it is shared across the outputs of `generate_many`
+        let outputs = gen.generate_many("S", &mut rng, 10).unwrap();
+        assert_eq!(outputs[0], "x");
+        assert!(outputs.iter().any(|s| s == "y"));
+    }
+
+    #[test]
+    fn observer() {
+        let text = r#"
+            <S> ::= <E> "+" <E> ;
+            <E> ::= "a" | "b" ;
+        "#;
+        let gen = text.parse::<Generator>().unwrap();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+        let mut coverage = std::collections::HashMap::new();
+        for _ in 0..10 {
+            gen.generate_with_observer("S", &mut rng, &mut |nt, alt| {
+                *coverage.entry((nt.as_str().to_string(), alt)).or_insert(0) += 1;
+            })
+            .unwrap();
+        }
+        // `<S>` has a single alternative, `<E>` has two of them
+        assert_eq!(coverage.len(), 3);
+        assert_eq!(coverage.values().sum::<usize>(), 30);
+    }
+
+    #[test]
+    fn overrides() {
+        let text = r#"
+            <S> ::= <E> | <E> <E> ;
+            <E> ::= "a" | "b" | "c" ;
+        "#;
+        let gen = text.parse::<Generator>().unwrap();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+        let overrides = [("S".to_string(), 1), ("E".to_string(), 2)].into();
+        for _ in 0..10 {
+            let out = gen.generate_with_overrides("S", &mut rng, &overrides);
+            assert_eq!(out.unwrap(), "c c");
+        }
+
+        let overrides = [("E".to_string(), 3)].into();
+        assert_eq!(
+            gen.generate_with_overrides("S", &mut rng, &overrides),
+            Err(Error::InvalidOverride {
+                name: "E".to_string(),
+                index: 3
+            })
+        );
+    }
+
+    #[test]
+    fn label_overrides() {
+        let text = r#"
+            <S> ::= <E> @one | <E> <E> @two ;
+            <E> ::= "a" @a | "b" @b | "c" @c ;
+        "#;
+        let gen = text.parse::<Generator>().unwrap();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+        let overrides = [
+            ("S".to_string(), "two".to_string()),
+            ("E".to_string(), "c".to_string()),
+        ]
+        .into();
+        for _ in 0..10 {
+            let out = gen.generate_with_label_overrides("S", &mut rng, &overrides);
+            assert_eq!(out.unwrap(), "c c");
+        }
+
+        let overrides = [("E".to_string(), "d".to_string())].into();
+        assert_eq!(
+            gen.generate_with_label_overrides("S", &mut rng, &overrides),
+            Err(Error::UnknownLabel {
+                name: "E".to_string(),
+                label: "d".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn geometric_repeat() {
+        let text = r#"
+            <S> ::= re("a{0,100}") ;
+        "#;
+        let grammar = RawGrammar::parse(text).unwrap().to_checked().unwrap();
+        let settings = GeneratorSettings::builder()
+            .repeat_dist(RepeatDist::Geometric { p: 0.5 })
+            .build();
+        let gen = Generator::builder()
+            .grammar(grammar)
+            .settings(settings)
+            .build();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+        let total = (0..10000)
+            .map(|_| gen.generate("S", &mut rng).unwrap().len())
+            .sum::<usize>();
+        // the mean of geometric distribution is (1 - p) / p = 1
+        let mean = total as f64 / 10000.0;
+        assert!((0.9..1.1).contains(&mean), "{}", mean);
+
+        for p in [1.5, -0.5, f64::NAN] {
+            let settings = GeneratorSettings::builder()
+                .repeat_dist(RepeatDist::Geometric { p })
+                .build();
+            let gen = Generator::builder()
+                .grammar(gen.grammar.clone())
+                .settings(settings)
+                .build();
+            let err = gen.generate("S", &mut rng).unwrap_err();
+            assert_eq!(err, Error::InvalidProbability { p: p.to_string() });
+        }
+        let err = Error::InvalidProbability { p: 1.5.to_string() };
+        assert_eq!(
+            err.to_string(),
+            "Probability 1.5 of the geometric repetition is not within 0..=1"
+        );
+    }
+
     #[test]
     fn test_tree_generator() {
         let text = r#"
@@ -94,12 +1609,29 @@ mod test {
             <E> ::= "a" {1, 10} | "b" {2, } | "c" {3} | "fallback" ;
         "#;
         let grammar = RawGrammar::parse(text).unwrap().to_checked().unwrap();
-        let tree_gen = TreeGenerator { grammar };
+        let tree_gen = TreeGenerator::builder().grammar(grammar).build();
         let mut seeded_rng = rand::rngs::StdRng::seed_from_u64(42);
-        let tree = tree_gen.generate("S", &mut seeded_rng);
+        let tree = tree_gen.generate("S", &mut seeded_rng).unwrap();
         insta::assert_debug_snapshot!(&tree);
     }
 
+    #[test]
+    fn flatten_tree_as_generator() {
+        let text = include_str!("../examples/set-algebra-typed.bnfgen");
+        let grammar = RawGrammar::parse(text).unwrap().to_checked().unwrap();
+        let gen = Generator::builder().grammar(grammar).build();
+        let out = gen
+            .generate("Program", &mut rand::rngs::StdRng::seed_from_u64(42))
+            .unwrap();
+
+        let grammar = RawGrammar::parse(text).unwrap().to_checked().unwrap();
+        let tree_gen = TreeGenerator::builder().grammar(grammar).build();
+        let tree = tree_gen
+            .generate("Program", &mut rand::rngs::StdRng::seed_from_u64(42))
+            .unwrap();
+        assert_eq!(tree.flatten(" "), out);
+    }
+
     #[test]
     fn test_typed_generator() {
         let text = r#"
@@ -114,19 +1646,19 @@ mod test {
                             | <E: "bool"> "&" <E: "bool"> {3, } ;
         "#;
         let grammar = RawGrammar::parse(text).unwrap().to_checked().unwrap();
-        let gen = Generator { grammar };
+        let gen = Generator::builder().grammar(grammar).build();
         let mut seeded_rng = rand::rngs::StdRng::seed_from_u64(42);
-        insta::assert_snapshot!(gen.generate("S", &mut seeded_rng));
+        insta::assert_snapshot!(gen.generate("S", &mut seeded_rng).unwrap());
     }
 
     #[test]
     fn test_typed_set_algebra_expr() {
         let text = include_str!("../examples/set-algebra-typed.bnfgen");
         let grammar = RawGrammar::parse(text).unwrap().to_checked().unwrap();
-        let gen = Generator { grammar };
+        let gen = Generator::builder().grammar(grammar).build();
         let mut seeded_rng = rand::rngs::StdRng::seed_from_u64(42);
         let out = (0..100)
-            .map(|_| gen.generate("Expr", &mut seeded_rng))
+            .map(|_| gen.generate("Expr", &mut seeded_rng).unwrap())
             .collect::<Vec<_>>()
             .join("\n");
         insta::assert_snapshot!(out);
@@ -136,9 +1668,9 @@ mod test {
     fn test_typed_set_algebra() {
         let text = include_str!("../examples/set-algebra-typed.bnfgen");
         let grammar = RawGrammar::parse(text).unwrap().to_checked().unwrap();
-        let gen = Generator { grammar };
+        let gen = Generator::builder().grammar(grammar).build();
         let mut seeded_rng = rand::rngs::StdRng::seed_from_u64(42);
-        let out = gen.generate("Program", &mut seeded_rng);
+        let out = gen.generate("Program", &mut seeded_rng).unwrap();
         insta::assert_snapshot!(out);
     }
 }
@@ -0,0 +1,480 @@
+use crate::error::{Error, Result};
+use crate::grammar::alt::{Alternative, Limit};
+use crate::grammar::production::WeightedProduction;
+use crate::grammar::raw::RawGrammar;
+use crate::grammar::rule::Rule;
+use crate::grammar::symbol::{NonTerminal, Symbol, SymbolKind};
+use crate::span::Span;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+/// the core rules of RFC 5234 (appendix B.1), except `LWSP` which needs a group,
+/// they are imported on demand when referenced but not defined
+const CORE_RULES: &str = r#"
+ALPHA = %x41-5A / %x61-7A
+BIT = "0" / "1"
+CHAR = %x01-7F
+CR = %x0D
+CRLF = CR LF
+CTL = %x00-1F / %x7F
+DIGIT = %x30-39
+DQUOTE = %x22
+HEXDIG = DIGIT / "A" / "B" / "C" / "D" / "E" / "F"
+HTAB = %x09
+LF = %x0A
+OCTET = %x00-FF
+SP = %x20
+VCHAR = %x21-7E
+WSP = SP / HTAB
+"#;
+
+impl RawGrammar {
+    /// Import a grammar written in a subset of ABNF (RFC 5234), e.g. `greeting = "hello" SP 1*3ALPHA`.
+    ///
+    /// Supported are rule definitions (`=` and `=/`), `/` alternation, `n*m` repetition,
+    /// quoted strings and `%x`/`%d`/`%b` values (including `%x41.42` and `%x30-39`).
+    /// Groups, options and prose values are rejected with an [`Error::UnsupportedAbnf`].
+    ///
+    /// Some notes on the translation:
+    /// - a repetition becomes a helper rule named `<rule>_rep<n>`, since invoke limits count
+    ///   the invocations during the whole generation rather than the repetitions in place
+    /// - rule names are case-insensitive, every reference is renamed to its definition
+    /// - referenced core rules such as `ALPHA` or `DIGIT` are imported if not defined
+    /// - ABNF operates on characters, so the generator is usually used with an empty separator
+    pub fn from_abnf(input: &str) -> Result<RawGrammar> {
+        let mut rules = Importer::new(tokenize(input)?).import()?;
+
+        let mut core = Importer::new(tokenize(CORE_RULES)?).import()?;
+        loop {
+            let defined: HashSet<String> = rules
+                .iter()
+                .map(|r| r.lhs.as_str().to_ascii_lowercase())
+                .collect();
+            let missing: HashSet<String> = rules
+                .iter()
+                .flat_map(|r| r.rhs().iter().flat_map(|a| a.symbols.iter()))
+                .filter_map(|s| s.non_terminal())
+                .map(|s| s.to_ascii_lowercase())
+                .filter(|s| !defined.contains(s))
+                .collect();
+            let (used, rest): (Vec<_>, Vec<_>) = core
+                .into_iter()
+                .partition(|r| missing.contains(&r.lhs.as_str().to_ascii_lowercase()));
+            core = rest;
+            if used.is_empty() {
+                break;
+            }
+            rules.extend(used);
+        }
+
+        let mut names: HashMap<String, NonTerminal> = HashMap::new();
+        for rule in &rules {
+            names
+                .entry(rule.lhs.as_str().to_ascii_lowercase())
+                .or_insert_with(|| rule.lhs.clone());
+        }
+        let canonical = |nt: &NonTerminal| names.get(&nt.as_str().to_ascii_lowercase()).cloned();
+        for rule in &mut rules {
+            if let Some(nt) = canonical(&rule.lhs) {
+                rule.lhs = nt;
+            }
+            for sym in rule
+                .production
+                .alts
+                .iter_mut()
+                .flat_map(|a| a.symbols.iter_mut())
+            {
+                if let SymbolKind::NonTerminal(nt) = &sym.kind {
+                    if let Some(nt) = canonical(nt) {
+                        sym.kind = SymbolKind::NonTerminal(nt);
+                    }
+                }
+            }
+        }
+
+        Ok(RawGrammar {
+            rules,
+            includes: Vec::new(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Name(String),
+    Str(String),
+    /// `%x41.42`, a concatenation of values
+    Values(String),
+    /// `%x30-39`, a range of values
+    Range(char, char),
+    /// `n*m` prefix, `None` if there is no upper bound
+    Repeat(usize, Option<usize>),
+    /// `=`
+    Define,
+    /// `=/`
+    Incremental,
+    /// `/`
+    Slash,
+    /// a new line not followed by whitespace, which ends the current rule
+    End,
+}
+
+fn invalid(start: usize, end: usize, reason: &str) -> Error {
+    Error::InvalidAbnf {
+        span: Span::new(start, end),
+        reason: reason.to_string(),
+    }
+}
+
+/// read the digits of the given radix starting at `start`, returns the value and the end
+fn digits(input: &str, start: usize, radix: u32) -> (Option<u32>, usize) {
+    let len = input[start..]
+        .find(|c: char| !c.is_digit(radix))
+        .unwrap_or(input.len() - start);
+    let value = u32::from_str_radix(&input[start..start + len], radix).ok();
+    (value, start + len)
+}
+
+fn tokenize(input: &str) -> Result<Vec<(Token, Span)>> {
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while let Some(c) = input[i..].chars().next() {
+        let start = i;
+        let token = match c {
+            ';' => {
+                i = input[i..].find('\n').map_or(input.len(), |n| i + n);
+                continue;
+            }
+            '\n' => {
+                i += 1;
+                match input[i..].chars().next() {
+                    Some(c) if !c.is_whitespace() => Token::End,
+                    _ => continue,
+                }
+            }
+            c if c.is_whitespace() => {
+                i += c.len_utf8();
+                continue;
+            }
+            '=' if input[i..].starts_with("=/") => {
+                i += 2;
+                Token::Incremental
+            }
+            '=' => {
+                i += 1;
+                Token::Define
+            }
+            '/' => {
+                i += 1;
+                Token::Slash
+            }
+            '"' => {
+                let len = input[i + 1..]
+                    .find(['"', '\n'])
+                    .filter(|&n| input[i + 1 + n..].starts_with('"'))
+                    .ok_or_else(|| invalid(start, start + 1, "this string is unterminated"))?;
+                i += len + 2;
+                Token::Str(input[start + 1..start + 1 + len].to_string())
+            }
+            '%' => {
+                let radix = match input[i + 1..].chars().next() {
+                    Some('x' | 'X') => 16,
+                    Some('d' | 'D') => 10,
+                    Some('b' | 'B') => 2,
+                    _ => {
+                        return Err(Error::UnsupportedAbnf {
+                            span: Span::new(start, start + 1),
+                        })
+                    }
+                };
+                i += 2;
+                let value = |i: &mut usize| {
+                    let (value, end) = digits(input, *i, radix);
+                    let c = value.and_then(char::from_u32);
+                    let c = c.ok_or_else(|| invalid(start, end.max(*i + 1), "invalid value"))?;
+                    *i = end;
+                    Ok::<_, Error>(c)
+                };
+                let first = value(&mut i)?;
+                if input[i..].starts_with('-') {
+                    i += 1;
+                    Token::Range(first, value(&mut i)?)
+                } else {
+                    let mut values = String::from(first);
+                    while input[i..].starts_with('.') {
+                        i += 1;
+                        values.push(value(&mut i)?);
+                    }
+                    Token::Values(values)
+                }
+            }
+            '0'..='9' | '*' => {
+                let (min, end) = digits(input, i, 10);
+                i = end;
+                if input[i..].starts_with('*') {
+                    let (max, end) = digits(input, i + 1, 10);
+                    i = end;
+                    Token::Repeat(min.unwrap_or(0) as usize, max.map(|n| n as usize))
+                } else {
+                    let n = min.unwrap() as usize;
+                    Token::Repeat(n, Some(n))
+                }
+            }
+            c if c.is_ascii_alphabetic() => {
+                let len = input[i..]
+                    .find(|c: char| !c.is_ascii_alphanumeric() && c != '-')
+                    .unwrap_or(input.len() - i);
+                i += len;
+                Token::Name(input[start..i].to_string())
+            }
+            '(' | '[' | '<' => {
+                return Err(Error::UnsupportedAbnf {
+                    span: Span::new(start, start + 1),
+                })
+            }
+            c => return Err(invalid(start, start + c.len_utf8(), "unexpected character")),
+        };
+        tokens.push((token, Span::new(start, i)));
+    }
+    tokens.push((Token::End, Span::new(input.len(), input.len())));
+    Ok(tokens)
+}
+
+struct Importer {
+    tokens: Vec<(Token, Span)>,
+    pos: usize,
+    rules: Vec<Rule>,
+    helpers: Vec<Rule>,
+    /// name of the rule being imported, used to name the helper rules
+    current: String,
+}
+
+impl Importer {
+    fn new(tokens: Vec<(Token, Span)>) -> Self {
+        Importer {
+            tokens,
+            pos: 0,
+            rules: Vec::new(),
+            helpers: Vec::new(),
+            current: String::new(),
+        }
+    }
+
+    fn peek(&self) -> Option<&(Token, Span)> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<(Token, Span)> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn import(mut self) -> Result<Vec<Rule>> {
+        while let Some((token, span)) = self.next() {
+            let (name, start) = match token {
+                Token::End => continue,
+                Token::Name(name) => (name, span),
+                _ => return Err(invalid(span.start(), span.end(), "expect a rule name")),
+            };
+            let incremental = match self.next() {
+                Some((Token::Define, _)) => false,
+                Some((Token::Incremental, _)) => true,
+                Some((_, span)) => return Err(invalid(span.start(), span.end(), "expect `=`")),
+                None => unreachable!("the tokens always end with `End`"),
+            };
+            self.current = name.clone();
+            let alts = self.alternation()?;
+            let end = self.tokens[self.pos - 1].1;
+            let span = Span::new(start.start(), end.end());
+
+            let prev = self
+                .rules
+                .iter_mut()
+                .find(|r| r.lhs.as_str().eq_ignore_ascii_case(&name));
+            match (incremental, prev) {
+                (true, Some(rule)) => {
+                    rule.production.alts.extend(alts);
+                }
+                (true, None) => {
+                    return Err(invalid(span.start(), span.end(), "this rule is undefined"));
+                }
+                (false, _) => self.rules.push(Rule {
+                    lhs: NonTerminal::untyped(name),
+                    production: WeightedProduction::new(alts, span),
+                    span,
+                    likes: Vec::new(),
+                }),
+            }
+        }
+        self.rules.append(&mut self.helpers);
+        Ok(self.rules)
+    }
+
+    fn alternation(&mut self) -> Result<Vec<Alternative>> {
+        let mut alts = vec![self.concatenation()?];
+        while let Some((Token::Slash, _)) = self.peek() {
+            self.pos += 1;
+            alts.push(self.concatenation()?);
+        }
+        match self.next() {
+            Some((Token::End, _)) => Ok(alts),
+            Some((_, span)) => Err(invalid(
+                span.start(),
+                span.end(),
+                "expect `/` or a new rule",
+            )),
+            None => unreachable!("the tokens always end with `End`"),
+        }
+    }
+
+    fn concatenation(&mut self) -> Result<Alternative> {
+        let mut symbols = vec![self.repetition()?];
+        while !matches!(self.peek(), Some((Token::Slash | Token::End, _))) {
+            symbols.push(self.repetition()?);
+        }
+        let span = Span::new(
+            symbols[0].span.start(),
+            symbols[symbols.len() - 1].span.end(),
+        );
+        Ok(Self::alternative(symbols, span))
+    }
+
+    fn repetition(&mut self) -> Result<Symbol> {
+        match self.peek().cloned() {
+            Some((Token::Repeat(min, max), span)) => {
+                self.pos += 1;
+                let sym = self.element()?;
+                let span = Span::new(span.start(), sym.span.end());
+                self.repeat(sym, min, max, span)
+            }
+            _ => self.element(),
+        }
+    }
+
+    fn element(&mut self) -> Result<Symbol> {
+        let (token, span) = self.next().expect("the tokens always end with `End`");
+        let kind = match token {
+            Token::Name(name) => SymbolKind::NonTerminal(NonTerminal::untyped(name)),
+            Token::Str(s) | Token::Values(s) => SymbolKind::Terminal(Arc::new(s)),
+            Token::Range(lo, hi) => SymbolKind::char_range(lo, hi, span)?,
+            _ => {
+                return Err(invalid(
+                    span.start(),
+                    span.end(),
+                    "expect a rule name, a string or a value",
+                ))
+            }
+        };
+        Ok(Symbol { kind, span })
+    }
+
+    /// `min*max sym` becomes `<R> ::= sym * min | ... | sym * max`,
+    /// while `min* sym` becomes `<R> ::= sym * min | sym <R>`
+    fn repeat(
+        &mut self,
+        sym: Symbol,
+        min: usize,
+        max: Option<usize>,
+        span: Span,
+    ) -> Result<Symbol> {
+        if max == Some(1) && min == 1 {
+            return Ok(sym);
+        }
+        let nt = NonTerminal::untyped(format!("{}_rep{}", self.current, self.helpers.len()));
+        let times = |n: usize| match n {
+            0 => vec![Symbol {
+                kind: SymbolKind::Terminal(Arc::new(String::new())),
+                span,
+            }],
+            n => vec![sym.clone(); n],
+        };
+        let alts = match max {
+            Some(max) if max < min => return Err(Error::InvalidRepeatRange { span }),
+            Some(max) => (min..=max)
+                .map(|n| Self::alternative(times(n), span))
+                .collect(),
+            None => {
+                let recursive = vec![
+                    sym.clone(),
+                    Symbol {
+                        kind: SymbolKind::NonTerminal(nt.clone()),
+                        span,
+                    },
+                ];
+                vec![
+                    Self::alternative(times(min), span),
+                    Self::alternative(recursive, span),
+                ]
+            }
+        };
+        self.helpers.push(Rule {
+            lhs: nt.clone(),
+            production: WeightedProduction::new(alts, span),
+            span,
+            likes: Vec::new(),
+        });
+        Ok(Symbol {
+            kind: SymbolKind::NonTerminal(nt),
+            span,
+        })
+    }
+
+    fn alternative(symbols: Vec<Symbol>, span: Span) -> Alternative {
+        Alternative {
+            span,
+            weight: 1.0,
+            invoke_limit: Limit::Unlimited,
+            symbols,
+            label: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::error::Error;
+    use crate::generator::{Generator, GeneratorSettings};
+    use crate::grammar::raw::RawGrammar;
+    use crate::span::Span;
+    use rand::SeedableRng;
+
+    const GREETING: &str = "greeting = \"hello\" SP name ; a comment\r\n\
+                            name = 1*3alpha\r\n\
+                            name =/ %x41.42 / 2DIGIT\r\n";
+
+    #[test]
+    fn from_abnf() {
+        let grammar = RawGrammar::from_abnf(GREETING).unwrap();
+        insta::assert_snapshot!(grammar.to_ebnf());
+    }
+
+    #[test]
+    fn generate_from_abnf() {
+        let grammar = RawGrammar::from_abnf(GREETING).unwrap();
+        let gen = Generator::builder()
+            .grammar(grammar.to_checked().unwrap())
+            .settings(GeneratorSettings::builder().separator("").build())
+            .build();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+        for _ in 0..100 {
+            let out = gen.generate("greeting", &mut rng).unwrap();
+            let name = out.strip_prefix("hello ").unwrap();
+            assert!((1..=3).contains(&name.len()));
+            assert!(name.chars().all(|c| c.is_ascii_alphanumeric()));
+        }
+    }
+
+    #[test]
+    fn unsupported_abnf() {
+        let err = RawGrammar::from_abnf("a = ( \"x\" )").unwrap_err();
+        assert_eq!(
+            err,
+            Error::UnsupportedAbnf {
+                span: Span::new(4, 5)
+            }
+        );
+        let err = RawGrammar::from_abnf("a = \"x").unwrap_err();
+        assert!(matches!(err, Error::InvalidAbnf { .. }));
+    }
+}
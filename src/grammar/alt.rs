@@ -4,7 +4,8 @@ use crate::span::Span;
 use rand::Rng;
 use std::hash::{DefaultHasher, Hash, Hasher};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum Limit {
     /// can be invoked any number of times
     Unlimited,
@@ -14,14 +15,75 @@ pub enum Limit {
         /// should be invoked at most `max` times (inclusive)
         max: usize,
     },
+    /// the weight is multiplied by `base^depth`, where `depth` is the number of non-terminals
+    /// expanded on the way from the start symbol, so that a recursive alternative
+    /// becomes less and less likely the deeper it goes
+    Decay { base: f64 },
 }
 
-#[derive(Debug)]
+/// the base of a decay is compared by its bits, it is never NaN once checked by the parser
+impl PartialEq for Limit {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Limit::Unlimited, Limit::Unlimited) => true,
+            (Limit::Limited { min: a, max: b }, Limit::Limited { min: c, max: d }) => {
+                (a, b) == (c, d)
+            }
+            (Limit::Decay { base: a }, Limit::Decay { base: b }) => a.to_bits() == b.to_bits(),
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Limit {}
+
+impl Hash for Limit {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            Limit::Unlimited => {}
+            Limit::Limited { min, max } => (min, max).hash(state),
+            Limit::Decay { base } => base.to_bits().hash(state),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Alternative {
     pub(crate) span: Span,
-    pub(crate) weight: usize,
+    pub(crate) weight: f64,
     pub(crate) invoke_limit: Limit,
     pub(crate) symbols: Vec<Symbol>,
+    /// `@name` after the alternative, unique within its rule
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub(crate) label: Option<String>,
+}
+
+/// Refers to an alternative of a rule, either by its index (counting from 0)
+/// or by its label, e.g. `"add"` for `<E> "+" <E> @add`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AltRef {
+    Index(usize),
+    Label(String),
+}
+
+impl From<usize> for AltRef {
+    fn from(index: usize) -> Self {
+        AltRef::Index(index)
+    }
+}
+
+impl From<&str> for AltRef {
+    fn from(label: &str) -> Self {
+        AltRef::Label(label.to_string())
+    }
+}
+
+impl From<String> for AltRef {
+    fn from(label: String) -> Self {
+        AltRef::Label(label)
+    }
 }
 
 impl Hash for Alternative {
@@ -30,9 +92,50 @@ impl Hash for Alternative {
     }
 }
 
+/// compare everything but the span
+impl PartialEq for Alternative {
+    fn eq(&self, other: &Self) -> bool {
+        self.weight == other.weight
+            && self.invoke_limit == other.invoke_limit
+            && self.symbols == other.symbols
+            && self.label == other.label
+    }
+}
+
 pub type AltId = u64;
 
 impl Alternative {
+    /// the weight, 1 if not given, percentages are kept as is and `off` weighs 0,
+    /// an alternative weighing 0 is valid but never chosen
+    pub fn weight(&self) -> f64 {
+        self.weight
+    }
+
+    pub fn invoke_limit(&self) -> &Limit {
+        &self.invoke_limit
+    }
+
+    pub fn symbols(&self) -> &[Symbol] {
+        &self.symbols
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    /// the name given by `@name`, if any
+    pub fn label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+
+    /// the weight at the given expansion depth, taking the decay into account
+    pub(crate) fn weight_at(&self, depth: usize) -> f64 {
+        match self.invoke_limit {
+            Limit::Decay { base } => self.weight * base.powi(depth.min(i32::MAX as usize) as i32),
+            _ => self.weight,
+        }
+    }
+
     /// returns the non-regex terminals in this alternative
     pub(crate) fn non_re_terminals(&self) -> Vec<&str> {
         self.symbols
@@ -49,23 +152,20 @@ impl Alternative {
     }
 
     pub(crate) fn has_invoke_limits(&self) -> bool {
-        match self.invoke_limit {
-            Limit::Unlimited => false,
-            _ => true,
-        }
+        matches!(self.invoke_limit, Limit::Limited { .. })
     }
 
     /// check if this alternative has exceeded its invoke limit base on the generator state
     pub(crate) fn exceeds_invoke_limit<R: Rng>(&self, state: &State<R>) -> bool {
         match self.invoke_limit {
-            Limit::Unlimited => false,
+            Limit::Unlimited | Limit::Decay { .. } => false,
             Limit::Limited { max, .. } => state.count(self.id()) > max,
         }
     }
 
     pub(crate) fn lose_invoke_limit<R: Rng>(&self, state: &State<R>) -> bool {
         match self.invoke_limit {
-            Limit::Unlimited => false,
+            Limit::Unlimited | Limit::Decay { .. } => false,
             Limit::Limited { min, .. } => state.count(self.id()) < min,
         }
     }
@@ -0,0 +1,243 @@
+use crate::error::{Error, Result};
+use crate::grammar::alt::{Alternative, Limit};
+use crate::grammar::checked::CheckedGrammar;
+use crate::grammar::production::WeightedProduction;
+use crate::grammar::raw::RawGrammar;
+use crate::grammar::rule::Rule;
+use crate::grammar::symbol::{NonTerminal, Symbol, SymbolKind, Ty};
+use crate::regex::Regex;
+use crate::span::Span;
+use std::sync::Arc;
+
+/// Build a grammar in code rather than parsing it from text, e.g.
+///
+/// ```text
+/// GrammarBuilder::new()
+///     .rule("S", |r| r.alt(|a| a.non_terminal("E").terminal(";")))
+///     .rule("E", |r| r.alt(|a| a.regex("[0-9]+")).alt(|a| a.weight(2.0).terminal("0")))
+///     .build()
+/// ```
+///
+/// There is no source text, every span is empty, so the diagnostics of the errors
+/// point at nothing in particular.
+#[derive(Debug, Default)]
+pub struct GrammarBuilder {
+    rules: Vec<Rule>,
+    /// the first error met while building, reported by [`build`](Self::build)
+    error: Option<Error>,
+}
+
+/// the alternatives of a rule, see [`GrammarBuilder::rule`]
+#[derive(Debug, Default)]
+pub struct RuleBuilder {
+    alts: Vec<Alternative>,
+    error: Option<Error>,
+}
+
+/// the symbols, the weight and the invoke limit of an alternative, see [`RuleBuilder::alt`]
+#[derive(Debug)]
+pub struct AltBuilder {
+    weight: f64,
+    invoke_limit: Limit,
+    symbols: Vec<Symbol>,
+    label: Option<String>,
+    error: Option<Error>,
+}
+
+impl GrammarBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// add the untyped rule `<name>`
+    pub fn rule<F>(self, name: &str, f: F) -> Self
+    where
+        F: FnOnce(RuleBuilder) -> RuleBuilder,
+    {
+        self.push(NonTerminal::untyped(name), f)
+    }
+
+    /// add the typed rule `<name: "ty">`
+    pub fn typed_rule<F>(self, name: &str, ty: &str, f: F) -> Self
+    where
+        F: FnOnce(RuleBuilder) -> RuleBuilder,
+    {
+        self.push(NonTerminal::typed(name, Ty::typed(ty)), f)
+    }
+
+    fn push<F>(mut self, lhs: NonTerminal, f: F) -> Self
+    where
+        F: FnOnce(RuleBuilder) -> RuleBuilder,
+    {
+        let rule = f(RuleBuilder::default());
+        self.error = self.error.or(rule.error);
+        self.rules.push(Rule {
+            lhs,
+            production: WeightedProduction::new(rule.alts, Span::default()),
+            span: Span::default(),
+            likes: Vec::new(),
+        });
+        self
+    }
+
+    /// the grammar without any check, e.g. to run the lints of [`RawGrammar`] on it
+    pub fn build_raw(self) -> Result<RawGrammar> {
+        match self.error {
+            Some(e) => Err(e),
+            None => Ok(RawGrammar {
+                rules: self.rules,
+                includes: Vec::new(),
+            }),
+        }
+    }
+
+    /// check the grammar as [`RawGrammar::to_checked`] does
+    pub fn build(self) -> Result<CheckedGrammar> {
+        self.build_raw()?.to_checked()
+    }
+}
+
+impl RuleBuilder {
+    /// add an alternative, weighing 1 and without invoke limits unless set otherwise
+    pub fn alt<F>(mut self, f: F) -> Self
+    where
+        F: FnOnce(AltBuilder) -> AltBuilder,
+    {
+        let alt = f(AltBuilder {
+            weight: 1.0,
+            invoke_limit: Limit::Unlimited,
+            symbols: Vec::new(),
+            label: None,
+            error: None,
+        });
+        self.error = self.error.or(alt.error);
+        self.alts.push(Alternative {
+            span: Span::default(),
+            weight: alt.weight,
+            invoke_limit: alt.invoke_limit,
+            symbols: alt.symbols,
+            label: alt.label,
+        });
+        self
+    }
+}
+
+impl AltBuilder {
+    pub fn weight(mut self, weight: f64) -> Self {
+        self.weight = weight;
+        self
+    }
+
+    /// `{min, max}`, invoke this alternative between `min` and `max` times (inclusive)
+    pub fn limit(mut self, min: usize, max: usize) -> Self {
+        self.invoke_limit = Limit::Limited { min, max };
+        self
+    }
+
+    /// `{decay = base}`, multiply the weight by `base^depth`
+    pub fn decay(mut self, base: f64) -> Self {
+        self.invoke_limit = Limit::Decay { base };
+        self
+    }
+
+    /// `@label`
+    pub fn label<S: Into<String>>(mut self, label: S) -> Self {
+        self.label = Some(label.into());
+        self
+    }
+
+    /// `"text"`
+    pub fn terminal<S: Into<String>>(self, text: S) -> Self {
+        self.symbol(SymbolKind::Terminal(Arc::new(text.into())))
+    }
+
+    /// `<name>`
+    pub fn non_terminal(self, name: &str) -> Self {
+        self.symbol(SymbolKind::NonTerminal(NonTerminal::untyped(name)))
+    }
+
+    /// `<name: "ty">`
+    pub fn typed_non_terminal(self, name: &str, ty: &str) -> Self {
+        self.symbol(SymbolKind::NonTerminal(NonTerminal::typed(
+            name,
+            Ty::typed(ty),
+        )))
+    }
+
+    /// `re("pattern")`, an invalid pattern fails the build with [`Error::InvalidRegex`]
+    pub fn regex(mut self, pattern: &str) -> Self {
+        match Regex::spanned(pattern, 0, 0) {
+            Ok(re) => self.symbol(SymbolKind::Regex(Arc::new(re))),
+            Err(e) => {
+                self.error = self.error.or(Some(e));
+                self
+            }
+        }
+    }
+
+    /// `'start'..'end'`, an empty range fails the build with [`Error::InvalidCharRange`]
+    pub fn char_range(mut self, start: char, end: char) -> Self {
+        match SymbolKind::char_range(start, end, Span::default()) {
+            Ok(kind) => self.symbol(kind),
+            Err(e) => {
+                self.error = self.error.or(Some(e));
+                self
+            }
+        }
+    }
+
+    fn symbol(mut self, kind: SymbolKind) -> Self {
+        self.symbols.push(Symbol {
+            kind,
+            span: Span::default(),
+        });
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::error::Error;
+    use crate::generator::Generator;
+    use crate::grammar::builder::GrammarBuilder;
+    use rand::SeedableRng;
+
+    #[test]
+    fn build() {
+        let grammar = GrammarBuilder::new()
+            .rule("S", |r| {
+                r.alt(|a| a.non_terminal("E").terminal(";"))
+                    .alt(|a| a.typed_non_terminal("E", "int").limit(1, 1))
+            })
+            .rule("E", |r| r.alt(|a| a.regex("[a-z]{2}")))
+            .typed_rule("E", "int", |r| {
+                r.alt(|a| a.char_range('0', '9'))
+                    .alt(|a| a.weight(0.0).terminal("x"))
+            })
+            .build()
+            .unwrap();
+        let gen = Generator::builder().grammar(grammar).build();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+        for _ in 0..20 {
+            let s = gen.generate("S", &mut rng).unwrap();
+            assert!(s.ends_with(" ;") || s.chars().all(|c| c.is_ascii_digit()));
+        }
+    }
+
+    #[test]
+    fn same_checks_as_parsed() {
+        let undefined = GrammarBuilder::new()
+            .rule("S", |r| r.alt(|a| a.non_terminal("E")))
+            .build();
+        assert!(matches!(undefined, Err(Error::UndefinedNonTerminal { .. })));
+        let duplicated = GrammarBuilder::new()
+            .rule("S", |r| r.alt(|a| a.terminal("a")))
+            .rule("S", |r| r.alt(|a| a.terminal("b")))
+            .build();
+        assert!(matches!(duplicated, Err(Error::DuplicatedRules { .. })));
+        let invalid = GrammarBuilder::new()
+            .rule("S", |r| r.alt(|a| a.regex("(")))
+            .build();
+        assert!(matches!(invalid, Err(Error::InvalidRegex { .. })));
+    }
+}
@@ -1,86 +1,939 @@
+use crate::error::{Error, Result};
+use crate::generator::{GeneratorSettings, OutputLen};
+use crate::grammar::alt::{AltRef, Alternative};
 use crate::grammar::production::WeightedProduction;
 use crate::grammar::state::State;
 use crate::grammar::symbol::Ty::Untyped;
-use crate::grammar::symbol::{NonTerminal, SymbolKind, Ty};
-use indexmap::IndexMap;
-use rand::prelude::SliceRandom;
+use crate::grammar::symbol::{Builtin, NonTerminal, SymbolKind, Ty};
+use indexmap::{IndexMap, IndexSet};
 use rand::Rng;
-use std::rc::Rc;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Arc;
 
-#[derive(Debug)]
+/// the most strings a regex is expanded to by [`CheckedGrammar::enumerate`]
+const MAX_ENUMERATED_REGEX: u64 = 1 << 16;
+
+#[derive(Debug, Clone)]
 pub struct CheckedGrammar {
     pub(crate) rules: IndexMap<NonTerminal, WeightedProduction>,
+    /// productions of the names defined by several rules (of different types),
+    /// merging the alternatives of all of them
+    pub(crate) merged: HashMap<Arc<String>, WeightedProduction>,
+    /// all the non-regex terminals, which a regex avoids generating
+    pub(crate) terminals: HashSet<String>,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum LanguageSize {
+    /// the grammar can produce exactly `n` derivations
+    Finite(u64),
+    /// the grammar can produce infinitely many strings
+    Infinite,
+    /// the number of derivations exceeds the given cap
+    AtLeast(u64),
 }
 
+/// The minimum number of terminals a symbol can derive,
+/// along with the height of the smallest derivation tree achieving it.
+///
+/// The height strictly decreases along the cheapest derivation, which guarantees that
+/// always choosing the cheapest alternative terminates.
+pub(crate) type Cost = (usize, usize);
+
 pub enum ReduceOutput {
-    Terminal(Rc<String>),
+    Terminal(Arc<String>),
+    /// generated by `re_bytes("...")`, may not be valid UTF-8
+    Bytes(Vec<u8>),
     NonTerminal {
-        name: Rc<String>,
+        name: Arc<String>,
+        /// index of the chosen alternative in the production
+        alt: usize,
         syms: Vec<SymbolKind>,
     },
 }
 
+/// bytes generated by `re_bytes("...")` as a terminal of a string, if they are valid UTF-8
+pub(crate) fn utf8(bytes: Vec<u8>) -> Result<Arc<String>> {
+    String::from_utf8(bytes)
+        .map(Arc::new)
+        .map_err(|_| Error::InvalidUtf8)
+}
+
 impl CheckedGrammar {
+    pub(crate) fn new(mut rules: IndexMap<NonTerminal, WeightedProduction>) -> Self {
+        rules.values_mut().for_each(|p| p.cache_dist());
+
+        let mut grouped: HashMap<&Arc<String>, Vec<(&NonTerminal, &WeightedProduction)>> =
+            HashMap::new();
+        for (nt, production) in &rules {
+            grouped.entry(&nt.name).or_default().push((nt, production));
+        }
+        let merged = grouped
+            .into_iter()
+            .filter(|(_, group)| group.len() > 1)
+            .map(|(name, mut group)| {
+                // the untyped rule comes first, then the typed ones ordered by type,
+                // so that the merged production does not depend on the order of definition
+                group.sort_by_key(|(nt, _)| nt.ty.ty());
+                let alts = group
+                    .iter()
+                    .flat_map(|(_, p)| p.alts.iter().cloned())
+                    .collect();
+                let mut production = WeightedProduction::new(alts, group[0].1.span);
+                production.cache_dist();
+                (name.clone(), production)
+            })
+            .collect();
+
+        let terminals = rules
+            .values()
+            .flat_map(|p| p.non_re_terminals())
+            .map(str::to_string)
+            .collect();
+
+        CheckedGrammar {
+            rules,
+            merged,
+            terminals,
+        }
+    }
+
+    /// all the non-terminals defined in the grammar, in the order of definition
+    pub fn non_terminals(&self) -> Vec<&NonTerminal> {
+        self.rules.keys().collect()
+    }
+
+    /// Check if both grammars define the same rules: the names, types, weights, invoke limits
+    /// and symbols of the alternatives are compared, but neither the spans nor the order
+    /// of the rules, which does not matter to the generation.
+    pub fn structurally_eq(&self, other: &CheckedGrammar) -> bool {
+        self.rules == other.rules
+    }
+
+    /// check if a non-terminal named `name` (of any type) is defined
+    pub fn contains(&self, name: &str) -> bool {
+        self.rules.keys().any(|k| k.as_str() == name)
+    }
+
+    /// resolve a non-terminal to the rules it may refer to,
+    /// an untyped non-terminal matches the rules of any type with the same name
+    pub(crate) fn resolve(&self, nt: &NonTerminal) -> Vec<(&NonTerminal, &WeightedProduction)> {
+        match nt.ty {
+            Untyped => self
+                .rules
+                .iter()
+                .filter(|(k, _)| k.name == nt.name)
+                .collect(),
+            Ty::Typed(_) => self.rules.get_key_value(nt).into_iter().collect(),
+        }
+    }
+
+    /// Enumerate every distinct string the grammar can produce from `start`,
+    /// terminals are joined with a single space as the [`Generator`](crate::generator::Generator) does.
+    ///
+    /// The expansion is breadth-first, the alternatives turned `off` (of zero weight) are skipped,
+    /// other weights and invoke limits are ignored,
+    /// `decl(<Id>)` and `ref(<Id>)` are expanded as `<Id>`,
+    /// while `ref(: "int")` is expanded as any `<Id>` declared by a `decl(<Id>: "int")`,
+    /// and a regex is expanded to every string it produces, skipping the invalid UTF-8 ones.
+    /// Return [`Error::InfiniteGrammar`] if a recursive rule or a regex producing
+    /// more than 65536 strings is reachable from `start`,
+    /// and [`Error::UnsetBuiltin`] if a builtin is, as no generation sets it.
+    pub fn enumerate<S: Into<String>>(
+        &self,
+        start: S,
+    ) -> Result<impl Iterator<Item = String> + '_> {
+        let start = self.start_symbol(start)?;
+        self.check_finite(&start, &mut IndexSet::new(), &mut HashSet::new())?;
+
+        let mut queue = VecDeque::from([vec![SymbolKind::NonTerminal(start)]]);
+        let mut seen = HashSet::new();
+        Ok(std::iter::from_fn(move || {
+            while let Some(form) = queue.pop_front() {
+                let pos = form.iter().position(|s| {
+                    s.as_non_terminal().is_some()
+                        || matches!(
+                            s,
+                            SymbolKind::CharRange { .. }
+                                | SymbolKind::TypedRef(_)
+                                | SymbolKind::Regex(_)
+                                | SymbolKind::RegexBytes(_)
+                        )
+                });
+                match pos {
+                    // a char range is expanded to every char of it
+                    Some(pos) if matches!(form[pos], SymbolKind::CharRange { .. }) => {
+                        let SymbolKind::CharRange { start, end } = form[pos] else {
+                            unreachable!()
+                        };
+                        for c in start..=end {
+                            let mut expanded = form.clone();
+                            expanded[pos] = SymbolKind::Terminal(Arc::new(c.to_string()));
+                            queue.push_back(expanded);
+                        }
+                    }
+                    // a typed reference is expanded to any non-terminal declared with the type
+                    Some(pos) if matches!(form[pos], SymbolKind::TypedRef(_)) => {
+                        let SymbolKind::TypedRef(ty) = &form[pos] else {
+                            unreachable!()
+                        };
+                        for nt in self.typed_decls(ty).collect::<IndexSet<_>>() {
+                            let mut expanded = form.clone();
+                            expanded[pos] = SymbolKind::NonTerminal(nt.clone());
+                            queue.push_back(expanded);
+                        }
+                    }
+                    // a regex is expanded to every string of its language
+                    Some(pos)
+                        if matches!(
+                            form[pos],
+                            SymbolKind::Regex(_) | SymbolKind::RegexBytes(_)
+                        ) =>
+                    {
+                        let (SymbolKind::Regex(re) | SymbolKind::RegexBytes(re)) = &form[pos]
+                        else {
+                            unreachable!()
+                        };
+                        // the language is small enough, as checked by `check_finite`
+                        let language = re.language(MAX_ENUMERATED_REGEX).unwrap_or_default();
+                        for s in language
+                            .into_iter()
+                            .filter_map(|s| String::from_utf8(s).ok())
+                        {
+                            let mut expanded = form.clone();
+                            expanded[pos] = SymbolKind::Terminal(Arc::new(s));
+                            queue.push_back(expanded);
+                        }
+                    }
+                    Some(pos) => {
+                        let nt = form[pos].as_non_terminal().unwrap();
+                        for (_, production) in self.resolve(nt) {
+                            for alt in production.alts.iter().filter(|a| a.weight() > 0.0) {
+                                let mut expanded = form[..pos].to_vec();
+                                expanded.extend(alt.symbols.iter().map(|s| s.kind.clone()));
+                                expanded.extend(form[pos + 1..].iter().cloned());
+                                queue.push_back(expanded);
+                            }
+                        }
+                    }
+                    None => {
+                        let out = form
+                            .iter()
+                            .filter_map(|s| s.literal())
+                            .collect::<Vec<_>>()
+                            .join(" ");
+                        if seen.insert(out.clone()) {
+                            return Some(out);
+                        }
+                    }
+                }
+            }
+            None
+        }))
+    }
+
+    /// depth-first search for the recursion, regex or builtin reachable from `nt`
+    fn check_finite<'a>(
+        &'a self,
+        nt: &NonTerminal,
+        path: &mut IndexSet<&'a NonTerminal>,
+        finite: &mut HashSet<&'a NonTerminal>,
+    ) -> Result<()> {
+        for (key, production) in self.resolve(nt) {
+            if finite.contains(key) {
+                continue;
+            }
+            if !path.insert(key) {
+                return Err(Error::InfiniteGrammar {
+                    name: key.as_str().to_string(),
+                });
+            }
+            let enabled = production.alts.iter().filter(|a| a.weight() > 0.0);
+            for sym in enabled.flat_map(|a| a.symbols.iter()) {
+                match &sym.kind {
+                    SymbolKind::Terminal(_)
+                    | SymbolKind::Note(_)
+                    | SymbolKind::CharRange { .. } => { /* do nothing */ }
+                    SymbolKind::NonTerminal(next)
+                    | SymbolKind::Decl(next)
+                    | SymbolKind::Ref(next)
+                    | SymbolKind::TypedDecl(next, _) => self.check_finite(next, path, finite)?,
+                    SymbolKind::TypedRef(ty) => {
+                        for next in self.typed_decls(ty) {
+                            self.check_finite(next, path, finite)?;
+                        }
+                    }
+                    SymbolKind::Builtin(builtin) => {
+                        return Err(Error::UnsetBuiltin { builtin: *builtin })
+                    }
+                    SymbolKind::Regex(re) | SymbolKind::RegexBytes(re) => {
+                        if re
+                            .language_size()
+                            .is_some_and(|n| n <= MAX_ENUMERATED_REGEX)
+                        {
+                            continue;
+                        }
+                        return Err(Error::InfiniteGrammar {
+                            name: key.as_str().to_string(),
+                        });
+                    }
+                }
+            }
+            path.pop();
+            finite.insert(key);
+        }
+        Ok(())
+    }
+
+    /// Count the strings the grammar can produce from `start`, up to `max`.
+    ///
+    /// The count is taken over derivations, so a string derived in several ways is counted
+    /// more than once, weights and invoke limits are ignored.
+    /// The language is infinite if a recursive rule or an unbounded regex is reachable from `start`.
+    pub fn language_size<S: Into<String>>(&self, start: S, max: u64) -> Result<LanguageSize> {
+        let start = self.start_symbol(start)?;
+        let size = self.count(&start, &mut IndexSet::new(), &mut HashMap::new());
+        Ok(match size {
+            None => LanguageSize::Infinite,
+            Some(n) if n > max => LanguageSize::AtLeast(max),
+            Some(n) => LanguageSize::Finite(n),
+        })
+    }
+
+    /// count the derivations of `nt`, `None` if there are infinitely many of them
+    fn count<'a>(
+        &'a self,
+        nt: &NonTerminal,
+        path: &mut IndexSet<&'a NonTerminal>,
+        memo: &mut HashMap<&'a NonTerminal, u64>,
+    ) -> Option<u64> {
+        let mut total = 0u64;
+        for (key, production) in self.resolve(nt) {
+            if let Some(n) = memo.get(key) {
+                total = total.saturating_add(*n);
+                continue;
+            }
+            if !path.insert(key) {
+                // reachable recursion
+                return None;
+            }
+            let mut size = 0u64;
+            for alt in &production.alts {
+                let mut product = 1u64;
+                for sym in &alt.symbols {
+                    let n = match &sym.kind {
+                        // a builtin has a single value in a generation
+                        SymbolKind::Terminal(_) | SymbolKind::Note(_) | SymbolKind::Builtin(_) => 1,
+                        SymbolKind::NonTerminal(next)
+                        | SymbolKind::Decl(next)
+                        | SymbolKind::Ref(next)
+                        | SymbolKind::TypedDecl(next, _) => self.count(next, path, memo)?,
+                        // any name declared with the type
+                        SymbolKind::TypedRef(ty) => {
+                            let mut n = 0u64;
+                            for next in self.typed_decls(ty) {
+                                n = n.saturating_add(self.count(next, path, memo)?);
+                            }
+                            n
+                        }
+                        SymbolKind::Regex(re) | SymbolKind::RegexBytes(re) => re.language_size()?,
+                        // surrogates are not chars
+                        SymbolKind::CharRange { start, end } => (*start..=*end).count() as u64,
+                    };
+                    product = product.saturating_mul(n);
+                }
+                size = size.saturating_add(product);
+            }
+            path.pop();
+            memo.insert(key, size);
+            total = total.saturating_add(size);
+        }
+        Some(total)
+    }
+
+    /// Check upfront that `start` can start a generation, i.e. it is defined
+    /// and at least one of its derivations ends up with only terminals,
+    /// otherwise the generation would fail or never terminate.
+    ///
+    /// The invoke limits are not taken into account.
+    pub fn validate_start(&self, start: &str) -> Result<()> {
+        let nt = self.start_symbol(start)?;
+        let costs = self.min_costs();
+        match self.symbol_cost(&SymbolKind::NonTerminal(nt.clone()), &costs) {
+            Some(_) => Ok(()),
+            None => Err(Error::UnproductiveStartSymbol {
+                span: self.require_production(&nt)?.span,
+                name: nt.name.to_string(),
+            }),
+        }
+    }
+
+    /// build the start symbol of a generation, fail if it is not defined
+    pub(crate) fn start_symbol<S: Into<String>>(&self, name: S) -> Result<NonTerminal> {
+        let name = name.into();
+        match self.contains(&name) {
+            true => Ok(NonTerminal::untyped(name)),
+            false => Err(Error::UndefinedStartSymbol { name }),
+        }
+    }
+
+    /// The production a non-terminal reduces with, `None` if it is not defined.
+    ///
+    /// An untyped non-terminal reduces with the alternatives of all the rules of that name.
+    pub fn production(&self, nt: &NonTerminal) -> Option<&WeightedProduction> {
+        match nt.ty {
+            // a wildcard, choose among the alternatives of all the rules with the same name,
+            // e.g. `2 "a"` of `<E: "int">` is twice as likely as `"b"` of `<E: "str">`
+            Untyped => self
+                .merged
+                .get(&nt.name)
+                .or_else(|| self.resolve(nt).first().map(|(_, p)| *p)),
+            // require an exact match
+            Ty::Typed(_) => self.rules.get(nt),
+        }
+    }
+
+    /// the production a non-terminal reduces with, fail if it is not defined
+    pub(crate) fn require_production(&self, nt: &NonTerminal) -> Result<&WeightedProduction> {
+        self.production(nt).ok_or_else(|| Error::UndefinedRule {
+            name: nt.to_string(),
+        })
+    }
+
+    /// The minimum number of terminals each non-terminal can expand to,
+    /// `None` if the non-terminal has no terminating derivation (e.g. part of a trap loop).
+    ///
+    /// A regex counts as a single terminal.
+    pub fn min_length(&self) -> IndexMap<NonTerminal, Option<usize>> {
+        self.min_costs()
+            .into_iter()
+            .map(|(nt, cost)| (nt.clone(), cost.map(|(len, _)| len)))
+            .collect()
+    }
+
+    /// Compute the [`Cost`] of every non-terminal with a fixpoint iteration,
+    /// `None` if the non-terminal has no terminating derivation.
+    pub(crate) fn min_costs(&self) -> IndexMap<&NonTerminal, Option<Cost>> {
+        let mut costs: IndexMap<&NonTerminal, Option<Cost>> =
+            self.rules.keys().map(|k| (k, None)).collect();
+        loop {
+            let mut changed = false;
+            for (nt, production) in &self.rules {
+                let best = production
+                    .alts
+                    .iter()
+                    .filter_map(|alt| self.alt_cost(alt, &costs))
+                    .map(|(len, height)| (len, height + 1))
+                    .min();
+                let improved = match (best, costs[nt]) {
+                    (Some(_), None) => true,
+                    (Some(best), Some(cost)) => best < cost,
+                    (None, _) => false,
+                };
+                if improved {
+                    costs[nt] = best;
+                    changed = true;
+                }
+            }
+            if !changed {
+                return costs;
+            }
+        }
+    }
+
+    /// the cost of an alternative, `None` if any of its symbols can not terminate
+    pub(crate) fn alt_cost(
+        &self,
+        alt: &Alternative,
+        costs: &IndexMap<&NonTerminal, Option<Cost>>,
+    ) -> Option<Cost> {
+        alt.symbols.iter().try_fold((0, 0), |(len, height), sym| {
+            let (l, h) = self.symbol_cost(&sym.kind, costs)?;
+            Some((len + l, height.max(h)))
+        })
+    }
+
+    pub(crate) fn symbol_cost(
+        &self,
+        symbol: &SymbolKind,
+        costs: &IndexMap<&NonTerminal, Option<Cost>>,
+    ) -> Option<Cost> {
+        match symbol {
+            // a regex, a note, a char range, a reference or a builtin always produce a single terminal
+            SymbolKind::Terminal(_)
+            | SymbolKind::Regex(_)
+            | SymbolKind::RegexBytes(_)
+            | SymbolKind::Note(_)
+            | SymbolKind::Ref(_)
+            | SymbolKind::TypedRef(_)
+            | SymbolKind::CharRange { .. }
+            | SymbolKind::Builtin(_) => Some((1, 0)),
+            SymbolKind::NonTerminal(nt) | SymbolKind::Decl(nt) | SymbolKind::TypedDecl(nt, _) => {
+                self.resolve(nt)
+                    .into_iter()
+                    .filter_map(|(k, _)| costs.get(k).copied().flatten())
+                    .min()
+            }
+        }
+    }
+
+    /// the non-terminals declared as `ty` by a `decl(<Id>: "ty")`
+    fn typed_decls<'a>(&'a self, ty: &'a str) -> impl Iterator<Item = &'a NonTerminal> {
+        self.rules
+            .values()
+            .flat_map(|p| p.alts.iter().flat_map(|a| a.symbols.iter()))
+            .filter_map(move |sym| match &sym.kind {
+                SymbolKind::TypedDecl(nt, t) if t.as_str() == ty => Some(nt),
+                _ => None,
+            })
+    }
+
+    /// the terminals a regex must not generate under the given settings
+    fn avoided(&self, settings: &GeneratorSettings) -> &HashSet<String> {
+        static NONE: std::sync::OnceLock<HashSet<String>> = std::sync::OnceLock::new();
+        if settings.avoid_terminals {
+            &self.terminals
+        } else {
+            NONE.get_or_init(HashSet::new)
+        }
+    }
+
     /// '+' --reduce--> '+'
     ///
     /// E   --reduce--> E, remaining: ['+', E]
     /// if E -> E '+' E
-    pub(crate) fn reduce<R: Rng>(&self, symbol: SymbolKind, state: &mut State<R>) -> ReduceOutput {
+    pub(crate) fn reduce<R: Rng>(
+        &self,
+        symbol: SymbolKind,
+        state: &mut State<R>,
+        settings: &GeneratorSettings,
+    ) -> Result<ReduceOutput> {
         match symbol {
-            SymbolKind::Terminal(s) => ReduceOutput::Terminal(s),
+            SymbolKind::Terminal(s) | SymbolKind::Note(s) => Ok(ReduceOutput::Terminal(s)),
             SymbolKind::NonTerminal(s) => {
-                let syms = match s.ty {
-                    Untyped => {
-                        let candidates = self
-                            .rules
-                            .keys()
-                            .filter(|k| k.name == s.name)
-                            .collect::<Vec<_>>();
-                        self.rules
-                            .get(
-                                *candidates
-                                    .choose(state.rng())
-                                    .expect("No candidates available"),
-                            )
-                            .unwrap_or_else(|| panic!("Fail to find rule of {:?}", s))
-                            .choose_by_state(state)
-                    }
-                    Ty::Typed(_) => {
-                        // require an exact match
-                        self.rules
-                            .get(&s)
-                            .unwrap_or_else(|| panic!("Fail to find rule of {:?}", s))
-                            .choose_by_state(state)
-                    }
+                let production = self.require_production(&s)?;
+                let alt = match state.overrides.get(s.as_str()).cloned() {
+                    Some(AltRef::Index(index)) => production
+                        .choose_by_index(index, state)
+                        .ok_or_else(|| Error::InvalidOverride {
+                            name: s.as_str().to_string(),
+                            index,
+                        })?,
+                    Some(AltRef::Label(label)) => production
+                        .choose_by_label(&label, state)
+                        .ok_or_else(|| Error::UnknownLabel {
+                            name: s.as_str().to_string(),
+                            label,
+                        })?,
+                    None => production.choose_by_state(state).ok_or_else(|| {
+                        Error::NoCandidatesAvailable {
+                            name: s.as_str().to_string(),
+                            span: production.span,
+                        }
+                    })?,
                 };
+                let syms = alt.symbols.iter().map(|s| s.kind.clone()).collect();
+                let alt = production
+                    .alts
+                    .iter()
+                    .position(|a| std::ptr::eq(a, alt))
+                    .unwrap();
 
-                ReduceOutput::NonTerminal { name: s.name, syms }
+                Ok(ReduceOutput::NonTerminal {
+                    name: s.name,
+                    alt,
+                    syms,
+                })
             }
             SymbolKind::Regex(re) => {
-                let terminals = self
-                    .rules
-                    .values()
-                    .flat_map(|r| r.non_re_terminals())
-                    .collect::<Vec<_>>();
-                let s = re.generate(state.rng(), terminals.as_slice());
-                ReduceOutput::Terminal(Rc::new(s))
+                let (rng, stats) = state.rng_and_repetitions();
+                let s = re.generate(rng, self.avoided(settings), settings, stats)?;
+                Ok(ReduceOutput::Terminal(Arc::new(s)))
+            }
+            SymbolKind::RegexBytes(re) => {
+                let (rng, stats) = state.rng_and_repetitions();
+                let bytes = re.generate_bytes(rng, self.avoided(settings), settings, stats)?;
+                Ok(ReduceOutput::Bytes(bytes))
+            }
+            SymbolKind::CharRange { start, end } => {
+                let c = state.rng().gen_range(start..=end);
+                Ok(ReduceOutput::Terminal(Arc::new(c.to_string())))
+            }
+            SymbolKind::Decl(nt) => {
+                let name = self.derive(SymbolKind::NonTerminal(nt.clone()), state, settings)?;
+                state.declare(nt, name.clone());
+                Ok(ReduceOutput::Terminal(name))
+            }
+            SymbolKind::Ref(nt) => match state.lookup(&nt) {
+                Some(name) => Ok(ReduceOutput::Terminal(name)),
+                None => Err(Error::UndeclaredReference {
+                    name: nt.to_string(),
+                }),
+            },
+            SymbolKind::TypedDecl(nt, ty) => {
+                let name = self.derive(SymbolKind::NonTerminal(nt.clone()), state, settings)?;
+                state.declare(nt, name.clone());
+                state.declare_typed(ty, name.clone());
+                Ok(ReduceOutput::Terminal(name))
+            }
+            SymbolKind::TypedRef(ty) => match state.lookup_typed(&ty) {
+                Some(name) => Ok(ReduceOutput::Terminal(name)),
+                None => Err(Error::UndeclaredReference {
+                    name: format!(": \"{}\"", ty.as_str()),
+                }),
+            },
+            SymbolKind::Builtin(b) => {
+                let value = match b {
+                    Builtin::Index => state.index.map(|i| i.to_string()),
+                    Builtin::Seed => state.seed.map(|s| s.to_string()),
+                };
+                value
+                    .map(|v| ReduceOutput::Terminal(Arc::new(v)))
+                    .ok_or(Error::UnsetBuiltin { builtin: b })
             }
         }
     }
+
+    /// Reduce `symbol` until only terminals remain, joined with the separator.
+    ///
+    /// The steps count towards those of the generation, and the result ends up in its output,
+    /// so the limits of the settings apply as in the generation.
+    fn derive<R: Rng>(
+        &self,
+        symbol: SymbolKind,
+        state: &mut State<R>,
+        settings: &GeneratorSettings,
+    ) -> Result<Arc<String>> {
+        let mut buf = Vec::new();
+        let mut len = OutputLen::default();
+        // the symbol is derived in place, at the depth of the caller
+        let base = state.depth;
+        // the stack is reversed, the next symbol to reduce is at the back
+        let mut stack = vec![(symbol, base)];
+        while let Some((symbol, depth)) = stack.pop() {
+            state.depth = depth;
+            state.progress.step(settings)?;
+            let s = match self.reduce(symbol, state, settings)? {
+                ReduceOutput::Terminal(s) => s,
+                ReduceOutput::Bytes(bytes) => utf8(bytes)?,
+                ReduceOutput::NonTerminal { syms, .. } => {
+                    stack.extend(syms.into_iter().rev().map(|sym| (sym, depth + 1)));
+                    continue;
+                }
+            };
+            len.push(settings, s.chars().count())?;
+            buf.push(s);
+        }
+        state.depth = base;
+        let out = buf
+            .iter()
+            .map(|s| s.as_str())
+            .collect::<Vec<_>>()
+            .join(&settings.separator);
+        Ok(Arc::new(out))
+    }
 }
 
 #[cfg(test)]
 mod test {
+    use crate::error::Error;
+    use crate::grammar::checked::LanguageSize;
     use crate::grammar::raw::RawGrammar;
+    use rand::SeedableRng;
 
     #[test]
     fn it_can_merge() {
         let text = r#"
-            <E> ::= <E: "int"> "+" <E: "int"> ;
-            <E> ::= <E: "str"> "+" <E: "str"> ;
+            <E> ::= <E: "int"> "+" <E: "int">
+                  | <E: "str"> "+" <E: "str"> ;
+            <E: "int"> ::= <E: "int"> "+" <E: "int"> ;
             <E: "str"> ::= <E: "str"> "+" <E: "str"> ;
         "#;
         let grammar = RawGrammar::parse(text).unwrap();
         assert!(grammar.to_checked().is_ok());
     }
+
+    #[test]
+    fn merge_untyped() {
+        let a = r#"
+            <S> ::= <E> ;
+            <E: "int"> ::= "1" | "2" ;
+            <E: "str"> ::= "a" ;
+        "#;
+        let b = r#"
+            <E: "str"> ::= "a" ;
+            <S> ::= <E> ;
+            <E: "int"> ::= "1" | "2" ;
+        "#;
+        let a = RawGrammar::parse(a).unwrap().to_checked().unwrap();
+        let b = RawGrammar::parse(b).unwrap().to_checked().unwrap();
+        let alts = |g: &super::CheckedGrammar| {
+            g.merged.values().next().unwrap().alts[..]
+                .iter()
+                .flat_map(|a| a.non_re_terminals())
+                .map(str::to_string)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(alts(&a), vec!["1", "2", "a"]);
+        assert_eq!(alts(&a), alts(&b));
+    }
+
+    #[test]
+    fn accessors() {
+        use crate::grammar::alt::Limit;
+        use crate::grammar::symbol::{NonTerminal, SymbolKind};
+
+        let text = r#"
+            <S> ::= 2 "a" <E> {1, 3} | "b" ;
+            <E: "int"> ::= "1" ;
+        "#;
+        let grammar = RawGrammar::parse(text).unwrap().to_checked().unwrap();
+        let production = grammar.production(&NonTerminal::untyped("S")).unwrap();
+        let alts = production.alternatives();
+        assert_eq!(alts.len(), 2);
+        assert_eq!((alts[0].weight(), alts[1].weight()), (2.0, 1.0));
+        assert!(matches!(
+            alts[0].invoke_limit(),
+            Limit::Limited { min: 1, max: 3 }
+        ));
+        assert!(matches!(alts[1].invoke_limit(), Limit::Unlimited));
+        let symbols = alts[0].symbols();
+        assert_eq!(symbols[0].kind().literal(), Some("a"));
+        assert!(matches!(symbols[1].kind(), SymbolKind::NonTerminal(nt) if nt.as_str() == "E"));
+        assert_eq!(
+            &text[symbols[0].span().start()..symbols[0].span().end()],
+            "\"a\""
+        );
+
+        let int = NonTerminal::typed("E", "int".to_string().into());
+        assert!(grammar.production(&int).is_some());
+        assert!(grammar.production(&NonTerminal::untyped("E")).is_some());
+        assert!(grammar.production(&NonTerminal::untyped("X")).is_none());
+    }
+
+    #[test]
+    fn validate_start() {
+        let text = r#"
+            <S> ::= <A> | "s" ;
+            <A> ::= <A> "a" ;
+            <B: "int"> ::= <A> ;
+            <B: "str"> ::= "b" ;
+        "#;
+        let grammar = RawGrammar::parse(text).unwrap().to_checked().unwrap();
+        assert_eq!(grammar.validate_start("S"), Ok(()));
+        // any type of an untyped start symbol may terminate
+        assert_eq!(grammar.validate_start("B"), Ok(()));
+        assert!(matches!(
+            grammar.validate_start("A"),
+            Err(Error::UnproductiveStartSymbol { name, .. }) if name == "A"
+        ));
+        assert!(matches!(
+            grammar.validate_start("X"),
+            Err(Error::UndefinedStartSymbol { .. })
+        ));
+    }
+
+    #[test]
+    fn structurally_eq() {
+        let checked = |text: &str| RawGrammar::parse(text).unwrap().to_checked().unwrap();
+        let grammar = checked(r#"<S> ::= <E> ";" ; <E> ::= "1" | 2 re("[0-9]+") {0, 3} ;"#);
+        let reordered = checked(
+            r#"
+            <E> ::= "1"
+                  | 2 re("[0-9]+") {0, 3} ;
+            <S> ::= <E> ";" ;
+        "#,
+        );
+        assert!(grammar.structurally_eq(&reordered));
+        let built = crate::grammar::builder::GrammarBuilder::new()
+            .rule("S", |r| r.alt(|a| a.non_terminal("E").terminal(";")))
+            .rule("E", |r| {
+                r.alt(|a| a.terminal("1"))
+                    .alt(|a| a.weight(2.0).regex("[0-9]+").limit(0, 3))
+            })
+            .build()
+            .unwrap();
+        assert!(grammar.structurally_eq(&built));
+
+        for other in [
+            r#"<S> ::= <E> ";" ; <E> ::= "1" | 3 re("[0-9]+") {0, 3} ;"#,
+            r#"<S> ::= <E> ";" ; <E> ::= "1" | 2 re("[0-9]+") {0, 4} ;"#,
+            r#"<S> ::= <E> ";" ; <E> ::= "1" | 2 re("[0-9]*") {0, 3} ;"#,
+            r#"<S> ::= <E> ";" ; <E: "int"> ::= "1" | 2 re("[0-9]+") {0, 3} ;"#,
+        ] {
+            assert!(!grammar.structurally_eq(&checked(other)), "{}", other);
+        }
+    }
+
+    #[test]
+    fn untyped_is_wildcard() {
+        let text = r#"
+            <S> ::= <E> ;
+            <E: "int"> ::= 3 "1" ;
+            <E: "str"> ::= "a" ;
+        "#;
+        let gen = text.parse::<crate::generator::Generator>().unwrap();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+        let ones = (0..4000)
+            .filter(|_| gen.generate("S", &mut rng).unwrap() == "1")
+            .count();
+        // the alternatives of both types are pooled, keeping their weights
+        assert!((2800..3200).contains(&ones), "{}", ones);
+    }
+
+    #[test]
+    fn min_length() {
+        let text = r#"
+            <S> ::= <E> "+" <E> | <T> ;
+            <E> ::= <E> <E> | re("[0-9]") | "" "" "" ;
+            <T> ::= <U> ;
+            <U> ::= <T> ;
+        "#;
+        let grammar = RawGrammar::parse(text).unwrap().to_checked().unwrap();
+        let lengths = grammar
+            .min_length()
+            .into_iter()
+            .map(|(nt, len)| (nt.as_str().to_string(), len))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lengths,
+            vec![
+                ("S".to_string(), Some(3)),
+                ("E".to_string(), Some(1)),
+                ("T".to_string(), None),
+                ("U".to_string(), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn cache_dist() {
+        let text = r#"
+            <S> ::= 2 "a" | "b" ;
+            <E> ::= "a" {1, 3} | "b" ;
+        "#;
+        let grammar = RawGrammar::parse(text).unwrap().to_checked().unwrap();
+        let dists = grammar
+            .rules
+            .values()
+            .map(|p| p.dist.is_some())
+            .collect::<Vec<_>>();
+        assert_eq!(dists, vec![true, false]);
+    }
+
+    #[test]
+    fn enumerate() {
+        let text = r#"
+            <S> ::= "a" | "b" ;
+        "#;
+        let grammar = RawGrammar::parse(text).unwrap().to_checked().unwrap();
+        let out = grammar.enumerate("S").unwrap().collect::<Vec<_>>();
+        assert_eq!(out, vec!["a", "b"]);
+    }
+
+    #[test]
+    fn enumerate_distinct() {
+        let text = r#"
+            <S> ::= <A> <A> | "x" "x" ;
+            <A> ::= "x" | "y" ;
+        "#;
+        let grammar = RawGrammar::parse(text).unwrap().to_checked().unwrap();
+        let out = grammar.enumerate("S").unwrap().collect::<Vec<_>>();
+        assert_eq!(out, vec!["x x", "x y", "y x", "y y"]);
+    }
+
+    #[test]
+    fn enumerate_typed_ref() {
+        let text = r#"
+            <S> ::= decl(<A>: "v") decl(<B>: "v") "=" ref(: "v") ;
+            <A> ::= "a" ;
+            <B> ::= "b" ;
+            <T> ::= ref(: "r") ;
+            <D> ::= decl(<R>: "r") ;
+            <R> ::= re("[a-z]+") ;
+        "#;
+        let grammar = RawGrammar::parse(text).unwrap().to_checked().unwrap();
+        let out = grammar.enumerate("S").unwrap().collect::<Vec<_>>();
+        assert_eq!(out, vec!["a b = a", "a b = b"]);
+        // the names of a typed reference are infinitely many
+        assert!(matches!(
+            grammar.enumerate("T").err(),
+            Some(Error::InfiniteGrammar { .. })
+        ));
+    }
+
+    #[test]
+    fn language_size() {
+        let text = r#"
+            <S> ::= <A> <A> | "x" ;
+            <A> ::= "a" | "b" | re("[0-2]") ;
+            <R> ::= <A> | <R> <A> ;
+        "#;
+        let grammar = RawGrammar::parse(text).unwrap().to_checked().unwrap();
+        assert_eq!(
+            grammar.language_size("S", 100).unwrap(),
+            LanguageSize::Finite(26)
+        );
+        assert_eq!(
+            grammar.language_size("S", 10).unwrap(),
+            LanguageSize::AtLeast(10)
+        );
+        assert_eq!(
+            grammar.language_size("R", 100).unwrap(),
+            LanguageSize::Infinite
+        );
+    }
+
+    #[test]
+    fn enumerate_infinite() {
+        let text = r#"
+            <S> ::= <A> | <S> <A> ;
+            <A> ::= "a" ;
+            <R> ::= re("[a-z]+") ;
+        "#;
+        let grammar = RawGrammar::parse(text).unwrap().to_checked().unwrap();
+        assert!(matches!(
+            grammar.enumerate("S").err(),
+            Some(Error::InfiniteGrammar { .. })
+        ));
+        assert!(matches!(
+            grammar.enumerate("R").err(),
+            Some(Error::InfiniteGrammar { .. })
+        ));
+    }
+
+    #[test]
+    fn enumerate_regex() {
+        let text = r#"
+            <S> ::= "b" re("[01]") | re("x{1,2}") ;
+        "#;
+        let grammar = RawGrammar::parse(text).unwrap().to_checked().unwrap();
+        let out = grammar.enumerate("S").unwrap().collect::<Vec<_>>();
+        assert_eq!(out, vec!["b 0", "b 1", "x", "xx"]);
+    }
+
+    #[test]
+    fn enumerate_off() {
+        let text = r#"
+            <S> ::= "a" | <S> "a" off | re("[a-z]+") off ;
+        "#;
+        let grammar = RawGrammar::parse(text).unwrap().to_checked().unwrap();
+        let out = grammar.enumerate("S").unwrap().collect::<Vec<_>>();
+        assert_eq!(out, vec!["a"]);
+    }
+
+    #[test]
+    fn enumerate_builtin() {
+        let text = r#"
+            <S> ::= "case" <N> ;
+            <N> ::= $INDEX | "0" ;
+            <Q> ::= "$INDEX" ;
+        "#;
+        let grammar = RawGrammar::parse(text).unwrap().to_checked().unwrap();
+        let err = grammar.enumerate("S").err().unwrap();
+        assert!(matches!(err, Error::UnsetBuiltin { .. }));
+        assert_eq!(err.to_string(), "$INDEX is not set for this generation");
+        let out = grammar.enumerate("Q").unwrap().collect::<Vec<_>>();
+        assert_eq!(out, vec!["$INDEX"]);
+    }
 }
@@ -0,0 +1,100 @@
+use crate::grammar::alt::{Alternative, Limit};
+use crate::grammar::raw::RawGrammar;
+use crate::grammar::symbol::{NonTerminal, SymbolKind, Ty};
+use std::fmt::Write;
+
+impl RawGrammar {
+    /// Render the grammar in ISO EBNF, e.g. `S = "hello" , S | "world" ;`.
+    ///
+    /// This is a best-effort, lossy export since EBNF has no counterpart for most extensions:
+    /// - types, weights, invoke limits, `decl`, `ref` and `note` are rendered as comments
+    /// - invoke limits are *not* repetitions, so they are not translated to `{ ... }`
+    /// - regexes are rendered as special sequences, e.g. `? [a-z]+ ?`
+    pub fn to_ebnf(&self) -> String {
+        let mut buf = String::new();
+        for rule in &self.rules {
+            let alts = rule
+                .rhs()
+                .iter()
+                .map(Self::ebnf_alternative)
+                .collect::<Vec<_>>();
+            writeln!(
+                buf,
+                "{} = {} ;",
+                Self::ebnf_non_terminal(&rule.lhs),
+                alts.join(" | ")
+            )
+            .unwrap();
+        }
+        buf
+    }
+
+    fn ebnf_alternative(alt: &Alternative) -> String {
+        let mut comments = Vec::new();
+        if alt.weight != 1.0 {
+            comments.push(format!("weight {}", alt.weight));
+        }
+        match alt.invoke_limit {
+            Limit::Limited { min, max } => {
+                comments.push(format!("invoked {} to {} times", min, max))
+            }
+            Limit::Decay { base } => comments.push(format!("decaying by {} per depth", base)),
+            Limit::Unlimited => { /* do nothing */ }
+        }
+        let symbols = alt
+            .symbols
+            .iter()
+            .map(|sym| match &sym.kind {
+                SymbolKind::Terminal(s) => Self::ebnf_terminal(s),
+                SymbolKind::NonTerminal(nt) => Self::ebnf_non_terminal(nt),
+                SymbolKind::Regex(re) => format!("? {} ?", re.as_str()),
+                SymbolKind::RegexBytes(re) => format!("? {} (bytes) ?", re.as_str()),
+                SymbolKind::CharRange { start, end } => format!("? '{}'..'{}' ?", start, end),
+                SymbolKind::Note(s) => format!("{} (* note *)", Self::ebnf_terminal(s)),
+                SymbolKind::Decl(nt) => format!("{} (* decl *)", Self::ebnf_non_terminal(nt)),
+                SymbolKind::Ref(nt) => format!("{} (* ref *)", Self::ebnf_non_terminal(nt)),
+                SymbolKind::TypedDecl(nt, ty) => {
+                    format!("{} (* decl: {:?} *)", Self::ebnf_non_terminal(nt), ty)
+                }
+                SymbolKind::TypedRef(ty) => format!("? ref: {:?} ?", ty),
+                SymbolKind::Builtin(b) => format!("? {} ?", b),
+            })
+            .collect::<Vec<_>>()
+            .join(" , ");
+        match comments.is_empty() {
+            true => symbols,
+            false => format!("{} (* {} *)", symbols, comments.join(", ")),
+        }
+    }
+
+    fn ebnf_non_terminal(nt: &NonTerminal) -> String {
+        match &nt.ty {
+            Ty::Untyped => nt.as_str().to_string(),
+            Ty::Typed(ty) => format!("{} (* type {} *)", nt.as_str(), ty),
+        }
+    }
+
+    /// EBNF has no escapes, prefer single quotes if the terminal contains a double quote
+    fn ebnf_terminal(s: &str) -> String {
+        match s.contains('"') && !s.contains('\'') {
+            true => format!("'{}'", s),
+            false => format!("\"{}\"", s),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::grammar::raw::RawGrammar;
+
+    #[test]
+    fn to_ebnf() {
+        let text = r#"
+            <S> ::= "hello" <S> | 2 "world" {1, 3} | re("[a-z]+") ;
+            <E: "int"> ::= decl(<Id>) "=" ref(<Id>) note("\"") ;
+            <Id> ::= "x" ;
+        "#;
+        let grammar = RawGrammar::parse(text).unwrap();
+        insta::assert_snapshot!(grammar.to_ebnf());
+    }
+}
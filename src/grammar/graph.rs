@@ -1,8 +1,13 @@
 use crate::error::Error;
+use crate::grammar::alt::Limit;
 use crate::grammar::rule::Rule;
+use crate::grammar::symbol::SymbolKind;
 use petgraph::graph::{DiGraph, NodeIndex};
 use petgraph::prelude::Dfs;
+use petgraph::visit::Reversed;
 use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fmt::Write;
 
 pub struct GrammarGraph<'rule> {
     pub(crate) rules: &'rule Vec<Rule>,
@@ -10,7 +15,70 @@ pub struct GrammarGraph<'rule> {
     pub(crate) nodes: HashMap<String, NodeIndex>,
 }
 
+/// Size metrics of a grammar, see [`GrammarGraph::stats`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GrammarStats {
+    /// number of rules, the rules of the same name but different types counted apart
+    pub rules: usize,
+    /// number of alternatives of all the rules
+    pub alternatives: usize,
+    /// number of non-terminals referring to themselves, directly or not
+    pub recursive: usize,
+    /// number of distinct names of non-terminals
+    pub non_terminals: usize,
+    /// number of distinct terminals, excluding the regexes and the notes
+    pub terminals: usize,
+    /// number of regexes
+    pub regexes: usize,
+    /// whether the grammar produces finitely many strings,
+    /// i.e. it is not recursive and none of its regexes repeats unboundedly
+    pub finite: bool,
+}
+
+impl GrammarStats {
+    pub fn alternatives_per_rule(&self) -> f64 {
+        match self.rules {
+            0 => 0.0,
+            n => self.alternatives as f64 / n as f64,
+        }
+    }
+}
+
+impl fmt::Display for GrammarStats {
+    /// one metric per line, e.g. `rules: 4`, so that the stats of two versions can be diffed
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "rules: {}", self.rules)?;
+        writeln!(f, "alternatives: {}", self.alternatives)?;
+        writeln!(
+            f,
+            "alternatives per rule: {:.2}",
+            self.alternatives_per_rule()
+        )?;
+        writeln!(f, "recursive rules: {}", self.recursive)?;
+        writeln!(f, "non-terminals: {}", self.non_terminals)?;
+        writeln!(f, "terminals: {}", self.terminals)?;
+        writeln!(f, "regexes: {}", self.regexes)?;
+        writeln!(f, "finite: {}", self.finite)
+    }
+}
+
 impl<'rule> GrammarGraph<'rule> {
+    /// the names of the non-terminals reachable from `start`, itself included
+    fn reachable(&self, start: &str) -> crate::error::Result<HashSet<&str>> {
+        let start = self
+            .nodes
+            .get(start)
+            .ok_or_else(|| Error::UndefinedStartSymbol {
+                name: start.to_string(),
+            })?;
+        let mut dfs = Dfs::new(&self.graph, *start);
+        let mut reachable = HashSet::new();
+        while let Some(nx) = dfs.next(&self.graph) {
+            reachable.insert(self.graph[nx].as_str());
+        }
+        Ok(reachable)
+    }
+
     pub fn check_unused<S: AsRef<str>>(&self, start: S) -> crate::error::Result<&Self> {
         let all_nts = self
             .nodes
@@ -18,17 +86,7 @@ impl<'rule> GrammarGraph<'rule> {
             .map(|s| s.as_str())
             .collect::<HashSet<_>>();
         // find the reachable nodes for a given start symbol
-        let start = self
-            .nodes
-            .get(start.as_ref())
-            .expect("The start symbol does not exist");
-
-        let mut dfs = Dfs::new(&self.graph, *start);
-        let mut reachable = HashSet::new();
-        while let Some(nx) = dfs.next(&self.graph) {
-            let name = &self.graph[nx];
-            reachable.insert(name.as_str());
-        }
+        let reachable = self.reachable(start.as_ref())?;
         let unreachable = all_nts.difference(&reachable).collect::<HashSet<_>>();
         // find the unreachable spans
         if !unreachable.is_empty() {
@@ -43,12 +101,146 @@ impl<'rule> GrammarGraph<'rule> {
         Ok(self)
     }
 
+    /// find the non-terminals that can derive the empty string,
+    /// i.e. having an alternative of which every symbol is nullable
+    pub fn nullable_nonterminals(&self) -> HashSet<&str> {
+        // a typed reference reuses the names of any declaration of its type
+        let mut typed_decls: HashMap<&str, Vec<&str>> = HashMap::new();
+        for sym in self
+            .rules
+            .iter()
+            .flat_map(|r| r.rhs().iter().flat_map(|a| &a.symbols))
+        {
+            if let SymbolKind::TypedDecl(nt, ty) = &sym.kind {
+                typed_decls
+                    .entry(ty.as_str())
+                    .or_default()
+                    .push(nt.as_str());
+            }
+        }
+        self.fixpoint(|sym, nullable| match sym {
+            SymbolKind::Terminal(s) | SymbolKind::Note(s) => s.is_empty(),
+            SymbolKind::Regex(re) | SymbolKind::RegexBytes(re) => re.is_nullable(),
+            SymbolKind::CharRange { .. } | SymbolKind::Builtin(_) => false,
+            SymbolKind::NonTerminal(nt)
+            | SymbolKind::Decl(nt)
+            | SymbolKind::Ref(nt)
+            | SymbolKind::TypedDecl(nt, _) => nullable.contains(nt.as_str()),
+            SymbolKind::TypedRef(ty) => typed_decls
+                .get(ty.as_str())
+                .is_some_and(|nts| nts.iter().any(|nt| nullable.contains(nt))),
+        })
+    }
+
+    /// find the non-terminals that can derive a string made of terminals only,
+    /// i.e. having an alternative of which every symbol is productive
+    pub fn productive_nonterminals(&self) -> HashSet<&str> {
+        self.fixpoint(|sym, productive| match sym {
+            SymbolKind::Terminal(_)
+            | SymbolKind::Regex(_)
+            | SymbolKind::RegexBytes(_)
+            | SymbolKind::Note(_)
+            | SymbolKind::Ref(_)
+            | SymbolKind::TypedRef(_)
+            | SymbolKind::CharRange { .. }
+            | SymbolKind::Builtin(_) => true,
+            SymbolKind::NonTerminal(nt) | SymbolKind::Decl(nt) | SymbolKind::TypedDecl(nt, _) => {
+                productive.contains(nt.as_str())
+            }
+        })
+    }
+
+    /// the smallest set of non-terminals having an alternative of which every symbol
+    /// satisfies `pred` with respect to the set
+    fn fixpoint<F>(&self, pred: F) -> HashSet<&str>
+    where
+        F: Fn(&SymbolKind, &HashSet<&str>) -> bool,
+    {
+        let mut set = HashSet::new();
+        // iterate until a fixpoint is reached
+        loop {
+            let mut changed = false;
+            for rule in self.rules {
+                if set.contains(rule.lhs.as_str()) {
+                    continue;
+                }
+                let satisfied = rule
+                    .rhs()
+                    .iter()
+                    .any(|alt| alt.symbols.iter().all(|sym| pred(&sym.kind, &set)));
+                if satisfied {
+                    set.insert(rule.lhs.as_str());
+                    changed = true;
+                }
+            }
+            if !changed {
+                return set;
+            }
+        }
+    }
+
+    pub fn check_nullable<S: AsRef<str>>(&self, start: S) -> crate::error::Result<&Self> {
+        let start = start.as_ref();
+        if self.nullable_nonterminals().contains(start) {
+            let spans = self
+                .rules
+                .iter()
+                .filter(|rule| rule.lhs.as_str() == start)
+                .map(|rule| rule.span)
+                .collect::<Vec<_>>();
+            return Err(Error::NullableStartSymbol {
+                name: start.to_string(),
+                spans,
+            });
+        }
+        Ok(self)
+    }
+
+    /// Like [`RawGrammar::check_references`](crate::grammar::raw::RawGrammar::check_references),
+    /// but only a `decl` reachable from `start` counts, as the other rules are never expanded
+    /// in a generation from `start`, e.g. the `decl(<Id>)` of an unused rule declares nothing.
+    pub fn check_references<S: AsRef<str>>(&self, start: S) -> crate::error::Result<&Self> {
+        let reachable = self.reachable(start.as_ref())?;
+        let symbols = || {
+            self.rules
+                .iter()
+                .filter(|rule| reachable.contains(rule.lhs.as_str()))
+                .flat_map(|rule| rule.rhs().iter().flat_map(|alt| &alt.symbols))
+        };
+        let declared = symbols()
+            .filter_map(|sym| match &sym.kind {
+                SymbolKind::Decl(nt) | SymbolKind::TypedDecl(nt, _) => Some(nt),
+                _ => None,
+            })
+            .collect::<HashSet<_>>();
+        let declared_types = symbols()
+            .filter_map(|sym| match &sym.kind {
+                SymbolKind::TypedDecl(_, ty) => Some(ty.as_str()),
+                _ => None,
+            })
+            .collect::<HashSet<_>>();
+        for sym in symbols() {
+            match &sym.kind {
+                SymbolKind::Ref(nt) if !declared.contains(nt) => {
+                    return Err(Error::NoDeclaredVariable { span: sym.span });
+                }
+                SymbolKind::TypedRef(ty) if !declared_types.contains(ty.as_str()) => {
+                    return Err(Error::NoDeclaredVariable { span: sym.span });
+                }
+                _ => { /* do nothing */ }
+            }
+        }
+        Ok(self)
+    }
+
     pub fn check_trap_loop(&self) -> crate::error::Result<&Self> {
+        let productive = self.productive_nonterminals();
         let sccs = petgraph::algo::tarjan_scc(&self.graph);
         for scc in sccs {
-            if self.is_trap_loop(&scc) {
+            if self.is_trap_loop(&scc, &productive) {
                 let spans = scc
                     .iter()
+                    .filter(|nx| !productive.contains(self.graph[**nx].as_str()))
                     .map(|nx| {
                         self.rules
                             .iter()
@@ -63,22 +255,230 @@ impl<'rule> GrammarGraph<'rule> {
         Ok(self)
     }
 
-    fn is_trap_loop(&self, scc: &Vec<NodeIndex>) -> bool {
-        let produce_t = scc.iter().map(|nx| self.graph[*nx].as_str()).any(|name| {
-            // check if rule produce a terminal
+    /// The non-terminals in dependency order, each of them after the ones it refers to,
+    /// e.g. `<S> ::= <E> ";" ; <E> ::= "1" ;` gives `["E", "S"]`.
+    ///
+    /// There is no such order if the grammar is recursive, the cycles are returned instead,
+    /// i.e. the strongly connected components with a loop, a rule referring to itself included.
+    pub fn topo_order(&self) -> Result<Vec<&'rule str>, Vec<Vec<&'rule str>>> {
+        // the rules of the same name but different types share a single node
+        let sccs = petgraph::algo::tarjan_scc(&self.graph)
+            .into_iter()
+            .map(|scc| {
+                scc.into_iter()
+                    .filter(|nx| self.nodes[&self.graph[*nx]] == *nx)
+                    .collect::<Vec<_>>()
+            })
+            .filter(|scc| !scc.is_empty())
+            .collect::<Vec<_>>();
+        // borrow the names from the grammar rather than from the graph
+        let names = self
+            .rules
+            .iter()
+            .map(|rule| (rule.lhs.as_str(), rule.lhs.as_str()))
+            .collect::<HashMap<_, _>>();
+        let name = |nx: &NodeIndex| names[self.graph[*nx].as_str()];
+        let cycles = sccs
+            .iter()
+            .filter(|scc| scc.len() > 1 || self.graph.contains_edge(scc[0], scc[0]))
+            .map(|scc| scc.iter().map(name).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        match cycles.is_empty() {
+            // tarjan's algorithm yields the components in reverse topological order
+            true => Ok(sccs.iter().map(|scc| name(&scc[0])).collect()),
+            false => Err(cycles),
+        }
+    }
+
+    /// The invoke limits are counted over a whole generation, so an alternative which should be
+    /// invoked at least `min` times can never be satisfied if its rule is expanded fewer times
+    /// along any derivation, e.g. `<S> ::= <A> ; <A> ::= "a" {5} ;` expands `<A>` only once.
+    ///
+    /// A rule reachable from a recursive one can be expanded any number of times.
+    /// Reported as a warning, as a [`State`](crate::grammar::state::State) shared across
+    /// generations keeps counting, so the limit may still be satisfied over a batch.
+    pub fn check_invoke_limits(&self) -> crate::error::Result<&Self> {
+        let recursive = petgraph::algo::tarjan_scc(&self.graph)
+            .into_iter()
+            .filter(|scc| scc.len() > 1 || self.graph.contains_edge(scc[0], scc[0]))
+            .flatten();
+        let mut unbounded = HashSet::new();
+        for nx in recursive {
+            let mut dfs = Dfs::new(&self.graph, nx);
+            while let Some(nx) = dfs.next(&self.graph) {
+                unbounded.insert(nx);
+            }
+        }
+        for rule in self.rules {
+            let target = self.nodes[rule.lhs.as_str()];
+            if unbounded.contains(&target) {
+                continue;
+            }
+            let mut max = None;
+            for alt in rule.rhs() {
+                let Limit::Limited { min, .. } = alt.invoke_limit else {
+                    continue;
+                };
+                let max = *max.get_or_insert_with(|| self.max_expansions(target));
+                if min > max {
+                    return Err(Error::UnsatisfiableInvokeLimit {
+                        span: alt.span,
+                        min,
+                        max,
+                    });
+                }
+            }
+        }
+        Ok(self)
+    }
+
+    /// the maximum number of times `target` can be expanded along a derivation of any rule,
+    /// `target` must not be reachable from a recursive rule
+    fn max_expansions(&self, target: NodeIndex) -> usize {
+        // only the rules reaching the target are visited, none of them is recursive
+        let mut reaching = HashSet::new();
+        let reversed = Reversed(&self.graph);
+        let mut dfs = Dfs::new(reversed, target);
+        while let Some(nx) = dfs.next(reversed) {
+            reaching.insert(nx);
+        }
+        let mut memo = HashMap::new();
+        reaching
+            .iter()
+            .map(|nx| self.expansions(*nx, target, &reaching, &mut memo))
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// the maximum number of times `target` can be expanded along a derivation of `nx`
+    fn expansions(
+        &self,
+        nx: NodeIndex,
+        target: NodeIndex,
+        reaching: &HashSet<NodeIndex>,
+        memo: &mut HashMap<NodeIndex, usize>,
+    ) -> usize {
+        if !reaching.contains(&nx) {
+            return 0;
+        }
+        if let Some(n) = memo.get(&nx) {
+            return *n;
+        }
+        let name = self.graph[nx].as_str();
+        let mut max = 0;
+        for alt in self
+            .rules
+            .iter()
+            .filter(|rule| rule.lhs.as_str() == name)
+            .flat_map(|rule| rule.rhs())
+        {
+            let mut sum = 0usize;
+            for sym in &alt.symbols {
+                if let Some(next) = sym.non_terminal() {
+                    let n = self.expansions(self.nodes[next], target, reaching, memo);
+                    sum = sum.saturating_add(n);
+                }
+            }
+            max = max.max(sum);
+        }
+        let n = max.saturating_add((nx == target) as usize);
+        memo.insert(nx, n);
+        n
+    }
+
+    /// Render the graph in Graphviz DOT, the non-terminals as nodes (once per name)
+    /// and the references as edges, e.g. to be piped into `dot -Tsvg`.
+    ///
+    /// The rules trapped in a dead loop are colored red,
+    /// and those unreachable from `start` (if given) are grayed out.
+    pub fn to_dot(&self, start: Option<&str>) -> String {
+        let productive = self.productive_nonterminals();
+        let trapped = petgraph::algo::tarjan_scc(&self.graph)
+            .into_iter()
+            .filter(|scc| self.is_trap_loop(scc, &productive))
+            .flatten()
+            .map(|nx| self.graph[nx].as_str())
+            .filter(|name| !productive.contains(name))
+            .collect::<HashSet<_>>();
+        let mut reachable = HashSet::new();
+        if let Some(start) = start.and_then(|start| self.nodes.get(start)) {
+            let mut dfs = Dfs::new(&self.graph, *start);
+            while let Some(nx) = dfs.next(&self.graph) {
+                reachable.insert(self.graph[nx].as_str());
+            }
+        }
+
+        // a name may be defined by several (typed) rules, only the last node of it has edges
+        let mut names = Vec::new();
+        for rule in self.rules {
+            if !names.contains(&rule.lhs.as_str()) {
+                names.push(rule.lhs.as_str());
+            }
+        }
+        let mut buf = String::from("digraph {\n");
+        for name in &names {
+            let attrs = if trapped.contains(name) {
+                " [color = red, fontcolor = red]"
+            } else if start.is_some() && !reachable.contains(name) {
+                " [color = gray, fontcolor = gray]"
+            } else {
+                ""
+            };
+            writeln!(buf, "    \"{}\"{};", name, attrs).unwrap();
+        }
+        for name in &names {
+            // petgraph yields the neighbors from the latest edge
+            let neighbors = self.graph.neighbors(self.nodes[*name]).collect::<Vec<_>>();
+            let mut targets = Vec::new();
+            for nx in neighbors.into_iter().rev() {
+                let target = self.graph[nx].as_str();
+                if !targets.contains(&target) {
+                    targets.push(target);
+                    writeln!(buf, "    \"{}\" -> \"{}\";", name, target).unwrap();
+                }
+            }
+        }
+        buf.push_str("}\n");
+        buf
+    }
+
+    /// Size metrics of the grammar, derived without any generation.
+    pub fn stats(&self) -> GrammarStats {
+        let symbols = || {
             self.rules
                 .iter()
-                .find(|rule| rule.lhs.as_str() == name)
-                .unwrap()
-                .produce_terminals()
-        });
-        if produce_t {
-            return false;
-        }
-        let out_deg: HashSet<NodeIndex> = scc
-            .iter()
-            .flat_map(|nx| self.graph.neighbors(*nx))
-            .collect();
-        out_deg == scc.iter().copied().collect()
+                .flat_map(|r| r.rhs().iter().flat_map(|a| a.symbols.iter()))
+        };
+        let recursive = match self.topo_order() {
+            Ok(_) => 0,
+            Err(cycles) => cycles.iter().map(Vec::len).sum(),
+        };
+        let regexes = symbols()
+            .filter_map(|sym| match &sym.kind {
+                SymbolKind::Regex(re) | SymbolKind::RegexBytes(re) => Some(re),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        GrammarStats {
+            rules: self.rules.len(),
+            alternatives: self.rules.iter().map(|r| r.rhs().len()).sum(),
+            recursive,
+            non_terminals: self.nodes.len(),
+            terminals: symbols()
+                .filter_map(|sym| sym.kind.non_re_terminal())
+                .collect::<HashSet<_>>()
+                .len(),
+            regexes: regexes.len(),
+            finite: recursive == 0 && regexes.iter().all(|re| re.language_size().is_some()),
+        }
+    }
+
+    /// a loop is a trap if any of its rules can never terminate, escaping the loop is not enough,
+    /// e.g. `<S> ::= <S> | <S> <E>` always re-enters `<S>` even if `<E>` terminates,
+    /// and in `<P> ::= <N> | "p" ; <N> ::= <N> <P>` the terminating `<P>` does not save `<N>`,
+    /// only the rules which can never terminate are trapped
+    fn is_trap_loop(&self, scc: &[NodeIndex], productive: &HashSet<&str>) -> bool {
+        scc.iter()
+            .any(|nx| !productive.contains(self.graph[*nx].as_str()))
     }
 }
@@ -1,5 +1,8 @@
+mod abnf;
 pub mod alt;
+pub mod builder;
 pub mod checked;
+mod ebnf;
 pub mod graph;
 pub mod production;
 pub mod raw;
@@ -9,9 +12,14 @@ pub mod symbol;
 
 #[cfg(test)]
 mod test {
+    use crate::error::Error;
+    use crate::generator::{Generator, GeneratorSettings};
+    use crate::grammar::alt::Limit;
     use crate::grammar::raw::RawGrammar;
     use crate::report::{Reporter, Style};
     use miette::{Diagnostic, Report};
+    use std::collections::HashSet;
+    use std::hash::{Hash, Hasher};
     use std::sync::Arc;
 
     fn report_with_unnamed_source<T: Diagnostic + Sync + Send + 'static, S: ToString>(
@@ -76,6 +84,14 @@ mod test {
         insta::assert_snapshot!(ui);
     }
 
+    #[test]
+    fn unsupported_look() {
+        let text = r#"<R> ::= re("foo$bar"); "#;
+        let err = RawGrammar::parse(text).err().unwrap();
+        let ui = report_with_unnamed_source(err, text);
+        insta::assert_snapshot!(ui);
+    }
+
     #[test]
     fn undefined_nt() {
         let text = "<E> ::= <S>;";
@@ -84,17 +100,520 @@ mod test {
         insta::assert_snapshot!(ui);
     }
 
-    // #[test]
-    // fn duplicated_def() {
-    //     let text = r#"
-    //         <E> ::= <S>;
-    //         <S> ::= <E>;
-    //         <E> ::= "?";
-    //     "#;
-    //     let err = RawGrammar::parse(text).unwrap().to_checked().err().unwrap();
-    //     let ui = report_with_unnamed_source(err, text);
-    //     insta::assert_snapshot!(ui);
-    // }
+    #[test]
+    fn undefined_nt_after_multibyte() {
+        // spans are byte offsets, the label must still underline exactly `<F>`
+        let text = "<E> ::= \"你好\" <F> \"世界\" ;";
+        let err = RawGrammar::parse(text).unwrap().to_checked().err().unwrap();
+        let ui = report_with_unnamed_source(err, text);
+        insta::assert_snapshot!(ui);
+    }
+
+    #[test]
+    fn invalid_escape_after_multibyte() {
+        let text = "<E> ::= \"你好\\q\" ;";
+        let err = RawGrammar::parse(text).err().unwrap();
+        let ui = report_with_unnamed_source(err, text);
+        insta::assert_snapshot!(ui);
+    }
+
+    #[test]
+    fn undefined_typed_nt() {
+        let text = r#"
+            <E: "int"> ::= "1" | <E: "bool"> ;
+        "#;
+        let err = RawGrammar::parse(text).unwrap().to_checked().err().unwrap();
+        let ui = report_with_unnamed_source(err, text);
+        insta::assert_snapshot!(ui);
+    }
+
+    #[test]
+    fn duplicated_def() {
+        let text = r#"
+            <E> ::= <S>;
+            <S> ::= <E>;
+            <E> ::= "?";
+        "#;
+        let err = RawGrammar::parse(text).unwrap().to_checked().err().unwrap();
+        let ui = report_with_unnamed_source(err, text);
+        insta::assert_snapshot!(ui);
+    }
+
+    #[test]
+    fn duplicated_typed_def() {
+        let text = r#"
+            <E: "int"> ::= "1" ;
+            <E: "str"> ::= "a" ;
+            <E: "int"> ::= "2" ;
+        "#;
+        let err = RawGrammar::parse(text).unwrap().to_checked().err().unwrap();
+        let ui = report_with_unnamed_source(err, text);
+        insta::assert_snapshot!(ui);
+    }
+
+    #[test]
+    fn definitions_and_references() {
+        let text = r#"<S> ::= <E> | decl(<E>) ; <E: "int"> ::= "1" | <E> ;"#;
+        let grammar = RawGrammar::parse(text).unwrap();
+        let defs = grammar
+            .definitions()
+            .map(|(name, span)| (name, &text[span.start()..span.end()]))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            defs,
+            vec![
+                ("S", "<S> ::= <E> | decl(<E>) ;"),
+                ("E", r#"<E: "int"> ::= "1" | <E> ;"#)
+            ]
+        );
+        let refs = grammar
+            .references("E")
+            .into_iter()
+            .map(|span| &text[span.start()..span.end()])
+            .collect::<Vec<_>>();
+        assert_eq!(refs, vec!["<E>", "decl(<E>)", "<E>"]);
+        assert!(grammar.references("S").is_empty());
+    }
+
+    #[test]
+    fn rename() {
+        let text = r#"<S> ::= <E> | decl(<E>) ; <E: "int"> ::= "1" | <E> ;"#;
+        let mut grammar = RawGrammar::parse(text).unwrap();
+        assert_eq!(grammar.rename("E", "Expr"), 4);
+        assert_eq!(grammar.rename("X", "Y"), 0);
+        assert_eq!(grammar.references("E").len(), 0);
+        assert_eq!(grammar.references("Expr").len(), 3);
+        let defs = grammar
+            .definitions()
+            .map(|(name, _)| name)
+            .collect::<Vec<_>>();
+        assert_eq!(defs, vec!["S", "Expr"]);
+        assert_eq!(grammar.rules[1].lhs.ty.ty(), Some("int"));
+    }
+
+    #[test]
+    fn includes() {
+        let text = r#"<S> ::= <A> <B> ; %include "a.bnfgen" ; <T> ::= <S> ; %include "b.bnfgen" ;"#;
+        let grammar = RawGrammar::parse(text).unwrap();
+        let includes = grammar.includes().map(|(p, _)| p).collect::<Vec<_>>();
+        assert_eq!(includes, vec!["a.bnfgen", "b.bnfgen"]);
+
+        // the included sources are appended, so that the spans never overlap
+        let source = format!(r#"{}<A> ::= "a" ;<B> ::= "b" ;"#, text);
+        let grammar = grammar
+            .resolve_includes(|path, _| match path {
+                "a.bnfgen" => RawGrammar::parse_at(&source[..text.len() + 13], text.len()),
+                _ => RawGrammar::parse_at(&source, text.len() + 13),
+            })
+            .unwrap();
+        assert_eq!(grammar.includes().count(), 0);
+        let defs = grammar.definitions().collect::<Vec<_>>();
+        assert_eq!(
+            defs.iter().map(|(name, _)| *name).collect::<Vec<_>>(),
+            vec!["S", "A", "T", "B"]
+        );
+        assert_eq!(defs[1].1.start(), text.len());
+        assert!(grammar.to_checked().is_ok());
+    }
+
+    #[test]
+    fn duplicate_alternative() {
+        let text = r#"
+            <S> ::= "a" | <E> | "a" ;
+            <E> ::= "a" | "b" ;
+        "#;
+        let err = RawGrammar::parse(text)
+            .unwrap()
+            .check_duplicate_alternatives()
+            .err()
+            .unwrap();
+        assert!(err.is_warning());
+        let ui = report_with_unnamed_source(err, text);
+        insta::assert_snapshot!(ui);
+        // alternatives differing by anything but their symbols are not duplicated
+        for text in [
+            r#"<S> ::= "x" @one | "x" @two ;"#,
+            r#"<S> ::= <Id> | decl(<Id>) ; <Id> ::= "x" ;"#,
+            r#"<S> ::= 2 "a" | "a" ;"#,
+            r#"<S> ::= "a" | "a" {1, 2} ;"#,
+        ] {
+            let grammar = RawGrammar::parse(text).unwrap();
+            assert!(grammar.check_duplicate_alternatives().is_ok(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn char_range() {
+        let text = r#"
+            <S> ::= 'a'..'c' '\''..'\'' ;
+        "#;
+        let grammar = RawGrammar::parse(text).unwrap().to_checked().unwrap();
+        let out = grammar.enumerate("S").unwrap().collect::<Vec<_>>();
+        assert_eq!(out, vec!["a '", "b '", "c '"]);
+    }
+
+    #[test]
+    fn invalid_char_range() {
+        let text = r#"
+            <S> ::= 'z'..'a' ;
+        "#;
+        let err = RawGrammar::parse(text).err().unwrap();
+        let ui = report_with_unnamed_source(err, text);
+        insta::assert_snapshot!(ui);
+    }
+
+    #[test]
+    fn percentages() {
+        let text = r#"
+            <S> ::= "a" 70% | "b" 20.5% {1, 2} | <E> 9.5% ;
+            <E> ::= "c" ;
+        "#;
+        let grammar = RawGrammar::parse(text).unwrap();
+        let weights = grammar.rules[0]
+            .rhs()
+            .iter()
+            .map(|alt| alt.weight)
+            .collect::<Vec<_>>();
+        assert_eq!(weights, vec![70.0, 20.5, 9.5]);
+    }
+
+    #[test]
+    fn parse_reader() {
+        let grammar = RawGrammar::parse_reader(r#"<S> ::= "a" ;"#.as_bytes()).unwrap();
+        assert_eq!(grammar.definitions().count(), 1);
+        let err = RawGrammar::parse_reader(&b"<S> ::= \"\xff\" ;"[..]).unwrap_err();
+        assert!(matches!(err, crate::error::Error::Io { .. }));
+    }
+
+    #[test]
+    fn from_str() {
+        let grammar: RawGrammar = r#"<S> ::= "a" | <S> "b" ;"#.parse().unwrap();
+        assert_eq!(grammar.definitions().count(), 1);
+        assert!("<S> ::= ".parse::<RawGrammar>().is_err());
+    }
+
+    #[test]
+    fn regex_escapes() {
+        let gen: Generator = r#"<S> ::= re("\d{3}\.") re("\\+") ;"#.parse().unwrap();
+        let mut rng = rand::thread_rng();
+        for _ in 0..10 {
+            let out = gen.generate("S", &mut rng).unwrap();
+            let (digits, plus) = out.split_once(". ").unwrap();
+            assert_eq!(digits.chars().filter(|c| c.is_numeric()).count(), 3);
+            assert_eq!(plus, "+");
+        }
+        // terminals keep rejecting unknown escapes
+        assert!(RawGrammar::parse(r#"<S> ::= "\d" ;"#).is_err());
+    }
+
+    #[test]
+    fn regex_unicode_class() {
+        let gen: Generator = r#"<S> ::= re("\p{Greek}{8}") ;"#.parse().unwrap();
+        let mut rng = rand::thread_rng();
+        for _ in 0..10 {
+            let out = gen.generate("S", &mut rng).unwrap();
+            assert_eq!(out.chars().count(), 8);
+            assert!(out.chars().all(|c| !c.is_ascii()));
+        }
+    }
+
+    #[test]
+    fn regex_max() {
+        let text = r#"<S> ::= re("a+", max = 40) ;"#;
+        let gen: Generator = text.parse().unwrap();
+        let mut rng = rand::thread_rng();
+        let lens = (0..100)
+            .map(|_| gen.generate("S", &mut rng).unwrap().len())
+            .collect::<Vec<_>>();
+        assert!(lens.iter().all(|len| (1..=40).contains(len)));
+        assert!(lens.iter().any(|len| *len > 5));
+
+        let text = r#"<S> ::= re("a+", min = 3) ;"#;
+        let err = RawGrammar::parse(text).err().unwrap();
+        let ui = report_with_unnamed_source(err, text);
+        insta::assert_snapshot!(ui);
+    }
+
+    #[test]
+    fn repeat_max() {
+        let grammar = RawGrammar::parse(r#"<S> ::= re("a+") re("b+", max = 2) ;"#)
+            .unwrap()
+            .to_checked()
+            .unwrap();
+        let settings = GeneratorSettings::builder().repeat_max(40).build();
+        let gen = Generator::builder()
+            .grammar(grammar)
+            .settings(settings)
+            .build();
+        let mut rng = rand::thread_rng();
+        let outs = (0..100)
+            .map(|_| gen.generate("S", &mut rng).unwrap())
+            .collect::<Vec<_>>();
+        let (a, b): (Vec<_>, Vec<_>) = outs.iter().map(|s| s.split_once(' ').unwrap()).unzip();
+        assert!(a.iter().all(|a| (1..=40).contains(&a.len())));
+        assert!(a.iter().any(|a| a.len() > 5));
+        assert!(b.iter().all(|b| (1..=2).contains(&b.len())));
+    }
+
+    #[test]
+    fn avoid_terminals() {
+        let grammar = RawGrammar::parse(r#"<S> ::= re("a|b") "a" ;"#)
+            .unwrap()
+            .to_checked()
+            .unwrap();
+        let firsts = |avoid_terminals: bool| {
+            let settings = GeneratorSettings::builder()
+                .avoid_terminals(avoid_terminals)
+                .build();
+            let gen = Generator::builder()
+                .grammar(grammar.clone())
+                .settings(settings)
+                .build();
+            let mut rng = rand::thread_rng();
+            (0..100)
+                .map(|_| gen.generate("S", &mut rng).unwrap())
+                .map(|s| s.split_once(' ').unwrap().0.to_string())
+                .collect::<HashSet<_>>()
+        };
+        assert_eq!(firsts(true), HashSet::from(["b".to_string()]));
+        assert!(firsts(false).contains("a"));
+    }
+
+    #[test]
+    fn off() {
+        let text = r#"
+            <S> ::= "a" 70% | "b" off | <E> 30% ;
+            <E> ::= "c" | "d" off {1, 2} ;
+        "#;
+        let grammar = RawGrammar::parse(text).unwrap();
+        let weights = |i: usize| {
+            grammar.rules[i]
+                .rhs()
+                .iter()
+                .map(|alt| alt.weight)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(weights(0), vec![70.0, 0.0, 30.0]);
+        assert_eq!(weights(1), vec![1.0, 0.0]);
+        assert!(RawGrammar::parse(r#"<S> ::= 2 "a" off ;"#).is_err());
+    }
+
+    #[test]
+    fn invalid_decay() {
+        let text = r#"<E> ::= "1" | <E> "+" <E> {decay = 2} ;"#;
+        let err = RawGrammar::parse(text).err().unwrap();
+        let ui = report_with_unnamed_source(err, text);
+        insta::assert_snapshot!(ui);
+        assert!(RawGrammar::parse(r#"<E> ::= "1" {depth = 0.5} ;"#).is_err());
+    }
+
+    #[test]
+    fn labels() {
+        let text = r#"
+            <E> ::= "1" @lit | <E> "+" <E> {0, 3} @add | "0" off @zero | "2" ;
+        "#;
+        let grammar = RawGrammar::parse(text).unwrap();
+        let labels = grammar.rules[0]
+            .rhs()
+            .iter()
+            .map(|alt| alt.label())
+            .collect::<Vec<_>>();
+        assert_eq!(labels, vec![Some("lit"), Some("add"), Some("zero"), None]);
+
+        let text = r#"<E> ::= "1" @lit | "2" @lit ;"#;
+        let err = RawGrammar::parse(text).unwrap().to_checked().err().unwrap();
+        let ui = report_with_unnamed_source(err, text);
+        insta::assert_snapshot!(ui);
+    }
+
+    #[test]
+    fn likes() {
+        let text = r#"
+            <A> ::= "a1" | "a2" ;
+            <B> ::= "b1" | like <C> | "b2" ;
+            <C> ::= like <A> | "c" ;
+        "#;
+        let grammar = RawGrammar::parse(text).unwrap().resolve_likes().unwrap();
+        let terminals = |i: usize| {
+            grammar.rules[i]
+                .rhs()
+                .iter()
+                .map(|alt| alt.symbols[0].kind.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            terminals(1),
+            vec!["\"b1\"", "\"a1\"", "\"a2\"", "\"c\"", "\"b2\""]
+        );
+        assert_eq!(terminals(2), vec!["\"a1\"", "\"a2\"", "\"c\""]);
+
+        let text = r#"<B> ::= "b" | like <A> ;"#;
+        let err = RawGrammar::parse(text).unwrap().to_checked().err().unwrap();
+        let ui = report_with_unnamed_source(err, text);
+        insta::assert_snapshot!("undefined_like", ui);
+
+        let text = r#"
+            <A> ::= "a" | like <B> ;
+            <B> ::= "b" | like <A> ;
+        "#;
+        let err = RawGrammar::parse(text).unwrap().to_checked().err().unwrap();
+        let ui = report_with_unnamed_source(err, text);
+        insta::assert_snapshot!("like_cycle", ui);
+    }
+
+    #[test]
+    fn alt_id_by_pattern() {
+        let id = |text: &str, i: usize| RawGrammar::parse(text).unwrap().rules[0].rhs()[i].id();
+        let a = id(r#"<A> ::= re("[a-z]+") "x" ;"#, 0);
+        let b = id(r#"<B> ::= "y" |   re("[a-z]+")  "x" {1, 2} ;"#, 1);
+        assert_eq!(a, b);
+        assert_ne!(a, id(r#"<A> ::= re("[a-z]+", max = 3) "x" ;"#, 0));
+        assert_ne!(a, id(r#"<A> ::= re("[a-z]*") "x" ;"#, 0));
+        assert_ne!(a, id(r#"<A> ::= re_bytes("[a-z]+") "x" ;"#, 0));
+
+        let text = r#"<S> ::= <Id> | decl(<Id>) | ref(<Id>) | "x" | note("x") ;"#;
+        let ids = (0..5).map(|i| id(text, i)).collect::<HashSet<_>>();
+        assert_eq!(ids.len(), 5);
+
+        let hash = |limit: &Limit| {
+            let mut hasher = std::hash::DefaultHasher::new();
+            limit.hash(&mut hasher);
+            hasher.finish()
+        };
+        let decay = Limit::Decay { base: 0.5 };
+        assert_eq!(decay, Limit::Decay { base: 0.5 });
+        assert_eq!(hash(&decay), hash(&Limit::Decay { base: 0.5 }));
+        assert_ne!(decay, Limit::Decay { base: 0.25 });
+        assert_ne!(Limit::Unlimited, Limit::Limited { min: 0, max: 0 });
+    }
+
+    #[test]
+    fn empty_alternative() {
+        let text = r#"
+            <S> ::= "a" <Opt> "b" ;
+            <Opt> ::= "x" | ;
+        "#;
+        let grammar = RawGrammar::parse(text).unwrap();
+        assert_eq!(grammar.rules[1].rhs().len(), 2);
+        assert!(grammar.rules[1].rhs()[1].symbols.is_empty());
+        assert!(grammar.graph().nullable_nonterminals().contains("Opt"));
+        let gen = Generator::builder()
+            .grammar(grammar.to_checked().unwrap())
+            .build();
+        let mut rng = rand::thread_rng();
+        let outs = (0..100)
+            .map(|_| gen.generate("S", &mut rng).unwrap())
+            .collect::<Vec<_>>();
+        assert!(outs.iter().all(|s| s == "a x b" || s == "a b"));
+        assert!(outs.iter().any(|s| s == "a x b"));
+        assert!(outs.iter().any(|s| s == "a b"));
+
+        // an empty alternative can be weighted, turned off, or be the only one
+        let grammar = RawGrammar::parse(r#"<A> ::= | "x" | 3 | off ; <B> ::= ;"#).unwrap();
+        let weights = grammar.rules[0]
+            .rhs()
+            .iter()
+            .map(|alt| (alt.weight, alt.symbols.len()))
+            .collect::<Vec<_>>();
+        assert_eq!(weights, vec![(1.0, 0), (1.0, 1), (3.0, 0), (0.0, 0)]);
+        assert_eq!(grammar.rules[1].rhs().len(), 1);
+    }
+
+    #[test]
+    fn unknown_builtin() {
+        let text = r#"<S> ::= "$FOO" $FOO ;"#;
+        let err = RawGrammar::parse(text).err().unwrap();
+        let ui = report_with_unnamed_source(err, text);
+        insta::assert_snapshot!(ui);
+    }
+
+    #[test]
+    fn eq_ignores_spans() {
+        let grammar = RawGrammar::parse(r#"<S> ::= "a" <S> {0, 2} @more | "b" ;"#).unwrap();
+        let formatted = RawGrammar::parse(
+            r#"
+            // the same grammar, formatted differently
+            <S> ::= "a" <S> {0, 2} @more
+                  | "b" ;
+        "#,
+        )
+        .unwrap();
+        assert_eq!(grammar, formatted);
+        let reordered = RawGrammar::parse(r#"<S> ::= "b" | "a" <S> {0, 2} @more ;"#).unwrap();
+        assert_ne!(grammar, reordered);
+
+        let liked = RawGrammar::parse(r#"<A> ::= "a" | "b" ; <B> ::= like <A> | "c" ;"#).unwrap();
+        let expanded =
+            RawGrammar::parse(r#"<A> ::= "a" | "b" ; <B> ::= "a" | "b" | "c" ;"#).unwrap();
+        assert_ne!(liked, expanded);
+        assert_eq!(liked.resolve_likes().unwrap(), expanded);
+    }
+
+    #[test]
+    fn mixed_percentages() {
+        let text = r#"
+            <S> ::= "a" 70% | 2 "b" ;
+        "#;
+        let err = RawGrammar::parse(text).err().unwrap();
+        let ui = report_with_unnamed_source(err, text);
+        insta::assert_snapshot!(ui);
+    }
+
+    #[test]
+    fn invalid_percentages() {
+        let text = r#"
+            <S> ::= "a" 70% | "b" 20% ;
+        "#;
+        let err = RawGrammar::parse(text).err().unwrap();
+        let ui = report_with_unnamed_source(err, text);
+        insta::assert_snapshot!(ui);
+    }
+
+    #[test]
+    fn zero_weights() {
+        let text = r#"
+            <S> ::= <E> ;
+            <E> ::= 0 "a" | 0.0 "b" ;
+        "#;
+        let err = RawGrammar::parse(text).unwrap().to_checked().err().unwrap();
+        let ui = report_with_unnamed_source(err, text);
+        insta::assert_snapshot!(ui);
+    }
+
+    #[test]
+    fn weight_overflow() {
+        // 9e307, whose double overflows
+        let big = format!("9{}.0", "0".repeat(307));
+        let text = format!(r#"<S> ::= {big} "a" | "b" | {big} "c" ;"#);
+        let err = RawGrammar::parse(&text)
+            .unwrap()
+            .to_checked()
+            .err()
+            .unwrap();
+        assert!(matches!(err, Error::WeightOverflow { ref name, .. } if name == "S"));
+        // an untyped reference chooses from the rules of every type
+        let text = format!(r#"<E: "int"> ::= {big} "1" ; <E: "str"> ::= {big} "a" ;"#);
+        let err = RawGrammar::parse(&text)
+            .unwrap()
+            .to_checked()
+            .err()
+            .unwrap();
+        assert!(matches!(err, Error::WeightOverflow { ref name, .. } if name == "E"));
+        // a single huge weight is fine
+        let text = format!(r#"<S> ::= {big} "a" | "b" ;"#);
+        assert!(RawGrammar::parse(&text).unwrap().to_checked().is_ok());
+    }
+
+    #[test]
+    fn regex_always_collides() {
+        let text = r#"
+            <S> ::= <E> | re("a|b") ;
+            <E> ::= "a" | "b" | re("[ab]c?") ;
+        "#;
+        let err = RawGrammar::parse(text).unwrap().to_checked().err().unwrap();
+        let ui = report_with_unnamed_source(err, text);
+        insta::assert_snapshot!(ui);
+    }
 
     #[test]
     fn invalid_repeat() {
@@ -106,6 +625,19 @@ mod test {
         insta::assert_snapshot!(ui);
     }
 
+    #[test]
+    fn no_candidates() {
+        let text = r#"
+            <S> ::= <X> <X> <X> ;
+            <X> ::= "foo" {1} ;
+        "#;
+        let grammar = RawGrammar::parse(text).unwrap().to_checked().unwrap();
+        let gen = Generator::builder().grammar(grammar).build();
+        let err = gen.generate("S", &mut rand::thread_rng()).err().unwrap();
+        let ui = report_with_unnamed_source(err, text);
+        insta::assert_snapshot!(ui);
+    }
+
     #[test]
     fn unreachable_nt() {
         let text = r#"
@@ -125,22 +657,274 @@ mod test {
         insta::assert_snapshot!(ui);
     }
 
-    // TODO: bug, this is also a dead loop
-    // #[test]
-    // fn common_dead_loop() {
-    //     let text = r#"
-    //         <S> ::= <S> | <S> <E> ;
-    //         <E> ::= "Terminal" ;
-    //     "#;
-    //     let err = RawGrammar::parse(text)
-    //         .unwrap()
-    //         .graph()
-    //         .check_trap_loop()
-    //         .err()
-    //         .unwrap();
-    //     let ui = report_with_unnamed_source(err, text);
-    //     insta::assert_snapshot!(ui);
-    // }
+    #[test]
+    fn undefined_start_symbol() {
+        let text = r#"
+            <S> ::= decl(<Id>) ref(<Id>) ;
+            <Id> ::= "x" ;
+        "#;
+        let grammar = RawGrammar::parse(text).unwrap();
+        let graph = grammar.graph();
+        let undefined = Error::UndefinedStartSymbol {
+            name: "X".to_string(),
+        };
+        assert_eq!(graph.check_unused("X").err(), Some(undefined.clone()));
+        assert_eq!(graph.check_references("X").err(), Some(undefined));
+    }
+
+    #[test]
+    fn no_declared_variable() {
+        let text = r#"
+            <S> ::= decl(<Id: "var">) "=" ref(<Id>) ;
+            <Id: "var"> ::= "x" ;
+        "#;
+        let err = RawGrammar::parse(text).unwrap().to_checked().err().unwrap();
+        let ui = report_with_unnamed_source(err, text);
+        insta::assert_snapshot!(ui);
+    }
+
+    #[test]
+    fn no_declared_type() {
+        let text = r#"
+            <S> ::= decl(<Id>: "str") "=" ref(: "int") ;
+            <Id> ::= "x" ;
+        "#;
+        let err = RawGrammar::parse(text).unwrap().to_checked().err().unwrap();
+        let ui = report_with_unnamed_source(err, text);
+        insta::assert_snapshot!(ui);
+    }
+
+    #[test]
+    fn unreachable_declaration() {
+        let text = r#"
+            <S> ::= ref(<Id>) ;
+            <Unused> ::= decl(<Id>) ;
+            <Id> ::= "x" ;
+        "#;
+        let grammar = RawGrammar::parse(text).unwrap();
+        // the state may carry the declarations of another start symbol
+        assert!(grammar.check_references().is_ok());
+        let err = grammar.graph().check_references("S").err().unwrap();
+        let ui = report_with_unnamed_source(err, text);
+        insta::assert_snapshot!(ui);
+        // a rule expanded before the `ref` may declare it
+        let text = r#"
+            <S> ::= <Decl> "=" <Use> ;
+            <Decl> ::= decl(<Id>) ;
+            <Use> ::= ref(<Id>) ;
+            <Id> ::= "x" ;
+        "#;
+        let grammar = RawGrammar::parse(text).unwrap();
+        assert!(grammar.graph().check_references("S").is_ok());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serialize_ast() {
+        let text = r#"
+            <S> ::= 2 <E: "int"> {1, } | 0.5 re("[a-z]+") decl(<E>) ;
+            <E: "int"> ::= "1" {1, 3} | ref(<E>) ;
+        "#;
+        let grammar = RawGrammar::parse(text).unwrap();
+        insta::assert_snapshot!(serde_json::to_string_pretty(&grammar).unwrap());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serialize_typed_var() {
+        let text = r#"
+            <S> ::= decl(<Id>: "int") ref(: "int") ;
+            <Id> ::= "x" ;
+        "#;
+        let grammar = RawGrammar::parse(text).unwrap();
+        insta::assert_snapshot!(serde_json::to_string_pretty(&grammar).unwrap());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serialize_error() {
+        let text = r#"
+            <E> ::= <S>;
+            <S> ::= <E>;
+            <E> ::= "?";
+        "#;
+        let err = RawGrammar::parse(text)
+            .unwrap()
+            .check_duplicate()
+            .err()
+            .unwrap();
+        insta::assert_snapshot!(serde_json::to_string_pretty(&err).unwrap());
+    }
+
+    #[test]
+    fn nullable() {
+        let text = r#"
+            <S> ::= <A> <B> | "x" ;
+            <A> ::= "" | "a" ;
+            <B> ::= re("b*") ;
+            <C> ::= <A> "c" ;
+        "#;
+        let grammar = RawGrammar::parse(text).unwrap();
+        let graph = grammar.graph();
+        let nullable = graph.nullable_nonterminals();
+        assert_eq!(nullable, ["S", "A", "B"].into_iter().collect());
+        assert!(graph.check_nullable("C").is_ok());
+        let err = graph.check_nullable("S").err().unwrap();
+        let ui = report_with_unnamed_source(err, text);
+        insta::assert_snapshot!(ui);
+    }
+
+    #[test]
+    fn common_dead_loop() {
+        let text = r#"
+            <S> ::= <S> | <S> <E> ;
+            <E> ::= "Terminal" ;
+        "#;
+        let err = RawGrammar::parse(text)
+            .unwrap()
+            .graph()
+            .check_trap_loop()
+            .err()
+            .unwrap();
+        let ui = report_with_unnamed_source(err, text);
+        insta::assert_snapshot!(ui);
+    }
+
+    #[test]
+    fn partly_productive_dead_loop() {
+        // `<P>` terminates, but `<N>` never does once it is picked
+        let text = r#"
+            <P> ::= <N> | "p" ;
+            <N> ::= <N> <P> ;
+        "#;
+        let err = RawGrammar::parse(text)
+            .unwrap()
+            .graph()
+            .check_trap_loop()
+            .err()
+            .unwrap();
+        let ui = report_with_unnamed_source(err, text);
+        insta::assert_snapshot!(ui);
+    }
+
+    #[test]
+    fn unsatisfiable_invoke_limit() {
+        let text = r#"
+            <S> ::= <A> <B> decl(<A>) | <B> ;
+            <A> ::= "a" {1, 2} | <B> ;
+            <B> ::= "b" {4, 5} | "c" ;
+        "#;
+        let err = RawGrammar::parse(text)
+            .unwrap()
+            .graph()
+            .check_invoke_limits()
+            .err()
+            .unwrap();
+        assert!(err.is_warning());
+        let ui = report_with_unnamed_source(err, text);
+        insta::assert_snapshot!(ui);
+
+        // `<B>` can be expanded 3 times, and any number of times once recursive
+        for text in [
+            r#"<S> ::= <A> <B> <A> ; <A> ::= <B> ; <B> ::= "b" {3} ;"#,
+            r#"<S> ::= <S> <A> | <A> ; <A> ::= <B> ; <B> ::= "b" {9} ;"#,
+        ] {
+            let grammar = RawGrammar::parse(text).unwrap();
+            assert!(grammar.graph().check_invoke_limits().is_ok(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn stats() {
+        let text = r#"
+            <S> ::= <Stmt> <S> | <Stmt> ;
+            <Stmt> ::= <Expr> ";" | "let" <Id> "=" <Expr> ";" ;
+            <Expr> ::= <Id> | <Num> | "(" <Expr> ")" ;
+            <Id> ::= re("[a-z]+") ;
+            <Num: "int"> ::= re("[0-9]{1,3}") | "0" ;
+            <Num: "bin"> ::= "0b" re("[01]{8}") ;
+        "#;
+        let grammar = RawGrammar::parse(text).unwrap();
+        let stats = grammar.graph().stats();
+        assert_eq!(stats.rules, 6);
+        assert_eq!(stats.alternatives, 11);
+        assert_eq!(stats.recursive, 2);
+        assert_eq!(stats.non_terminals, 5);
+        assert_eq!(stats.terminals, 7);
+        assert_eq!(stats.regexes, 3);
+        assert!(!stats.finite);
+        assert_eq!(
+            stats.to_string(),
+            "rules: 6\nalternatives: 11\nalternatives per rule: 1.83\nrecursive rules: 2\n\
+             non-terminals: 5\nterminals: 7\nregexes: 3\nfinite: false\n"
+        );
+
+        let grammar = RawGrammar::parse(r#"<S> ::= <N> "+" <N> ; <N> ::= re("[0-9]{1,3}") ;"#);
+        assert!(grammar.unwrap().graph().stats().finite);
+        let grammar = RawGrammar::parse(r#"<S> ::= <N> "+" <N> ; <N> ::= re("[0-9]+") ;"#);
+        assert!(!grammar.unwrap().graph().stats().finite);
+    }
+
+    #[test]
+    fn topo_order() {
+        let text = r#"
+            <S> ::= <Stmt> <S> | <Stmt> ;
+            <Stmt> ::= <Expr> ";" | <Decl> ;
+            <Decl> ::= "let" <Id> "=" <Expr> ;
+            <Expr> ::= <Id> | <Num> ;
+            <Id> ::= re("[a-z]+") ;
+            <Num> ::= re("[0-9]+") ;
+        "#;
+        let grammar = RawGrammar::parse(text).unwrap();
+        let graph = grammar.graph();
+        // `<S>` is recursive
+        assert_eq!(graph.topo_order(), Err(vec![vec!["S"]]));
+
+        let text = text.replace("<Stmt> <S> | ", "");
+        let grammar = RawGrammar::parse(&text).unwrap();
+        let order = grammar.graph().topo_order().unwrap();
+        let pos = |name: &str| order.iter().position(|n| *n == name).unwrap();
+        assert_eq!(order.len(), 6);
+        for (user, used) in [
+            ("S", "Stmt"),
+            ("Stmt", "Expr"),
+            ("Stmt", "Decl"),
+            ("Decl", "Id"),
+            ("Decl", "Expr"),
+            ("Expr", "Id"),
+            ("Expr", "Num"),
+        ] {
+            assert!(pos(used) < pos(user), "{} before {}", used, user);
+        }
+
+        let text = r#"
+            <A> ::= <B> | "a" ;
+            <B> ::= <C> ;
+            <C> ::= <A> | <D> ;
+            <D> ::= "d" ;
+            <E: "int"> ::= <E> "+" <E> | "1" ;
+            <E: "str"> ::= "s" ;
+        "#;
+        let grammar = RawGrammar::parse(text).unwrap();
+        let mut cycles = grammar.graph().topo_order().unwrap_err();
+        cycles.iter_mut().for_each(|cycle| cycle.sort());
+        cycles.sort();
+        assert_eq!(cycles, vec![vec!["A", "B", "C"], vec!["E"]]);
+    }
+
+    #[test]
+    fn to_dot() {
+        let text = r#"
+            <S> ::= <E> "+" <E> | <A> ;
+            <E: "int"> ::= "1" | <E> ;
+            <E: "str"> ::= "a" ;
+            <A> ::= <B> ;
+            <B> ::= <A> ;
+            <Unused> ::= "x" ;
+        "#;
+        let grammar = RawGrammar::parse(text).unwrap();
+        insta::assert_snapshot!(grammar.graph().to_dot(Some("S")));
+    }
 
     #[test]
     fn trap_loop() {
@@ -1,44 +1,218 @@
-use crate::grammar::alt::Alternative;
+use crate::error::{Error, Result};
+use crate::grammar::alt::{Alternative, Limit};
+use crate::grammar::checked::Cost;
 use crate::grammar::state::State;
-use crate::grammar::symbol::SymbolKind;
+use crate::span::Span;
 use rand::distributions::Distribution;
 use rand::distributions::WeightedIndex;
 use rand::Rng;
 
-#[derive(Debug)]
-#[repr(transparent)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct WeightedProduction {
     pub(crate) alts: Vec<Alternative>,
+    /// span of the rule defining this production
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) span: Span,
+    /// distribution over all the alternatives, only available when none of them has invoke limits
+    /// or decays, since the candidates and their weights never change in that case
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) dist: Option<WeightedIndex<f64>>,
+}
+
+/// how the weight of an alternative is written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WeightKind {
+    /// no weight given, defaults to 1
+    Default,
+    /// a bare weight before the symbols, e.g. `2 "a"`
+    Bare,
+    /// a percentage after the symbols, e.g. `"a" 70%`
+    Percentage,
+    /// `off` after the symbols, e.g. `"a" off`, an alias of a weight of 0
+    Off,
+}
+
+impl WeightKind {
+    /// resolve the weight of an alternative, which can not be given both before
+    /// and after the symbols
+    pub(crate) fn resolve(
+        weight: Option<f64>,
+        trailing: Option<(f64, WeightKind)>,
+        span: Span,
+    ) -> Result<(f64, WeightKind)> {
+        match (weight, trailing) {
+            (None, None) => Ok((1.0, WeightKind::Default)),
+            (Some(w), None) => Ok((w, WeightKind::Bare)),
+            (None, Some(trailing)) => Ok(trailing),
+            (Some(_), Some(_)) => Err(Error::MixedWeights { span }),
+        }
+    }
+}
+
+/// compare the alternatives only, the span and the cached distribution aside
+impl PartialEq for WeightedProduction {
+    fn eq(&self, other: &Self) -> bool {
+        self.alts == other.alts
+    }
 }
 
 impl WeightedProduction {
-    pub(crate) fn choose_by_state<R: Rng>(&self, state: &mut State<R>) -> Vec<SymbolKind> {
-        let candidates = match self.alts.iter().any(|alt| alt.lose_invoke_limit(state)) {
+    /// the alternatives, in the order of definition
+    pub fn alternatives(&self) -> &[Alternative] {
+        &self.alts
+    }
+
+    /// the span of the rule defining this production
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    pub(crate) fn new(alts: Vec<Alternative>, span: Span) -> Self {
+        WeightedProduction {
+            alts,
+            span,
+            dist: None,
+        }
+    }
+
+    /// percentages are stored as weights as is, but they must be given to
+    /// either all or none of the alternatives (except the ones turned `off`), and sum up to 100
+    pub(crate) fn with_percentages(
+        alts: Vec<(Alternative, WeightKind)>,
+        span: Span,
+    ) -> Result<Self> {
+        if alts.iter().any(|(_, k)| *k == WeightKind::Percentage) {
+            let mixed = alts
+                .iter()
+                .find(|(_, k)| !matches!(k, WeightKind::Percentage | WeightKind::Off));
+            if let Some((alt, _)) = mixed {
+                return Err(Error::MixedWeights { span: alt.span });
+            }
+            let sum: f64 = alts.iter().map(|(alt, _)| alt.weight).sum();
+            if (sum - 100.0).abs() > 1e-6 {
+                return Err(Error::InvalidPercentages {
+                    span,
+                    sum: sum.to_string(),
+                });
+            }
+        }
+        Ok(Self::new(
+            alts.into_iter().map(|(alt, _)| alt).collect(),
+            span,
+        ))
+    }
+
+    /// precompute the distribution if the candidates are fixed
+    pub(crate) fn cache_dist(&mut self) {
+        let fixed = |alt: &Alternative| matches!(alt.invoke_limit, Limit::Unlimited);
+        if self.alts.iter().all(fixed) {
+            self.dist = WeightedIndex::new(self.alts.iter().map(|a| a.weight)).ok();
+        }
+    }
+
+    /// choose an alternative respecting the weights and invoke limits,
+    /// return `None` if no alternative can be chosen
+    pub(crate) fn choose_by_state<R: Rng>(&self, state: &mut State<R>) -> Option<&Alternative> {
+        if let Some(dist) = &self.dist {
+            return Some(&self.alts[dist.sample(state.rng())]);
+        }
+
+        let candidates = self.candidates(state);
+        let alt = Self::sample(&candidates, state)?;
+        Self::track(alt, state);
+        Some(alt)
+    }
+
+    /// choose the alternative at `idx` regardless of the weights and invoke limits
+    pub(crate) fn choose_by_index<R: Rng>(
+        &self,
+        idx: usize,
+        state: &mut State<R>,
+    ) -> Option<&Alternative> {
+        let alt = self.alts.get(idx)?;
+        Self::track(alt, state);
+        Some(alt)
+    }
+
+    /// choose the alternative labeled `label` regardless of the weights and invoke limits
+    pub(crate) fn choose_by_label<R: Rng>(
+        &self,
+        label: &str,
+        state: &mut State<R>,
+    ) -> Option<&Alternative> {
+        let idx = self
+            .alts
+            .iter()
+            .position(|alt| alt.label() == Some(label))?;
+        self.choose_by_index(idx, state)
+    }
+
+    /// Choose an alternative like [`choose_by_state`](Self::choose_by_state), but only among
+    /// the ones accepted by `fits`. If none of them fits, the one with the lowest `cost` is chosen,
+    /// but never one weighing 0.
+    pub(crate) fn choose_bounded<R, F, C>(
+        &self,
+        state: &mut State<R>,
+        fits: F,
+        cost: C,
+    ) -> Option<&Alternative>
+    where
+        R: Rng,
+        F: Fn(&Alternative) -> bool,
+        C: Fn(&Alternative) -> Option<Cost>,
+    {
+        let candidates = self.candidates(state);
+        let fitting = candidates
+            .iter()
+            .copied()
+            .filter(|alt| fits(alt))
+            .collect::<Vec<_>>();
+        let alt = match Self::sample(&fitting, state) {
+            Some(alt) => alt,
+            None => {
+                candidates
+                    .into_iter()
+                    .filter(|alt| alt.weight > 0.0)
+                    .filter_map(|alt| Some((cost(alt)?, alt)))
+                    .min_by_key(|(cost, _)| *cost)?
+                    .1
+            }
+        };
+        Self::track(alt, state);
+        Some(alt)
+    }
+
+    /// the alternatives that can be chosen under the invoke limits
+    fn candidates<R: Rng>(&self, state: &State<R>) -> Vec<&Alternative> {
+        match self.alts.iter().any(|alt| alt.lose_invoke_limit(state)) {
             true => self
                 .alts
                 .iter()
                 .filter(|alt| alt.lose_invoke_limit(state))
-                .collect::<Vec<_>>(),
+                .collect(),
             false => self
                 .alts
                 .iter()
                 .filter(|alt| !alt.exceeds_invoke_limit(state))
-                .collect::<Vec<_>>(),
-        };
+                .collect(),
+        }
+    }
 
-        let dist = WeightedIndex::new(candidates.iter().map(|a| a.weight)).unwrap();
-        let idx = dist.sample(state.rng());
+    fn sample<'a, R: Rng>(
+        candidates: &[&'a Alternative],
+        state: &mut State<R>,
+    ) -> Option<&'a Alternative> {
+        let depth = state.depth;
+        let dist = WeightedIndex::new(candidates.iter().map(|a| a.weight_at(depth))).ok()?;
+        Some(candidates[dist.sample(state.rng())])
+    }
 
-        // tracking the selected alternative
-        if candidates[idx].has_invoke_limits() {
-            state.track(candidates[idx].id());
+    /// tracking the selected alternative
+    fn track<R: Rng>(alt: &Alternative, state: &mut State<R>) {
+        if alt.has_invoke_limits() {
+            state.track(alt.id());
         }
-
-        candidates[idx]
-            .symbols
-            .iter()
-            .map(|s| s.kind.clone())
-            .collect()
     }
 
     pub fn non_re_terminals(&self) -> Vec<&str> {
@@ -3,35 +3,208 @@ use crate::grammar::alt::Limit;
 use crate::grammar::checked::CheckedGrammar;
 use crate::grammar::graph::GrammarGraph;
 use crate::grammar::rule::Rule;
-use crate::grammar::symbol::SymbolKind;
+use crate::grammar::symbol::{NonTerminal, SymbolKind, Ty};
 use crate::lexer;
+use crate::span::Span;
 use crate::utils::convert_parse_error;
 use indexmap::IndexMap;
 use petgraph::graph::{DiGraph, NodeIndex};
 use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use std::sync::Arc;
 
-#[repr(transparent)]
-#[derive(Debug)]
+#[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct RawGrammar {
     pub(crate) rules: Vec<Rule>,
+    /// the path and the span of every `%include "path" ;` directive,
+    /// the library never reads files, resolving them is up to the caller
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Vec::is_empty"))]
+    pub(crate) includes: Vec<(String, Span)>,
+}
+
+/// Two grammars are equal if they define the same rules in the same order and include the
+/// same paths, wherever they are written: the spans are not compared, so parses of the same
+/// text formatted differently are equal.
+impl PartialEq for RawGrammar {
+    fn eq(&self, other: &Self) -> bool {
+        self.rules == other.rules
+            && self
+                .includes()
+                .map(|(p, _)| p)
+                .eq(other.includes().map(|(p, _)| p))
+    }
+}
+
+/// a top-level item of a grammar file
+pub(crate) enum Item {
+    Rule(Rule),
+    Include(String, Span),
 }
 
 impl RawGrammar {
     pub fn parse<S: AsRef<str>>(input: S) -> crate::error::Result<RawGrammar> {
-        let lexer = lexer::Lexer::new(input.as_ref());
+        Self::parse_at(input.as_ref(), 0)
+    }
+
+    /// Parse a grammar from `reader`, e.g. a file or stdin, which is read to the end first,
+    /// failing with [`Error::Io`] if it can not be read or is not valid UTF-8.
+    pub fn parse_reader<R: std::io::Read>(mut reader: R) -> crate::error::Result<RawGrammar> {
+        let mut input = String::new();
+        reader.read_to_string(&mut input).map_err(|e| Error::Io {
+            message: e.to_string(),
+        })?;
+        Self::parse(input)
+    }
+
+    /// Parse `input[offset..]` while keeping the spans relative to the whole `input`,
+    /// so that several sources concatenated into `input` share one coordinate space.
+    pub fn parse_at(input: &str, offset: usize) -> crate::error::Result<RawGrammar> {
+        let lexer = lexer::Lexer::with_offset(input, offset);
         let parser = crate::parser::RawGrammarParser::new();
         parser.parse(lexer).map_err(convert_parse_error)
     }
 
+    /// the path and the span of every `%include` directive, in the order of appearance
+    pub fn includes(&self) -> impl Iterator<Item = (&str, Span)> {
+        self.includes
+            .iter()
+            .map(|(path, span)| (path.as_str(), *span))
+    }
+
+    /// Resolve every `%include` directive by calling `load` with its path and span, in the
+    /// order of appearance, and splice the rules of the loaded grammar in place of the directive.
+    pub fn resolve_includes<F>(mut self, mut load: F) -> crate::error::Result<RawGrammar>
+    where
+        F: FnMut(&str, Span) -> crate::error::Result<RawGrammar>,
+    {
+        let mut rules = Vec::with_capacity(self.rules.len());
+        let mut pending = self.rules.into_iter().peekable();
+        for (path, span) in std::mem::take(&mut self.includes) {
+            while let Some(rule) = pending.next_if(|rule| rule.span.start() < span.start()) {
+                rules.push(rule);
+            }
+            rules.extend(load(&path, span)?.rules);
+        }
+        rules.extend(pending);
+        self.rules = rules;
+        Ok(self)
+    }
+
+    /// Expand every `like <A>` entry into a copy of the alternatives of `<A>`, which are
+    /// expanded first, so that `like` can be chained as long as it does not loop.
+    ///
+    /// As for the other symbols, an untyped `<A>` refers to the rules `<A>` of every type.
+    pub fn resolve_likes(mut self) -> crate::error::Result<RawGrammar> {
+        let mut visiting = vec![false; self.rules.len()];
+        for i in 0..self.rules.len() {
+            self.expand_likes(i, &mut visiting)?;
+        }
+        Ok(self)
+    }
+
+    fn expand_likes(&mut self, i: usize, visiting: &mut [bool]) -> crate::error::Result<()> {
+        if self.rules[i].likes.is_empty() {
+            return Ok(());
+        }
+        visiting[i] = true;
+        let likes = std::mem::take(&mut self.rules[i].likes);
+        let mut copies = Vec::with_capacity(likes.len());
+        for like in &likes {
+            let targets = (0..self.rules.len())
+                .filter(|&j| match like.target.ty {
+                    Ty::Untyped => self.rules[j].lhs.as_str() == like.target.as_str(),
+                    Ty::Typed(_) => self.rules[j].lhs == like.target,
+                })
+                .collect::<Vec<_>>();
+            if targets.is_empty() {
+                return Err(Error::UndefinedLike { span: like.span });
+            }
+            let mut alts = Vec::new();
+            for j in targets {
+                if visiting[j] {
+                    return Err(Error::LikeCycle { span: like.span });
+                }
+                self.expand_likes(j, visiting)?;
+                alts.extend(self.rules[j].rhs().iter().cloned());
+            }
+            copies.push(alts);
+        }
+        visiting[i] = false;
+        // insert from the last one so that the positions of the previous ones stay valid
+        let rule = &mut self.rules[i];
+        for (like, alts) in likes.iter().zip(copies).rev() {
+            rule.production.alts.splice(like.at..like.at, alts);
+        }
+        Ok(())
+    }
+
     pub fn to_checked(self) -> crate::error::Result<CheckedGrammar> {
-        self.check_undefined()?.check_duplicate()?.check_repeats()?;
+        let this = self.resolve_likes()?;
+        this.check_undefined()?
+            .check_duplicate()?
+            .check_labels()?
+            .check_references()?
+            .check_repeats()?
+            .check_weights()?
+            .check_regex_collisions()?;
 
         let mut rules = IndexMap::new();
-        for rule in self.rules {
+        for rule in this.rules {
             rules.insert(rule.lhs, rule.production);
         }
 
-        Ok(CheckedGrammar { rules })
+        Ok(CheckedGrammar::new(rules))
+    }
+
+    /// the name and the span of every rule, in the order of definition
+    pub fn definitions(&self) -> impl Iterator<Item = (&str, Span)> {
+        self.rules.iter().map(|rule| (rule.lhs.as_str(), rule.span))
+    }
+
+    /// the spans of every symbol referring to the non-terminal `name` (of any type),
+    /// including `decl(<name>)` and `ref(<name>)`
+    pub fn references(&self, name: &str) -> Vec<Span> {
+        self.rules
+            .iter()
+            .flat_map(|rule| rule.rhs().iter().flat_map(|alt| alt.symbols.iter()))
+            .filter(|sym| match sym.kind.as_non_terminal() {
+                Some(nt) => nt.as_str() == name,
+                None => false,
+            })
+            .map(|sym| sym.span)
+            .collect()
+    }
+
+    /// Rename the non-terminal `from` (of any type, keeping the type) to `to`,
+    /// both its rules and its references, return the number of occurrences renamed.
+    pub fn rename(&mut self, from: &str, to: &str) -> usize {
+        let to = Arc::new(to.to_string());
+        let mut count = 0;
+        let mut rename = |nt: &mut NonTerminal| {
+            if nt.as_str() == from {
+                nt.name = to.clone();
+                count += 1;
+            }
+        };
+        for rule in &mut self.rules {
+            rename(&mut rule.lhs);
+            for sym in rule
+                .production
+                .alts
+                .iter_mut()
+                .flat_map(|alt| alt.symbols.iter_mut())
+            {
+                match &mut sym.kind {
+                    SymbolKind::NonTerminal(nt)
+                    | SymbolKind::Decl(nt)
+                    | SymbolKind::Ref(nt)
+                    | SymbolKind::TypedDecl(nt, _) => rename(nt),
+                    _ => { /* do nothing */ }
+                }
+            }
+        }
+        count
     }
 
     pub fn graph(&self) -> GrammarGraph<'_> {
@@ -64,8 +237,53 @@ impl RawGrammar {
         }
     }
 
+    /// rules of the same name but different types are distinct,
+    /// while two definitions of the same name and type are duplicated
     pub fn check_duplicate(&self) -> crate::error::Result<&Self> {
-        // TODO: need to rework
+        let mut defined: HashMap<&NonTerminal, Span> = HashMap::new();
+        for rule in &self.rules {
+            if let Some(prev) = defined.insert(&rule.lhs, rule.span) {
+                return Err(Error::DuplicatedRules {
+                    span: rule.span,
+                    prev,
+                });
+            }
+        }
+        Ok(self)
+    }
+
+    /// identical alternatives within a rule, i.e. of the same symbols, weight, invoke limit
+    /// and label, silently add up their weights, which is rarely intended,
+    /// reported as a warning since the grammar is still valid
+    pub fn check_duplicate_alternatives(&self) -> crate::error::Result<&Self> {
+        for rule in &self.rules {
+            let alts = rule.rhs();
+            for (i, alt) in alts.iter().enumerate() {
+                if let Some(prev) = alts[..i].iter().find(|prev| *prev == alt) {
+                    return Err(Error::DuplicateAlternative {
+                        span: alt.span,
+                        prev: prev.span,
+                    });
+                }
+            }
+        }
+        Ok(self)
+    }
+
+    /// labels name the alternatives of a rule, so they must be unique within it
+    pub fn check_labels(&self) -> crate::error::Result<&Self> {
+        for rule in &self.rules {
+            let mut seen = HashMap::new();
+            for alt in rule.rhs() {
+                let Some(label) = alt.label() else { continue };
+                if let Some(prev) = seen.insert(label, alt.span) {
+                    return Err(Error::DuplicateLabel {
+                        span: alt.span,
+                        prev,
+                    });
+                }
+            }
+        }
         Ok(self)
     }
 
@@ -82,16 +300,114 @@ impl RawGrammar {
         Ok(self)
     }
 
+    /// an alternative without a weight defaults to a weight of 1,
+    /// but if every alternative of a rule weighs 0 none of them can be chosen.
+    ///
+    /// The weights are summed up to sample an alternative, the sum must be finite, including
+    /// the sum over the rules of every type of a name, which an untyped reference chooses from.
+    pub fn check_weights(&self) -> crate::error::Result<&Self> {
+        for rule in &self.rules {
+            if rule.rhs().iter().all(|alt| alt.weight == 0.0) {
+                return Err(Error::ZeroWeights { span: rule.span });
+            }
+        }
+        let mut sums: HashMap<&str, f64> = HashMap::new();
+        for rule in &self.rules {
+            let sum = sums.entry(rule.lhs.as_str()).or_default();
+            for alt in rule.rhs() {
+                *sum += alt.weight;
+                if !sum.is_finite() {
+                    return Err(Error::WeightOverflow {
+                        span: alt.span,
+                        name: rule.lhs.as_str().to_string(),
+                    });
+                }
+            }
+        }
+        Ok(self)
+    }
+
+    /// A regex never generates a string which is also a terminal, it is re-generated instead,
+    /// so a regex whose every string is a terminal, e.g. `re("a|b")` along with `"a"` and `"b"`,
+    /// would be re-generated forever.
+    pub fn check_regex_collisions(&self) -> crate::error::Result<&Self> {
+        let symbols = || {
+            self.rules
+                .iter()
+                .flat_map(|rule| rule.rhs().iter().flat_map(|alt| alt.symbols.iter()))
+        };
+        let terminals: HashSet<&[u8]> = symbols()
+            .filter_map(|sym| sym.kind.non_re_terminal())
+            .map(str::as_bytes)
+            .collect();
+        for sym in symbols() {
+            if let SymbolKind::Regex(re) | SymbolKind::RegexBytes(re) = &sym.kind {
+                // a language larger than the set of terminals can not be contained in it
+                let language = re.language(terminals.len() as u64);
+                if let Some(language) = language {
+                    if language.iter().all(|s| terminals.contains(s.as_slice())) {
+                        return Err(Error::RegexAlwaysCollides { span: sym.span });
+                    }
+                }
+            }
+        }
+        Ok(self)
+    }
+
+    /// A `ref(<Id>)` reuses a name declared by a `decl(<Id>)` of exactly the same non-terminal,
+    /// it fails at generation if there is none, which is certain if no such `decl` is written.
+    /// Likewise a `ref(: "int")` requires a `decl(<Id>: "int")`.
+    pub fn check_references(&self) -> crate::error::Result<&Self> {
+        let symbols = || {
+            self.rules
+                .iter()
+                .flat_map(|rule| rule.rhs().iter().flat_map(|alt| alt.symbols.iter()))
+        };
+        let declared: HashSet<&NonTerminal> = symbols()
+            .filter_map(|sym| match &sym.kind {
+                SymbolKind::Decl(nt) | SymbolKind::TypedDecl(nt, _) => Some(nt),
+                _ => None,
+            })
+            .collect();
+        let declared_types: HashSet<&str> = symbols()
+            .filter_map(|sym| match &sym.kind {
+                SymbolKind::TypedDecl(_, ty) => Some(ty.as_str()),
+                _ => None,
+            })
+            .collect();
+        for sym in symbols() {
+            match &sym.kind {
+                SymbolKind::Ref(nt) if !declared.contains(nt) => {
+                    return Err(Error::NoDeclaredVariable { span: sym.span });
+                }
+                SymbolKind::TypedRef(ty) if !declared_types.contains(ty.as_str()) => {
+                    return Err(Error::NoDeclaredVariable { span: sym.span });
+                }
+                _ => { /* do nothing */ }
+            }
+        }
+        Ok(self)
+    }
+
     pub fn check_undefined(&self) -> crate::error::Result<&Self> {
-        let defined: HashSet<String> =
-            HashSet::from_iter(self.rules.iter().map(|r| r.lhs.as_str().to_string()));
+        let names: HashSet<&str> = HashSet::from_iter(self.rules.iter().map(|r| r.lhs.as_str()));
+        let typed: HashSet<&NonTerminal> = HashSet::from_iter(self.rules.iter().map(|r| &r.lhs));
+        // an untyped non-terminal can refer to a rule of any type,
+        // while a typed one requires a rule of exactly the same type
+        let is_defined = |nt: &NonTerminal| match nt.ty {
+            Ty::Untyped => names.contains(nt.as_str()),
+            Ty::Typed(_) => typed.contains(nt),
+        };
         for rule in &self.rules {
             for sym in rule.rhs().iter().flat_map(|a| a.symbols.iter()) {
                 match &sym.kind {
-                    SymbolKind::NonTerminal(s) => {
-                        if !defined.contains(s.as_str()) {
-                            return Err(Error::UndefinedNonTerminal { span: sym.span });
-                        }
+                    SymbolKind::NonTerminal(s)
+                    | SymbolKind::Decl(s)
+                    | SymbolKind::Ref(s)
+                    | SymbolKind::TypedDecl(s, _)
+                        if !is_defined(s) =>
+                    {
+                        return Err(Error::UndefinedNonTerminal { span: sym.span });
                     }
                     _ => { /* do nothing */ }
                 }
@@ -100,3 +416,11 @@ impl RawGrammar {
         Ok(self)
     }
 }
+
+impl FromStr for RawGrammar {
+    type Err = Error;
+
+    fn from_str(s: &str) -> crate::error::Result<Self> {
+        RawGrammar::parse(s)
+    }
+}
@@ -1,16 +1,71 @@
+use crate::error::Result;
 use crate::grammar::alt::Alternative;
-use crate::grammar::production::WeightedProduction;
+use crate::grammar::production::{WeightKind, WeightedProduction};
 use crate::grammar::symbol::NonTerminal;
 use crate::span::Span;
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Rule {
     pub(crate) lhs: NonTerminal,
     pub(crate) production: WeightedProduction,
     pub(crate) span: Span,
+    /// the `like <A>` entries, expanded by [`RawGrammar::resolve_likes`](crate::grammar::raw::RawGrammar::resolve_likes)
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Vec::is_empty"))]
+    pub(crate) likes: Vec<Like>,
+}
+
+/// `like <A>` among the alternatives of a rule, standing for a copy of the alternatives of `<A>`
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Like {
+    pub(crate) target: NonTerminal,
+    /// the number of alternatives written before it, where the copies are inserted
+    pub(crate) at: usize,
+    pub(crate) span: Span,
+}
+
+/// compare everything but the spans
+impl PartialEq for Rule {
+    fn eq(&self, other: &Self) -> bool {
+        self.lhs == other.lhs && self.production == other.production && self.likes == other.likes
+    }
+}
+
+impl PartialEq for Like {
+    fn eq(&self, other: &Self) -> bool {
+        self.target == other.target && self.at == other.at
+    }
+}
+
+/// an entry of the alternatives of a rule as parsed
+pub(crate) enum Entry {
+    Alt(Alternative, WeightKind),
+    Like(NonTerminal, Span),
 }
 
 impl Rule {
+    pub(crate) fn from_entries(lhs: NonTerminal, entries: Vec<Entry>, span: Span) -> Result<Rule> {
+        let mut alts = Vec::new();
+        let mut likes = Vec::new();
+        for entry in entries {
+            match entry {
+                Entry::Alt(alt, kind) => alts.push((alt, kind)),
+                Entry::Like(target, span) => likes.push(Like {
+                    target,
+                    at: alts.len(),
+                    span,
+                }),
+            }
+        }
+        Ok(Rule {
+            lhs,
+            production: WeightedProduction::with_percentages(alts, span)?,
+            span,
+            likes,
+        })
+    }
+
     pub fn rhs(&self) -> &[Alternative] {
         self.production.alts.as_slice()
     }
@@ -1,12 +1,35 @@
-use crate::grammar::alt::AltId;
+use crate::generator::{Progress, RepetitionStats};
+use crate::grammar::alt::{AltId, AltRef};
+use crate::grammar::symbol::{NonTerminal, SymbolKind};
+use rand::prelude::SliceRandom;
 use rand::Rng;
 use std::collections::HashMap;
+use std::sync::Arc;
 
 pub struct State<R: Rng> {
     rng: R,
     /// tracking the number of times an alternative has been selected
     /// Notes: only those with invoke limits are tracked
     pub(crate) tracking: HashMap<AltId, usize>,
+    /// names declared by `decl(<Id>)`, keyed by the declared non-terminal
+    pub(crate) vars: HashMap<NonTerminal, Vec<Arc<String>>>,
+    /// names declared by `decl(<Id>: "ty")`, keyed by the type
+    pub(crate) typed_vars: HashMap<Arc<String>, Vec<Arc<String>>>,
+    /// alternatives forced for the non-terminals of the given names
+    pub(crate) overrides: HashMap<String, AltRef>,
+    /// symbols pending reduction along with their depth,
+    /// kept here so that its allocation is reused across generations
+    pub(crate) stack: Vec<(SymbolKind, usize)>,
+    /// the depth of the non-terminal being expanded, 0 for the start symbol
+    pub(crate) depth: usize,
+    /// the steps of the current generation, checked against the limits of the settings
+    pub(crate) progress: Progress,
+    /// the value of `$INDEX`
+    pub(crate) index: Option<usize>,
+    /// the value of `$SEED`
+    pub(crate) seed: Option<u64>,
+    /// the counts chosen for the regex repetitions, if collected
+    pub(crate) repetitions: Option<RepetitionStats>,
 }
 
 impl<R: Rng> State<R> {
@@ -14,13 +37,54 @@ impl<R: Rng> State<R> {
         Self {
             rng,
             tracking: HashMap::new(),
+            vars: HashMap::new(),
+            typed_vars: HashMap::new(),
+            overrides: HashMap::new(),
+            stack: Vec::new(),
+            depth: 0,
+            progress: Progress::new(),
+            index: None,
+            seed: None,
+            repetitions: None,
         }
     }
 
+    /// set `$INDEX`, e.g. to the number of the string in a batch
+    pub fn with_index(mut self, index: usize) -> Self {
+        self.index = Some(index);
+        self
+    }
+
+    /// set `$SEED`, e.g. to the seed the rng is built from
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// force the alternatives of the non-terminals of the given names, by index or by label
+    pub fn with_overrides<A: Into<AltRef>>(mut self, overrides: HashMap<String, A>) -> Self {
+        self.overrides = overrides
+            .into_iter()
+            .map(|(name, alt)| (name, alt.into()))
+            .collect();
+        self
+    }
+
+    /// the depth of the symbol being reduced, i.e. the number of non-terminals expanded
+    /// on the way from the start symbol, which is at depth 0
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
     pub fn rng(&mut self) -> &mut R {
         &mut self.rng
     }
 
+    /// the rng along with the repetition stats to add to, if collected
+    pub(crate) fn rng_and_repetitions(&mut self) -> (&mut R, Option<&mut RepetitionStats>) {
+        (&mut self.rng, self.repetitions.as_mut())
+    }
+
     pub fn track(&mut self, id: AltId) {
         let count = self.tracking.entry(id).or_insert(0);
         *count += 1;
@@ -29,4 +93,22 @@ impl<R: Rng> State<R> {
     pub fn count(&self, id: AltId) -> usize {
         *self.tracking.get(&id).unwrap_or(&0)
     }
+
+    pub fn declare(&mut self, nt: NonTerminal, name: Arc<String>) {
+        self.vars.entry(nt).or_default().push(name);
+    }
+
+    /// pick one of the names declared for `nt`, if any
+    pub fn lookup(&mut self, nt: &NonTerminal) -> Option<Arc<String>> {
+        self.vars.get(nt)?.choose(&mut self.rng).cloned()
+    }
+
+    pub fn declare_typed(&mut self, ty: Arc<String>, name: Arc<String>) {
+        self.typed_vars.entry(ty).or_default().push(name);
+    }
+
+    /// pick one of the names declared as `ty`, whatever their non-terminal, if any
+    pub fn lookup_typed(&mut self, ty: &Arc<String>) -> Option<Arc<String>> {
+        self.typed_vars.get(ty)?.choose(&mut self.rng).cloned()
+    }
 }
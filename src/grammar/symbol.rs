@@ -1,13 +1,16 @@
+use crate::error::Error;
 use crate::regex::Regex;
 use crate::span::Span;
+use std::fmt;
 use std::hash::Hash;
-use std::rc::Rc;
+use std::sync::Arc;
 
-pub type Terminal = Rc<String>;
+pub type Terminal = Arc<String>;
 
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct NonTerminal {
-    pub(crate) name: Rc<String>,
+    pub(crate) name: Arc<String>,
     pub(crate) ty: Ty,
 }
 
@@ -21,14 +24,14 @@ impl Hash for NonTerminal {
 impl NonTerminal {
     pub fn untyped<S: Into<String>>(name: S) -> Self {
         NonTerminal {
-            name: Rc::new(name.into()),
+            name: Arc::new(name.into()),
             ty: Ty::Untyped,
         }
     }
 
     pub fn typed<S: Into<String>>(name: S, ty: Ty) -> Self {
         NonTerminal {
-            name: Rc::new(name.into()),
+            name: Arc::new(name.into()),
             ty,
         }
     }
@@ -38,15 +41,31 @@ impl NonTerminal {
     }
 }
 
+impl fmt::Display for NonTerminal {
+    /// render as it is written in the grammar, e.g. `<E>` or `<E: "int">`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.ty {
+            Ty::Untyped => write!(f, "<{}>", self.name),
+            Ty::Typed(ty) => write!(f, "<{}: \"{}\">", self.name, ty),
+        }
+    }
+}
+
+/// The type of a non-terminal.
+///
+/// A typed reference such as `<E: "int">` refers to the rule of exactly that type,
+/// while an untyped reference `<E>` is a wildcard referring to the rules of *any* type named `E`
+/// (including the untyped one): their alternatives are pooled together, each keeping its weight.
 #[derive(Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum Ty {
     Untyped,
-    Typed(Rc<String>),
+    Typed(Arc<String>),
 }
 
 impl From<String> for Ty {
     fn from(s: String) -> Self {
-        Ty::Typed(Rc::new(s))
+        Ty::Typed(Arc::new(s))
     }
 }
 
@@ -65,7 +84,7 @@ impl Ty {
     }
 
     pub fn typed<S: Into<String>>(s: S) -> Self {
-        Ty::Typed(Rc::new(s.into()))
+        Ty::Typed(Arc::new(s.into()))
     }
 }
 
@@ -87,24 +106,133 @@ impl Ty {
     }
 }
 
-#[derive(Debug, Clone)]
-pub(crate) enum SymbolKind {
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SymbolKind {
     Terminal(Terminal),
     NonTerminal(NonTerminal),
-    Regex(Rc<Regex>),
+    Regex(Arc<Regex>),
+    /// `re_bytes("...")`, a regex over raw bytes which may not be valid UTF-8,
+    /// only [`Generator::generate_bytes`](crate::generator::Generator::generate_bytes) accepts them
+    RegexBytes(Arc<Regex>),
+    /// `note("...")`, a literal passed through to the output,
+    /// not taken into account when avoiding regex collisions
+    Note(Terminal),
+    /// `decl(<Id>)`, derive `<Id>` and remember the result as a declared name
+    Decl(NonTerminal),
+    /// `ref(<Id>)`, reuse a name previously declared by `decl(<Id>)`
+    Ref(NonTerminal),
+    /// `decl(<Id>: "int")`, declare like `decl(<Id>)` and also remember the name as an `"int"`
+    TypedDecl(NonTerminal, Arc<String>),
+    /// `ref(: "int")`, reuse any name previously declared as an `"int"`, whatever its rule
+    TypedRef(Arc<String>),
+    /// `'a'..'z'`, a single char sampled uniformly from the inclusive range,
+    /// a lightweight alternative to `re("[a-z]")` not avoiding the terminals
+    CharRange {
+        start: char,
+        end: char,
+    },
+    /// `$INDEX` or `$SEED`, a pseudo-terminal resolved from the generation state
+    Builtin(Builtin),
+}
+
+/// The pseudo-terminals resolved at generation time, written with a `$` outside of quotes,
+/// so that a `"$INDEX"` in quotes is a plain terminal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Builtin {
+    /// `$INDEX`, the number of the string in a batch, see [`State::with_index`](crate::grammar::state::State::with_index)
+    Index,
+    /// `$SEED`, the seed of the rng, see [`State::with_seed`](crate::grammar::state::State::with_seed)
+    Seed,
 }
 
+impl Builtin {
+    /// the builtin named `name`, without its `$`
+    pub(crate) fn from_name(name: &str) -> Option<Builtin> {
+        match name {
+            "INDEX" => Some(Builtin::Index),
+            "SEED" => Some(Builtin::Seed),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Builtin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Builtin::Index => write!(f, "$INDEX"),
+            Builtin::Seed => write!(f, "$SEED"),
+        }
+    }
+}
+
+/// consistent with `PartialEq`, the kind is hashed first so that e.g. `<Id>`, `decl(<Id>)`
+/// and `ref(<Id>)`, or a regex and a `re_bytes` of the same pattern, hash differently
 impl Hash for SymbolKind {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            SymbolKind::Terminal(s) | SymbolKind::Note(s) => s.hash(state),
+            SymbolKind::NonTerminal(s) | SymbolKind::Decl(s) | SymbolKind::Ref(s) => s.hash(state),
+            SymbolKind::Regex(s) | SymbolKind::RegexBytes(s) => s.hash(state),
+            SymbolKind::TypedDecl(s, ty) => (s, ty).hash(state),
+            SymbolKind::TypedRef(ty) => ty.hash(state),
+            SymbolKind::CharRange { start, end } => (start, end).hash(state),
+            SymbolKind::Builtin(b) => b.hash(state),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for SymbolKind {
+    /// terminals are rendered as their string value, non-terminals (and decl/ref) as their name
+    /// and regexes as their original pattern
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            SymbolKind::Terminal(s) | SymbolKind::Note(s) => serializer.serialize_str(s.as_str()),
+            SymbolKind::NonTerminal(s) => serializer.serialize_str(s.as_str()),
+            SymbolKind::Regex(re) | SymbolKind::RegexBytes(re) => {
+                serializer.serialize_str(re.as_str())
+            }
+            SymbolKind::Decl(s) | SymbolKind::Ref(s) | SymbolKind::TypedDecl(s, _) => {
+                serializer.serialize_str(s.as_str())
+            }
+            SymbolKind::TypedRef(_) => serializer.serialize_str(&self.to_string()),
+            SymbolKind::CharRange { start, end } => {
+                serializer.serialize_str(&format!("'{}'..'{}'", start, end))
+            }
+            SymbolKind::Builtin(b) => serializer.serialize_str(&b.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for SymbolKind {
+    /// render as it is written in the grammar, e.g. `"a"`, `<E>` or `re("[a-z]+")`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            SymbolKind::Terminal(s) => s.hash(state),
-            SymbolKind::NonTerminal(s) => s.hash(state),
-            SymbolKind::Regex(s) => s.hash(state),
+            SymbolKind::Terminal(s) => write!(f, "{:?}", s),
+            SymbolKind::NonTerminal(nt) => write!(f, "{}", nt),
+            SymbolKind::Regex(re) => write!(f, "re({:?})", re.as_str()),
+            SymbolKind::RegexBytes(re) => write!(f, "re_bytes({:?})", re.as_str()),
+            SymbolKind::Note(s) => write!(f, "note({:?})", s),
+            SymbolKind::Decl(nt) => write!(f, "decl({})", nt),
+            SymbolKind::Ref(nt) => write!(f, "ref({})", nt),
+            SymbolKind::TypedDecl(nt, ty) => write!(f, "decl({}: {:?})", nt, ty),
+            SymbolKind::TypedRef(ty) => write!(f, "ref(: {:?})", ty),
+            SymbolKind::CharRange { start, end } => write!(f, "{:?}..{:?}", start, end),
+            SymbolKind::Builtin(b) => write!(f, "{}", b),
         }
     }
 }
 
 impl SymbolKind {
+    /// `'a'..'z'`, fail if the range is empty
+    pub(crate) fn char_range(start: char, end: char, span: Span) -> Result<SymbolKind, Error> {
+        match start <= end {
+            true => Ok(SymbolKind::CharRange { start, end }),
+            false => Err(Error::InvalidCharRange { span }),
+        }
+    }
+
     /// return the name of current symbol
     /// if it is a non-terminal symbol, return the name of the non-terminal symbol
     /// else return non
@@ -112,7 +240,15 @@ impl SymbolKind {
         match self {
             SymbolKind::Terminal(_) => None,
             SymbolKind::Regex(_) => None,
-            SymbolKind::NonTerminal(s) => Some(s.as_str()),
+            SymbolKind::RegexBytes(_) => None,
+            SymbolKind::Note(_) => None,
+            SymbolKind::Ref(_) => None,
+            SymbolKind::TypedRef(_) => None,
+            SymbolKind::CharRange { .. } => None,
+            SymbolKind::Builtin(_) => None,
+            SymbolKind::NonTerminal(s) | SymbolKind::Decl(s) | SymbolKind::TypedDecl(s, _) => {
+                Some(s.as_str())
+            }
         }
     }
 
@@ -123,35 +259,139 @@ impl SymbolKind {
         }
     }
 
-    pub fn is_terminal(&self) -> bool {
+    /// the text of a terminal or a note
+    pub fn literal(&self) -> Option<&str> {
         match self {
-            SymbolKind::Terminal(_) | SymbolKind::Regex(_) => true,
-            _ => false,
+            SymbolKind::Terminal(s) | SymbolKind::Note(s) => Some(s.as_str()),
+            _ => None,
         }
     }
 
-    // get the non-terminal symbol if it is a non-terminal symbol, else none
+    /// a reference is treated as a terminal since it never derives anything
+    pub fn is_terminal(&self) -> bool {
+        matches!(
+            self,
+            SymbolKind::Terminal(_)
+                | SymbolKind::Regex(_)
+                | SymbolKind::RegexBytes(_)
+                | SymbolKind::Note(_)
+                | SymbolKind::Ref(_)
+                | SymbolKind::TypedRef(_)
+                | SymbolKind::CharRange { .. }
+                | SymbolKind::Builtin(_)
+        )
+    }
+
+    // get the non-terminal symbol if it is a non-terminal (or a declaration), else none
     pub fn non_terminal(&self) -> Option<&str> {
         match self {
-            SymbolKind::NonTerminal(s) => Some(s.as_str()),
+            SymbolKind::NonTerminal(s) | SymbolKind::Decl(s) | SymbolKind::TypedDecl(s, _) => {
+                Some(s.as_str())
+            }
+            _ => None,
+        }
+    }
+
+    /// the non-terminal whose language this symbol ranges over,
+    /// statically both `decl(<Id>)` and `ref(<Id>)` produce what `<Id>` produces,
+    /// while `ref(: "int")` may produce what any `decl(<Id>: "int")` does
+    pub(crate) fn as_non_terminal(&self) -> Option<&NonTerminal> {
+        match self {
+            SymbolKind::NonTerminal(s)
+            | SymbolKind::Decl(s)
+            | SymbolKind::Ref(s)
+            | SymbolKind::TypedDecl(s, _) => Some(s),
             _ => None,
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Symbol {
     pub(crate) kind: SymbolKind,
     pub(crate) span: Span,
 }
 
+#[cfg(feature = "serde")]
+impl serde::Serialize for Symbol {
+    /// rendered as `{"kind": ..., "value": ..., "span": ...}` to keep the kind of the symbol,
+    /// regexes are rendered as their original pattern
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+        let mut s = serializer.serialize_struct("Symbol", 3)?;
+        match &self.kind {
+            SymbolKind::Terminal(t) => {
+                s.serialize_field("kind", "terminal")?;
+                s.serialize_field("value", t)?;
+            }
+            SymbolKind::NonTerminal(nt) => {
+                s.serialize_field("kind", "non_terminal")?;
+                s.serialize_field("value", nt)?;
+            }
+            SymbolKind::Regex(re) => {
+                s.serialize_field("kind", "regex")?;
+                s.serialize_field("value", re.as_str())?;
+            }
+            SymbolKind::RegexBytes(re) => {
+                s.serialize_field("kind", "regex_bytes")?;
+                s.serialize_field("value", re.as_str())?;
+            }
+            SymbolKind::Note(t) => {
+                s.serialize_field("kind", "note")?;
+                s.serialize_field("value", t)?;
+            }
+            SymbolKind::Decl(nt) => {
+                s.serialize_field("kind", "decl")?;
+                s.serialize_field("value", nt)?;
+            }
+            SymbolKind::Ref(nt) => {
+                s.serialize_field("kind", "ref")?;
+                s.serialize_field("value", nt)?;
+            }
+            SymbolKind::TypedDecl(..) | SymbolKind::TypedRef(_) => {
+                // rendered as written, e.g. `decl(<Id>: "int")`
+                s.serialize_field("kind", "typed_var")?;
+                s.serialize_field("value", &self.kind.to_string())?;
+            }
+            SymbolKind::CharRange { .. } => {
+                // rendered as written, e.g. `'a'..'z'`
+                s.serialize_field("kind", "char_range")?;
+                s.serialize_field("value", &self.kind)?;
+            }
+            SymbolKind::Builtin(_) => {
+                s.serialize_field("kind", "builtin")?;
+                s.serialize_field("value", &self.kind)?;
+            }
+        }
+        s.serialize_field("span", &self.span)?;
+        s.end()
+    }
+}
+
 impl Hash for Symbol {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
         self.kind.hash(state);
     }
 }
 
+/// symbols of the same kind are equal wherever they are written
+impl PartialEq for Symbol {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind
+    }
+}
+
+impl Eq for Symbol {}
+
 impl Symbol {
+    pub fn kind(&self) -> &SymbolKind {
+        &self.kind
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
     pub fn is_terminal(&self) -> bool {
         self.kind.is_terminal()
     }
@@ -11,9 +11,12 @@ pub struct Lexer<'input> {
 }
 
 impl<'input> Lexer<'input> {
-    pub fn new(input: &'input str) -> Self {
+    /// lex `input[offset..]`, the spans are still relative to the whole `input`
+    pub fn with_offset(input: &'input str, offset: usize) -> Self {
+        let mut lexer = Token::lexer(input);
+        lexer.bump(offset);
         Self {
-            token_stream: Token::lexer(input).spanned(),
+            token_stream: lexer.spanned(),
         }
     }
 }
@@ -40,7 +43,7 @@ mod test {
     #[test]
     fn it_works() {
         let input = include_str!("../examples/bnf.bnfgen");
-        let lexer = super::Lexer::new(input);
+        let lexer = super::Lexer::with_offset(input, 0);
         let tokens: Vec<_> = lexer.collect();
         insta::assert_debug_snapshot!(tokens);
     }
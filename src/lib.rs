@@ -1,3 +1,4 @@
+pub mod coverage;
 pub mod error;
 pub mod generator;
 pub mod grammar;
@@ -5,9 +6,14 @@ mod lexer;
 pub mod parse_tree;
 mod regex;
 pub mod report;
-mod span;
+pub mod span;
 mod token;
 mod utils;
 
+/// The version of `rand` the generation is generic over (`rand = "0.8"`), the `Rng` bounds of
+/// the public API refer to its traits, so an rng must be built with it, e.g.
+/// `bnfgen::rand::rngs::StdRng::seed_from_u64(42)`, whatever version of `rand` a dependent uses.
+pub use rand;
+
 use lalrpop_util::lalrpop_mod;
 lalrpop_mod!(parser);
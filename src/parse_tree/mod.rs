@@ -1 +1,2 @@
 pub mod tree;
+pub mod visitor;
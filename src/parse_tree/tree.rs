@@ -1,29 +1,202 @@
+use crate::grammar::symbol::SymbolKind;
+use crate::parse_tree::visitor::Visitor;
 use std::fmt;
 use std::fmt::Debug;
+use std::ops::Index;
 
 pub enum ParseTree<T> {
     Leaf(T),
     Branch {
         name: String,
+        /// index of the alternative chosen for the non-terminal, if known
+        alt: Option<usize>,
         children: Vec<ParseTree<T>>,
     },
 }
 
 impl<T> ParseTree<T> {
-    pub(crate) fn leaf(value: T) -> ParseTree<T> {
+    /// a terminal, e.g. to build a tree by hand
+    pub fn leaf(value: T) -> ParseTree<T> {
         ParseTree::Leaf(value)
     }
 
-    pub(crate) fn branch(name: String, children: Vec<ParseTree<T>>) -> ParseTree<T> {
-        ParseTree::Branch { name, children }
+    /// the non-terminal `name` expanded to `children` by its `alt`-th alternative, if known
+    pub fn branch(name: String, alt: Option<usize>, children: Vec<ParseTree<T>>) -> ParseTree<T> {
+        ParseTree::Branch {
+            name,
+            alt,
+            children,
+        }
     }
 
+    #[allow(dead_code)]
     pub(crate) fn children_len(&self) -> usize {
         match self {
             ParseTree::Leaf(_) => 1, // TODO: 0 or 1 ?
             ParseTree::Branch { children, .. } => children.len(),
         }
     }
+
+    /// the subtrees of a branch, empty for a leaf
+    pub fn children(&self) -> &[ParseTree<T>] {
+        match self {
+            ParseTree::Leaf(_) => &[],
+            ParseTree::Branch { children, .. } => children,
+        }
+    }
+
+    /// the value of a leaf, `None` for a branch
+    pub fn leaf_value(&self) -> Option<&T> {
+        match self {
+            ParseTree::Leaf(value) => Some(value),
+            ParseTree::Branch { .. } => None,
+        }
+    }
+
+    /// the name of the non-terminal of a branch, `None` for a leaf
+    pub fn name(&self) -> Option<&str> {
+        match self {
+            ParseTree::Leaf(_) => None,
+            ParseTree::Branch { name, .. } => Some(name),
+        }
+    }
+
+    /// traverse the tree depth-first, invoking the callbacks of `visitor`
+    pub fn visit<V: Visitor<T>>(&self, visitor: &mut V) {
+        match self {
+            ParseTree::Leaf(value) => visitor.visit_leaf(value),
+            ParseTree::Branch { name, children, .. } => {
+                visitor.enter_branch(name, children);
+                children.iter().for_each(|c| c.visit(visitor));
+                visitor.leave_branch(name, children);
+            }
+        }
+    }
+
+    /// transform every leaf with `f` while keeping the shape of the tree
+    pub fn map_leaves<U, F: FnMut(T) -> U>(self, mut f: F) -> ParseTree<U> {
+        self.map_leaves_helper(&mut f)
+    }
+
+    fn map_leaves_helper<U, F: FnMut(T) -> U>(self, f: &mut F) -> ParseTree<U> {
+        match self {
+            ParseTree::Leaf(value) => ParseTree::Leaf(f(value)),
+            ParseTree::Branch {
+                name,
+                alt,
+                children,
+            } => ParseTree::Branch {
+                name,
+                alt,
+                children: children
+                    .into_iter()
+                    .map(|c| c.map_leaves_helper(f))
+                    .collect(),
+            },
+        }
+    }
+}
+
+impl ParseTree<SymbolKind> {
+    /// recover the generated string by joining the terminal leaves (in order) with `sep`
+    pub fn flatten(&self, sep: &str) -> String {
+        let mut terminals = Vec::new();
+        self.collect_terminals(&mut terminals);
+        terminals.join(sep)
+    }
+
+    /// render as a s-expression, e.g. `(S (E "a") "+" (E "b"))`, same as `format!("{}", tree)`
+    pub fn to_sexpr(&self) -> String {
+        self.to_string()
+    }
+
+    /// write the s-expression, with every child of a branch on its own line
+    /// (unless all of them are leaves) if `indent` is given
+    fn write_sexpr(&self, f: &mut fmt::Formatter<'_>, indent: Option<usize>) -> fmt::Result {
+        match self {
+            ParseTree::Leaf(SymbolKind::Terminal(s)) => write!(f, "{:?}", s.as_str()),
+            // only terminals can be leaves
+            ParseTree::Leaf(_) => Ok(()),
+            ParseTree::Branch { name, children, .. } => {
+                write!(f, "({}", name)?;
+                let inline = children.iter().all(|c| matches!(c, ParseTree::Leaf(_)));
+                for child in children {
+                    match indent {
+                        Some(depth) if !inline => write!(f, "\n{}", "  ".repeat(depth + 1))?,
+                        _ => write!(f, " ")?,
+                    }
+                    child.write_sexpr(f, indent.map(|depth| depth + 1))?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+
+    fn collect_terminals<'a>(&'a self, buf: &mut Vec<&'a str>) {
+        match self {
+            ParseTree::Leaf(SymbolKind::Terminal(s)) => buf.push(s.as_str()),
+            ParseTree::Leaf(_) => { /* only terminals can be leaves */ }
+            ParseTree::Branch { children, .. } => {
+                children.iter().for_each(|c| c.collect_terminals(buf));
+            }
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<T: serde::Serialize> serde::Serialize for ParseTree<T> {
+    /// branches are rendered as `{"name": ..., "alt": ..., "children": [...]}` (`alt` omitted if unknown),
+    /// leaves are rendered as `{"terminal": ...}`
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+        match self {
+            ParseTree::Leaf(value) => {
+                let mut map = serializer.serialize_map(Some(1))?;
+                map.serialize_entry("terminal", value)?;
+                map.end()
+            }
+            ParseTree::Branch {
+                name,
+                alt,
+                children,
+            } => {
+                let mut map = serializer.serialize_map(Some(2 + alt.is_some() as usize))?;
+                map.serialize_entry("name", name)?;
+                if let Some(alt) = alt {
+                    map.serialize_entry("alt", alt)?;
+                }
+                map.serialize_entry("children", children)?;
+                map.end()
+            }
+        }
+    }
+}
+
+impl fmt::Display for ParseTree<SymbolKind> {
+    /// a s-expression on a single line, or indented with the alternate flag `{:#}`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let indent = f.alternate().then_some(0);
+        self.write_sexpr(f, indent)
+    }
+}
+
+/// iterate over the children, nothing for a leaf
+impl<'a, T> IntoIterator for &'a ParseTree<T> {
+    type Item = &'a ParseTree<T>;
+    type IntoIter = std::slice::Iter<'a, ParseTree<T>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.children().iter()
+    }
+}
+
+/// the `i`-th child, panic if out of bounds (always for a leaf)
+impl<T> Index<usize> for ParseTree<T> {
+    type Output = ParseTree<T>;
+
+    fn index(&self, i: usize) -> &Self::Output {
+        &self.children()[i]
+    }
 }
 
 // derive debug if T: Debug
@@ -31,11 +204,154 @@ impl<T: Debug> Debug for ParseTree<T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             ParseTree::Leaf(value) => f.debug_tuple("Leaf").field(value).finish(),
-            ParseTree::Branch { name, children } => f
+            ParseTree::Branch {
+                name,
+                alt,
+                children,
+            } => f
                 .debug_struct("Branch")
                 .field("name", name)
+                .field("alt", alt)
                 .field("children", children)
                 .finish(),
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::parse_tree::tree::ParseTree;
+    use crate::parse_tree::visitor::Visitor;
+
+    fn sample() -> ParseTree<&'static str> {
+        ParseTree::branch(
+            "S".to_string(),
+            None,
+            vec![
+                ParseTree::leaf("a"),
+                ParseTree::branch("E".to_string(), None, vec![ParseTree::leaf("b")]),
+                ParseTree::leaf("c"),
+            ],
+        )
+    }
+
+    #[test]
+    fn visitor_counts_leaves() {
+        #[derive(Default)]
+        struct Counter {
+            leaves: usize,
+            branches: usize,
+        }
+
+        impl Visitor<&'static str> for Counter {
+            fn enter_branch(&mut self, _name: &str, _children: &[ParseTree<&'static str>]) {
+                self.branches += 1;
+            }
+
+            fn visit_leaf(&mut self, _value: &&'static str) {
+                self.leaves += 1;
+            }
+        }
+
+        let mut counter = Counter::default();
+        sample().visit(&mut counter);
+        assert_eq!(counter.leaves, 3);
+        assert_eq!(counter.branches, 2);
+    }
+
+    #[test]
+    fn children() {
+        let tree = sample();
+        assert_eq!(tree.name(), Some("S"));
+        assert_eq!(tree.children().len(), 3);
+        assert_eq!(tree[1].name(), Some("E"));
+        assert_eq!(tree[1][0].leaf_value(), Some(&"b"));
+        assert!(tree[0].children().is_empty());
+        assert_eq!(tree[0].name(), None);
+        assert_eq!(tree.leaf_value(), None);
+        let leaves = (&tree)
+            .into_iter()
+            .filter_map(|c| c.leaf_value())
+            .collect::<Vec<_>>();
+        assert_eq!(leaves, [&"a", &"c"]);
+        let mut names = Vec::new();
+        for child in &tree {
+            names.extend(child.name());
+        }
+        assert_eq!(names, ["E"]);
+    }
+
+    #[test]
+    fn build_by_hand() {
+        use crate::grammar::symbol::SymbolKind;
+        use std::sync::Arc;
+
+        let terminal = |s: &str| ParseTree::leaf(SymbolKind::Terminal(Arc::new(s.to_string())));
+        let tree = ParseTree::branch(
+            "E".to_string(),
+            Some(1),
+            vec![
+                ParseTree::branch("E".to_string(), Some(0), vec![terminal("1")]),
+                terminal("+"),
+                ParseTree::branch("E".to_string(), Some(0), vec![terminal("2")]),
+            ],
+        );
+        assert_eq!(tree.flatten(" "), "1 + 2");
+        assert_eq!(tree.to_sexpr(), r#"(E (E "1") "+" (E "2"))"#);
+    }
+
+    #[test]
+    fn map_leaves() {
+        let tree = sample().map_leaves(|s| s.to_uppercase());
+        let mut leaves = Vec::new();
+        tree.map_leaves(|s| leaves.push(s));
+        assert_eq!(leaves, vec!["A", "B", "C"]);
+    }
+
+    #[test]
+    fn sexpr() {
+        use crate::grammar::symbol::SymbolKind;
+        use std::sync::Arc;
+
+        let tree = sample().map_leaves(|s| SymbolKind::Terminal(Arc::new(s.to_string())));
+        let tree = ParseTree::branch(
+            "R".to_string(),
+            None,
+            vec![
+                tree,
+                ParseTree::leaf(SymbolKind::Terminal(Arc::new("\"".to_string()))),
+            ],
+        );
+        assert_eq!(tree.to_sexpr(), r#"(R (S "a" (E "b") "c") "\"")"#);
+        assert_eq!(
+            format!("{:#}", tree),
+            "(R\n  (S\n    \"a\"\n    (E \"b\")\n    \"c\")\n  \"\\\"\")"
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serialize_to_json() {
+        use crate::grammar::symbol::SymbolKind;
+        use std::sync::Arc;
+
+        let tree = ParseTree::branch(
+            "S".to_string(),
+            None,
+            vec![
+                ParseTree::leaf(SymbolKind::Terminal(Arc::new("a".to_string()))),
+                ParseTree::branch(
+                    "E".to_string(),
+                    None,
+                    vec![ParseTree::leaf(SymbolKind::Terminal(Arc::new(
+                        "b".to_string(),
+                    )))],
+                ),
+            ],
+        );
+        assert_eq!(
+            serde_json::to_string(&tree).unwrap(),
+            r#"{"name":"S","children":[{"terminal":"a"},{"name":"E","children":[{"terminal":"b"}]}]}"#
+        );
+    }
+}
@@ -0,0 +1,14 @@
+use crate::parse_tree::tree::ParseTree;
+
+/// Callbacks invoked by [`ParseTree::visit`] during a depth-first traversal,
+/// all of them do nothing by default
+pub trait Visitor<T> {
+    /// called before the children of a branch are visited
+    fn enter_branch(&mut self, _name: &str, _children: &[ParseTree<T>]) {}
+
+    /// called after all the children of a branch are visited
+    fn leave_branch(&mut self, _name: &str, _children: &[ParseTree<T>]) {}
+
+    /// called on every leaf
+    fn visit_leaf(&mut self, _value: &T) {}
+}
@@ -1,97 +1,694 @@
 use crate::error::{Error, Result};
+use crate::generator::{GeneratorSettings, RepetitionStats};
 use crate::span::Span;
+use rand::distributions::{Distribution, WeightedIndex};
 use rand::Rng;
-use regex_syntax::hir::{Class, Hir, HirKind};
+use regex_syntax::hir::{Class, Hir, HirKind, Look};
+use std::collections::HashSet;
 use std::hash::Hash;
 
 #[derive(Debug)]
 pub struct Regex {
     lit: String,
     hir: Hir,
+    /// the maximum count of an unbounded repetition, set by `re("...", max = N)`,
+    /// [`GeneratorSettings::repeat_max`] if unset
+    max: Option<u32>,
 }
 
 impl Hash for Regex {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
         self.lit.hash(state);
+        self.max.hash(state);
     }
 }
 
+/// two regexes are equal if they are written the same, consistently with [`Hash`]
+impl PartialEq for Regex {
+    fn eq(&self, other: &Self) -> bool {
+        self.lit == other.lit && self.max == other.max
+    }
+}
+
+impl Eq for Regex {}
+
 impl Regex {
+    #[cfg(test)]
     fn new(input: &str) -> Self {
         let lit = input.to_string();
         let hir = regex_syntax::Parser::new().parse(input).unwrap();
-        Self { lit, hir }
+        Self {
+            lit,
+            hir,
+            max: None,
+        }
+    }
+
+    /// check if the regex can match the empty string
+    pub(crate) fn is_nullable(&self) -> bool {
+        self.hir.properties().minimum_len() == Some(0)
     }
 
     pub fn spanned(input: &str, l: usize, r: usize) -> Result<Regex> {
+        Self::parse(regex_syntax::Parser::new(), input, l, r)
+    }
+
+    /// A regex over raw bytes, e.g. `[\x00-\xff]{4}`, for `re_bytes("...")`.
+    ///
+    /// Unicode mode is off, so that classes and escapes denote bytes rather than chars,
+    /// and matching invalid UTF-8 is allowed.
+    pub fn spanned_bytes(input: &str, l: usize, r: usize) -> Result<Regex> {
+        let parser = regex_syntax::ParserBuilder::new()
+            .unicode(false)
+            .utf8(false)
+            .build();
+        Self::parse(parser, input, l, r)
+    }
+
+    fn parse(mut parser: regex_syntax::Parser, input: &str, l: usize, r: usize) -> Result<Regex> {
         let lit = input.to_string();
-        let hir = regex_syntax::Parser::new()
+        let hir = parser
             .parse(input)
-            .map_err(|_| Error::InvalidRegex {
+            .ok()
+            .filter(|hir| Self::supported_look(hir, true, true))
+            .ok_or(Error::InvalidRegex {
                 span: Span::new(l, r),
             })?;
-        Ok(Regex { lit, hir })
+        Ok(Regex {
+            lit,
+            hir,
+            max: None,
+        })
+    }
+
+    /// only `^` at the beginning and `$` at the end of the pattern are supported,
+    /// they match the empty string, other look-arounds can not be generated
+    fn supported_look(re: &Hir, at_start: bool, at_end: bool) -> bool {
+        match re.kind() {
+            HirKind::Look(Look::Start) => at_start,
+            HirKind::Look(Look::End) => at_end,
+            HirKind::Look(_) => false,
+            HirKind::Repetition(rep) => Self::supported_look(&rep.sub, false, false),
+            HirKind::Concat(cat) => cat.iter().enumerate().all(|(i, h)| {
+                Self::supported_look(h, at_start && i == 0, at_end && i == cat.len() - 1)
+            }),
+            HirKind::Alternation(alt) => alt
+                .iter()
+                .all(|h| Self::supported_look(h, at_start, at_end)),
+            HirKind::Capture(cap) => Self::supported_look(&cap.sub, at_start, at_end),
+            HirKind::Empty | HirKind::Literal(_) | HirKind::Class(_) => true,
+        }
+    }
+
+    /// override the maximum count of the unbounded repetitions, e.g. `re("[01]+", max = 256)`
+    pub fn with_max(mut self, max: Option<u32>) -> Self {
+        self.max = max.or(self.max);
+        self
+    }
+
+    /// the original pattern of this regex
+    pub fn as_str(&self) -> &str {
+        self.lit.as_str()
+    }
+
+    /// number of strings this regex can produce, `None` if it is infinite
+    pub(crate) fn language_size(&self) -> Option<u64> {
+        Self::size_helper(&self.hir)
+    }
+
+    /// every string this regex can produce, as bytes, `None` if there are more than `max` of them
+    pub(crate) fn language(&self, max: u64) -> Option<Vec<Vec<u8>>> {
+        match self.language_size()? {
+            // an empty product may hide large factors
+            0 => Some(Vec::new()),
+            n if n <= max => Some(Self::language_helper(&self.hir)),
+            _ => None,
+        }
+    }
+
+    fn language_helper(re: &Hir) -> Vec<Vec<u8>> {
+        match re.kind() {
+            HirKind::Empty | HirKind::Look(_) => vec![Vec::new()],
+            HirKind::Literal(lit) => vec![lit.0.to_vec()],
+            HirKind::Class(Class::Unicode(unicode)) => unicode
+                .iter()
+                .flat_map(|r| r.start()..=r.end())
+                .map(|c| c.to_string().into_bytes())
+                .collect(),
+            HirKind::Class(Class::Bytes(bytes)) => bytes
+                .iter()
+                .flat_map(|r| r.start()..=r.end())
+                .map(|b| vec![b])
+                .collect(),
+            HirKind::Repetition(rep) => {
+                let sub = Self::language_helper(&rep.sub);
+                // the language size is finite, so is the max
+                let max = rep.max.unwrap();
+                let mut power = vec![Vec::new()];
+                let mut language = Vec::new();
+                for k in 0..=max {
+                    if k >= rep.min {
+                        language.extend(power.iter().cloned());
+                    }
+                    if k < max {
+                        power = Self::product(&power, &sub);
+                    }
+                }
+                language
+            }
+            HirKind::Concat(cat) => cat.iter().fold(vec![Vec::new()], |acc, h| {
+                Self::product(&acc, &Self::language_helper(h))
+            }),
+            HirKind::Alternation(alt) => alt.iter().flat_map(Self::language_helper).collect(),
+            HirKind::Capture(cap) => Self::language_helper(&cap.sub),
+        }
+    }
+
+    /// every concatenation of a string of `lhs` and a string of `rhs`
+    fn product(lhs: &[Vec<u8>], rhs: &[Vec<u8>]) -> Vec<Vec<u8>> {
+        lhs.iter()
+            .flat_map(|l| {
+                rhs.iter()
+                    .map(move |r| [l.as_slice(), r.as_slice()].concat())
+            })
+            .collect()
+    }
+
+    fn size_helper(re: &Hir) -> Option<u64> {
+        match re.kind() {
+            HirKind::Empty | HirKind::Literal(_) | HirKind::Look(_) => Some(1),
+            HirKind::Class(Class::Unicode(unicode)) => {
+                Some(unicode.iter().map(|r| char_count(r.start(), r.end())).sum())
+            }
+            HirKind::Class(Class::Bytes(bytes)) => Some(
+                bytes
+                    .iter()
+                    .map(|r| r.end() as u64 - r.start() as u64 + 1)
+                    .sum(),
+            ),
+            HirKind::Repetition(rep) => {
+                let sub = Self::size_helper(&rep.sub)?;
+                let max = rep.max?;
+                // sum of sub^k for k in min..=max
+                let mut total = 0u64;
+                let mut power = sub.saturating_pow(rep.min);
+                for _ in rep.min..=max {
+                    total = total.saturating_add(power);
+                    if total == u64::MAX {
+                        break;
+                    }
+                    power = power.saturating_mul(sub);
+                }
+                Some(total)
+            }
+            HirKind::Concat(cat) => cat.iter().try_fold(1u64, |acc, h| {
+                Some(acc.saturating_mul(Self::size_helper(h)?))
+            }),
+            HirKind::Alternation(alt) => alt.iter().try_fold(0u64, |acc, h| {
+                Some(acc.saturating_add(Self::size_helper(h)?))
+            }),
+            HirKind::Capture(cap) => Self::size_helper(&cap.sub),
+        }
     }
 
-    pub fn generate<R: Rng>(&self, rng: &mut R, terminals: &[&str]) -> String {
+    /// Return [`Error::RegexTooLarge`] if the generated string would be longer than
+    /// [`GeneratorSettings::max_regex_len`], e.g. for a typo like `a{1000000000}`.
+    ///
+    /// Return [`Error::RegexCollisionLimit`] if every attempt out of
+    /// [`GeneratorSettings::max_regex_attempts`] generates a terminal.
+    ///
+    /// The counts chosen for the repetitions of the returned string are added to `stats`.
+    pub fn generate<R: Rng>(
+        &self,
+        rng: &mut R,
+        terminals: &HashSet<String>,
+        settings: &GeneratorSettings,
+        mut stats: Option<&mut RepetitionStats>,
+    ) -> Result<String> {
+        settings.repeat_dist.check()?;
+        let mut reps = Vec::new();
         // if regex produce a string that is a terminal, re-generate it
-        loop {
-            let s = Self::helper(&self.hir, rng);
-            if !terminals.contains(&s.as_str()) {
-                return s;
+        for _ in 0..settings.max_regex_attempts {
+            let mut s = String::new();
+            reps.clear();
+            let record = stats.is_some().then_some(&mut reps);
+            if self
+                .helper(&self.hir, rng, settings, &mut s, record)
+                .is_none()
+            {
+                return Err(Error::RegexTooLarge {
+                    pattern: self.lit.clone(),
+                    limit: settings.max_regex_len,
+                });
+            }
+            if !terminals.contains(&s) {
+                self.record(&reps, stats.as_deref_mut());
+                return Ok(s);
+            }
+        }
+        Err(self.collision_limit(settings))
+    }
+
+    /// Generate raw bytes like [`generate`](Self::generate), which may not be valid UTF-8
+    /// if the regex was built by [`spanned_bytes`](Self::spanned_bytes).
+    pub fn generate_bytes<R: Rng>(
+        &self,
+        rng: &mut R,
+        terminals: &HashSet<String>,
+        settings: &GeneratorSettings,
+        mut stats: Option<&mut RepetitionStats>,
+    ) -> Result<Vec<u8>> {
+        settings.repeat_dist.check()?;
+        let mut reps = Vec::new();
+        for _ in 0..settings.max_regex_attempts {
+            let mut bytes = Vec::new();
+            reps.clear();
+            let record = stats.is_some().then_some(&mut reps);
+            if self
+                .helper(&self.hir, rng, settings, &mut bytes, record)
+                .is_none()
+            {
+                return Err(Error::RegexTooLarge {
+                    pattern: self.lit.clone(),
+                    limit: settings.max_regex_len,
+                });
+            }
+            if !std::str::from_utf8(&bytes).is_ok_and(|s| terminals.contains(s)) {
+                self.record(&reps, stats.as_deref_mut());
+                return Ok(bytes);
             }
         }
+        Err(self.collision_limit(settings))
     }
 
-    fn helper<R: Rng>(re: &Hir, rng: &mut R) -> String {
+    fn collision_limit(&self, settings: &GeneratorSettings) -> Error {
+        Error::RegexCollisionLimit {
+            pattern: self.lit.clone(),
+            attempts: settings.max_regex_attempts,
+        }
+    }
+
+    fn record(&self, reps: &[(&Hir, u32)], stats: Option<&mut RepetitionStats>) {
+        if let Some(stats) = stats {
+            for (rep, count) in reps {
+                stats.record(&self.lit, rep.to_string(), *count);
+            }
+        }
+    }
+
+    /// append the generated string to `buf`, return `None` as soon as it exceeds the limit,
+    /// the repetitions along with their chosen counts are pushed to `reps` if given
+    fn helper<'h, R: Rng, B: Buffer>(
+        &self,
+        re: &'h Hir,
+        rng: &mut R,
+        settings: &GeneratorSettings,
+        buf: &mut B,
+        mut reps: Option<&mut Vec<(&'h Hir, u32)>>,
+    ) -> Option<()> {
         match re.kind() {
-            HirKind::Empty => String::new(),
-            HirKind::Literal(lit) => String::from_utf8(lit.0.clone().into()).unwrap(),
+            HirKind::Empty => {}
+            HirKind::Literal(lit) => buf.push_bytes(&lit.0),
             HirKind::Repetition(rep) => {
-                let mut buf = Vec::new();
-                // todo: allow manually set the max reps
-                for _ in 0..rng.gen_range(rep.min..=rep.max.unwrap_or(5)) {
-                    buf.push(Self::helper(&rep.sub, rng));
+                // an unbounded repetition is capped, but never below its minimum
+                let cap = self.max.unwrap_or(settings.repeat_max);
+                let max = rep.max.unwrap_or(cap.max(rep.min));
+                let count = settings.repeat_dist.sample(rng, rep.min, max);
+                if let Some(reps) = reps.as_deref_mut() {
+                    reps.push((re, count));
+                }
+                for _ in 0..count {
+                    self.helper(&rep.sub, rng, settings, buf, reps.as_deref_mut())?;
+                }
+            }
+            HirKind::Concat(cat) => {
+                for h in cat {
+                    self.helper(h, rng, settings, buf, reps.as_deref_mut())?;
                 }
-                buf.join("")
             }
-            HirKind::Concat(cat) => cat.iter().map(|h| Self::helper(h, rng)).collect(),
             HirKind::Alternation(alt) => {
                 let idx = rng.gen_range(0..alt.len());
-                Self::helper(&alt[idx], rng)
+                self.helper(&alt[idx], rng, settings, buf, reps)?;
             }
+            // ranges are weighted by their size so that every char in the class is equiprobable
             HirKind::Class(cls) => match cls {
                 Class::Unicode(unicode) => {
-                    let idx = rng.gen_range(0..unicode.iter().count());
-                    let range = unicode.iter().nth(idx).unwrap();
-                    let pick = rng.gen_range(range.start()..=range.end());
-                    pick.to_string()
+                    // a class such as `\p{L}` has hundreds of ranges, walk them instead of
+                    // building a weighted index for every char
+                    let total = unicode.iter().map(|r| char_count(r.start(), r.end())).sum();
+                    let mut k = rng.gen_range(0..total);
+                    for range in unicode.iter() {
+                        let size = char_count(range.start(), range.end());
+                        if k < size {
+                            let pick = (range.start()..=range.end()).nth(k as usize).unwrap();
+                            buf.push_str(&Self::maybe_flip_case(pick, rng, settings));
+                            break;
+                        }
+                        k -= size;
+                    }
                 }
                 Class::Bytes(bytes) => {
-                    let idx = rng.gen_range(0..bytes.iter().count());
-                    let range = bytes.iter().nth(idx).unwrap();
-                    let pick = rng.gen_range(range.start()..=range.end()) as char;
-                    pick.to_string()
+                    let sizes = bytes.iter().map(|r| r.end() as u32 - r.start() as u32 + 1);
+                    let idx = WeightedIndex::new(sizes).unwrap().sample(rng);
+                    let range = bytes.ranges()[idx];
+                    let pick = rng.gen_range(range.start()..=range.end());
+                    match pick.is_ascii() {
+                        true => buf.push_str(&Self::maybe_flip_case(pick as char, rng, settings)),
+                        false => buf.push_bytes(&[pick]),
+                    }
                 }
             },
-            HirKind::Look(_) => todo!(),
-            HirKind::Capture(cap) => Self::helper(&cap.sub, rng),
+            // anchors are validated in `Regex::spanned`, they match the empty string
+            HirKind::Look(_) => {}
+            HirKind::Capture(cap) => self.helper(&cap.sub, rng, settings, buf, reps)?,
+        }
+        (buf.len() <= settings.max_regex_len).then_some(())
+    }
+
+    /// flip the case of a cased char with a probability of 1/2 if `randomize_case` is set
+    fn maybe_flip_case<R: Rng>(c: char, rng: &mut R, settings: &GeneratorSettings) -> String {
+        if !settings.randomize_case || !(c.is_lowercase() || c.is_uppercase()) {
+            return c.to_string();
         }
+        match (rng.gen_bool(0.5), c.is_lowercase()) {
+            (false, _) => c.to_string(),
+            (true, true) => c.to_uppercase().collect(),
+            (true, false) => c.to_lowercase().collect(),
+        }
+    }
+}
+
+/// the number of chars in `start..=end`, which excludes the surrogates
+fn char_count(start: char, end: char) -> u64 {
+    let (start, end) = (start as u64, end as u64);
+    let surrogates = match start < 0xD800 && end > 0xDFFF {
+        true => 0x800,
+        false => 0,
+    };
+    end - start + 1 - surrogates
+}
+
+/// the output of [`Regex::helper`], either a string or raw bytes
+trait Buffer {
+    fn push_str(&mut self, s: &str);
+    fn push_bytes(&mut self, bytes: &[u8]);
+    /// length in bytes
+    fn len(&self) -> usize;
+}
+
+impl Buffer for String {
+    fn push_str(&mut self, s: &str) {
+        String::push_str(self, s)
+    }
+
+    /// a regex in unicode mode only produces valid UTF-8
+    fn push_bytes(&mut self, bytes: &[u8]) {
+        String::push_str(self, std::str::from_utf8(bytes).unwrap())
+    }
+
+    fn len(&self) -> usize {
+        String::len(self)
+    }
+}
+
+impl Buffer for Vec<u8> {
+    fn push_str(&mut self, s: &str) {
+        self.extend_from_slice(s.as_bytes())
+    }
+
+    fn push_bytes(&mut self, bytes: &[u8]) {
+        self.extend_from_slice(bytes)
+    }
+
+    fn len(&self) -> usize {
+        Vec::len(self)
     }
 }
 
 #[cfg(test)]
 mod test {
+    use crate::generator::GeneratorSettings;
     use rand::rngs::StdRng;
     use rand::SeedableRng;
+    use std::collections::HashSet;
 
     #[test]
     fn it_works() {
         let mut rng = StdRng::seed_from_u64(42);
         let re = super::Regex::new("[a-zA-Z0-9]*");
         let generated = (0..10)
-            .map(|_| re.generate(&mut rng, &["M"]))
+            .map(|_| {
+                re.generate(
+                    &mut rng,
+                    &HashSet::from(["M".to_string()]),
+                    &Default::default(),
+                    None,
+                )
+                .unwrap()
+            })
             .collect::<Vec<_>>();
         insta::assert_debug_snapshot!(generated);
     }
+
+    #[test]
+    fn randomize_case() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let settings = GeneratorSettings::builder().randomize_case(true).build();
+        let re = super::Regex::new("[a-z][0-9]");
+        let generated = (0..100)
+            .map(|_| {
+                re.generate(&mut rng, &HashSet::new(), &settings, None)
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        assert!(generated
+            .iter()
+            .any(|s| s.starts_with(|c: char| c.is_uppercase())));
+        assert!(generated
+            .iter()
+            .any(|s| s.starts_with(|c: char| c.is_lowercase())));
+        assert!(generated
+            .iter()
+            .all(|s| s.ends_with(|c: char| c.is_ascii_digit())));
+    }
+
+    #[test]
+    fn class_weighted_by_range_size() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let re = super::Regex::new("[a-b0-9]");
+        let digits = (0..12000)
+            .map(|_| {
+                re.generate(&mut rng, &HashSet::new(), &Default::default(), None)
+                    .unwrap()
+            })
+            .filter(|s| s.chars().all(|c| c.is_ascii_digit()))
+            .count();
+        // expect ~10/12 of the generated chars to be digits
+        assert!((9600..10400).contains(&digits), "{}", digits);
+    }
+
+    fn class(pattern: &str) -> regex_syntax::hir::ClassUnicode {
+        match regex_syntax::Parser::new()
+            .parse(pattern)
+            .unwrap()
+            .into_kind()
+        {
+            regex_syntax::hir::HirKind::Class(regex_syntax::hir::Class::Unicode(class)) => class,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn unicode_classes() {
+        let mut rng = StdRng::seed_from_u64(42);
+        for pattern in ["\\p{Greek}", "\\p{L}", "\\P{L}", "\\p{Lu}", "\\p{Han}"] {
+            let re = super::Regex::new(&format!("{}{{1000}}", pattern));
+            let class = class(pattern);
+            let generated = re
+                .generate(&mut rng, &HashSet::new(), &Default::default(), None)
+                .unwrap();
+            assert_eq!(generated.chars().count(), 1000);
+            assert!(generated.chars().all(|c| class
+                .ranges()
+                .iter()
+                .any(|r| (r.start()..=r.end()).contains(&c))));
+        }
+    }
+
+    #[test]
+    fn negated_classes() {
+        let mut rng = StdRng::seed_from_u64(42);
+        // the complement is normalized into the ranges around the negated ones
+        let re = super::Regex::new("[^a-z]");
+        assert_eq!(re.language_size(), Some(0x110000 - 0x800 - 26));
+        let generated = super::Regex::new("[^a-z]{1000}")
+            .generate(&mut rng, &HashSet::new(), &Default::default(), None)
+            .unwrap();
+        assert_eq!(generated.chars().count(), 1000);
+        assert!(!generated.chars().any(|c| c.is_ascii_lowercase()));
+
+        let re = super::Regex::spanned_bytes("[^a-z]", 0, 0).unwrap();
+        assert_eq!(re.language_size(), Some(256 - 26));
+        let re = super::Regex::spanned_bytes("[^\\x00-\\x1f]{1000}", 0, 0).unwrap();
+        let generated = re
+            .generate_bytes(&mut rng, &HashSet::new(), &Default::default(), None)
+            .unwrap();
+        assert_eq!(generated.len(), 1000);
+        assert!(generated.iter().all(|b| *b >= 0x20));
+        // both the ASCII and the upper half of the complement are sampled
+        assert!(generated.iter().any(|b| b.is_ascii()));
+        assert!(generated.iter().any(|b| !b.is_ascii()));
+        let re = super::Regex::spanned_bytes("[^a-z]{1000}", 0, 0).unwrap();
+        let generated = re
+            .generate_bytes(&mut rng, &HashSet::new(), &Default::default(), None)
+            .unwrap();
+        assert!(!generated.iter().any(|b| b.is_ascii_lowercase()));
+        assert!(generated.iter().any(|b| *b < b'a'));
+        assert!(generated.iter().any(|b| *b > b'z'));
+    }
+
+    #[test]
+    fn class_size_without_surrogates() {
+        // `\u{D7FF}` and `\u{E000}` are adjacent chars, so they form a single range of 2 chars
+        let mut rng = StdRng::seed_from_u64(42);
+        let re = super::Regex::new("[a\\x{D7FF}\\x{E000}]");
+        assert_eq!(re.language_size(), Some(3));
+        let a = (0..3000)
+            .map(|_| {
+                re.generate(&mut rng, &HashSet::new(), &Default::default(), None)
+                    .unwrap()
+            })
+            .filter(|s| s == "a")
+            .count();
+        assert!((800..1200).contains(&a), "{}", a);
+    }
+
+    #[test]
+    fn language_size() {
+        assert_eq!(super::Regex::new("[a-z0-9]").language_size(), Some(36));
+        assert_eq!(
+            super::Regex::new("(a|bc)[01]{1,2}").language_size(),
+            Some(12)
+        );
+        assert_eq!(super::Regex::new("a+").language_size(), None);
+    }
+
+    #[test]
+    fn language() {
+        let re = super::Regex::new("(a|bc)[01]{0,1}");
+        let mut language = re.language(6).unwrap();
+        language.sort();
+        let expected = ["a", "a0", "a1", "bc", "bc0", "bc1"];
+        assert_eq!(language, expected.map(|s| s.as_bytes().to_vec()));
+        assert_eq!(re.language(5), None);
+        assert_eq!(super::Regex::new("a*").language(u64::MAX), None);
+    }
+
+    #[test]
+    fn anchors() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let re = super::Regex::spanned("^[a-z]+$", 0, 0).unwrap();
+        let generated = re
+            .generate(&mut rng, &HashSet::new(), &Default::default(), None)
+            .unwrap();
+        assert!(generated.chars().all(|c| c.is_ascii_lowercase()));
+        assert!(super::Regex::spanned("a|^b$", 0, 0).is_ok());
+    }
+
+    #[test]
+    fn too_large() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let re = super::Regex::new("a{1000000000}");
+        let err = re
+            .generate(&mut rng, &HashSet::new(), &Default::default(), None)
+            .unwrap_err();
+        assert_eq!(
+            err,
+            crate::error::Error::RegexTooLarge {
+                pattern: "a{1000000000}".to_string(),
+                limit: 1 << 20,
+            }
+        );
+        let settings = GeneratorSettings::builder().max_regex_len(3).build();
+        let re = super::Regex::new("[a-z]{3}");
+        assert_eq!(
+            re.generate(&mut rng, &HashSet::new(), &settings, None)
+                .unwrap()
+                .len(),
+            3
+        );
+    }
+
+    #[test]
+    fn collision_limit() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let terminals = HashSet::from(["a".to_string()]);
+        let err = super::Regex::new("a")
+            .generate(&mut rng, &terminals, &Default::default(), None)
+            .unwrap_err();
+        assert_eq!(
+            err,
+            crate::error::Error::RegexCollisionLimit {
+                pattern: "a".to_string(),
+                attempts: 1000,
+            }
+        );
+        let settings = GeneratorSettings::builder().max_regex_attempts(10).build();
+        let err = super::Regex::spanned_bytes("a", 0, 0)
+            .unwrap()
+            .generate_bytes(&mut rng, &terminals, &settings, None)
+            .unwrap_err();
+        assert_eq!(
+            err,
+            crate::error::Error::RegexCollisionLimit {
+                pattern: "a".to_string(),
+                attempts: 10,
+            }
+        );
+        // a terminal only now and then is still avoided
+        let generated = super::Regex::new("a|b")
+            .generate(&mut rng, &terminals, &settings, None)
+            .unwrap();
+        assert_eq!(generated, "b");
+    }
+
+    #[test]
+    fn bytes() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let re = super::Regex::spanned_bytes(r"\xff[\x80-\xfe]{3}a", 0, 0).unwrap();
+        let generated = re
+            .generate_bytes(&mut rng, &HashSet::new(), &Default::default(), None)
+            .unwrap();
+        assert_eq!(generated.len(), 5);
+        assert_eq!(generated[0], 0xff);
+        assert!(generated[1..4].iter().all(|b| (0x80..=0xfe).contains(b)));
+        assert_eq!(generated[4], b'a');
+        // invalid UTF-8 is rejected in unicode mode
+        assert!(super::Regex::spanned(r"(?-u:\xff)", 0, 0).is_err());
+    }
+
+    #[test]
+    fn max() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let settings = GeneratorSettings::builder()
+            .repeat_dist(crate::generator::RepeatDist::Geometric { p: 0.0 })
+            .build();
+        let len = |re: &super::Regex, rng: &mut StdRng| {
+            re.generate(rng, &HashSet::new(), &settings, None)
+                .unwrap()
+                .len()
+        };
+        // a geometric distribution never succeeding always reaches the max
+        assert_eq!(len(&super::Regex::new("a*"), &mut rng), 5);
+        assert_eq!(len(&super::Regex::new("a{8,}"), &mut rng), 8);
+        let re = super::Regex::new("a+").with_max(Some(256));
+        assert_eq!(len(&re, &mut rng), 256);
+    }
+
+    #[test]
+    fn unsupported_look() {
+        for re in [r"a\b", "a^b", "(^a)*", "a$b"] {
+            assert!(super::Regex::spanned(re, 0, 0).is_err(), "{}", re);
+        }
+    }
 }
@@ -1,4 +1,6 @@
 use miette::{GraphicalReportHandler, GraphicalTheme, Report};
+use std::fmt;
+use std::io;
 
 #[derive(Debug)]
 pub enum Style {
@@ -44,6 +46,20 @@ impl Reporter {
         Ok(())
     }
 
+    /// write the diagnostics directly to an io sink, e.g. `stderr`
+    pub fn report_io<W: io::Write>(&self, writer: &mut W) -> io::Result<()> {
+        let mut adapter = IoAdapter {
+            inner: writer,
+            error: None,
+        };
+        match self.report(&mut adapter) {
+            Ok(()) => Ok(()),
+            Err(fmt::Error) => Err(adapter
+                .error
+                .unwrap_or_else(|| io::Error::other("failed to format the diagnostics"))),
+        }
+    }
+
     pub fn report_to_string(&self) -> String {
         let mut buffer = String::new();
         self.report(&mut buffer).unwrap();
@@ -54,3 +70,41 @@ impl Reporter {
         !self.diagnostics.is_empty()
     }
 }
+
+/// bridge a `std::io::Write` to `std::fmt::Write`, keeping the underlying io error
+struct IoAdapter<'a, W: io::Write> {
+    inner: &'a mut W,
+    error: Option<io::Error>,
+}
+
+impl<W: io::Write> fmt::Write for IoAdapter<'_, W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.inner.write_all(s.as_bytes()).map_err(|e| {
+            self.error = Some(e);
+            fmt::Error
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::grammar::raw::RawGrammar;
+    use crate::report::{Reporter, Style};
+    use miette::Report;
+
+    #[test]
+    fn report_io() {
+        let text = r#"<S> ::= <A> ;"#;
+        let err = RawGrammar::parse(text)
+            .unwrap()
+            .check_undefined()
+            .err()
+            .unwrap();
+        let mut reporter = Reporter::new(Style::NoColor);
+        reporter.push(Report::from(err).with_source_code(text.to_string()));
+
+        let mut buf = Vec::new();
+        reporter.report_io(&mut buf).unwrap();
+        assert_eq!(String::from_utf8(buf).unwrap(), reporter.report_to_string());
+    }
+}
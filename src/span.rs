@@ -1,6 +1,7 @@
 use miette::SourceSpan;
 
-#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Span {
     start: usize,
     end: usize,
@@ -10,6 +11,25 @@ impl Span {
     pub fn new(start: usize, end: usize) -> Self {
         Self { start, end }
     }
+
+    /// byte offset of the start (inclusive)
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    /// byte offset of the end (exclusive)
+    pub fn end(&self) -> usize {
+        self.end
+    }
+
+    /// 1-based line and column (counted in chars) of the start in `source`
+    pub fn line_col(&self, source: &str) -> (usize, usize) {
+        let before = &source[..self.start.min(source.len())];
+        let line = before.matches('\n').count() + 1;
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        let col = before[line_start..].chars().count() + 1;
+        (line, col)
+    }
 }
 
 impl From<Span> for SourceSpan {
@@ -26,3 +46,19 @@ impl From<logos::Span> for Span {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::Span;
+
+    #[test]
+    fn line_col() {
+        let source = "<S> ::= <E> ;\n<E> ::= \"é\" <F> ;\n";
+        assert_eq!(Span::new(0, 3).line_col(source), (1, 1));
+        assert_eq!(Span::new(8, 11).line_col(source), (1, 9));
+        assert_eq!(Span::new(14, 17).line_col(source), (2, 1));
+        // columns are counted in chars rather than bytes
+        let offset = source.find("<F>").unwrap();
+        assert_eq!(Span::new(offset, offset + 3).line_col(source), (2, 13));
+    }
+}
@@ -3,14 +3,31 @@ use std::num::ParseIntError;
 use crate::span::Span;
 use logos::Logos;
 
+/// Where the lexer is, the argument of `re(...)` and `re_bytes(...)` keeps the escapes
+/// unknown to string literals, e.g. `re("\d+")`, for the regex to interpret them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Context {
+    #[default]
+    Normal,
+    /// right after `re` or `re_bytes`
+    Regex,
+    /// right after `re(` or `re_bytes(`
+    RegexArg,
+}
+
 #[derive(Logos, Debug, PartialEq, Clone)]
-#[logos(skip r"[ \t\n\f]+", skip r"//.*\n?", error = LexicalError)]
+#[logos(skip r"[ \t\n\f]+", skip r"//.*\n?", error = LexicalError, extras = Context)]
 pub enum Token {
     #[token("{")]
     LBrace,
     #[token("}")]
     RBrace,
-    #[token("(")]
+    #[token("(", |lex| {
+        lex.extras = match lex.extras {
+            Context::Regex => Context::RegexArg,
+            _ => Context::Normal,
+        }
+    })]
     LParen,
     #[token(")")]
     RParen,
@@ -18,6 +35,8 @@ pub enum Token {
     Or,
     #[token(",")]
     Comma,
+    #[token("=")]
+    Eq,
     #[token(":")]
     Colon,
     #[token("::=")]
@@ -28,8 +47,30 @@ pub enum Token {
     RAngle,
     #[token(";")]
     Semi,
-    #[token("re")]
+    #[token("%")]
+    Percent,
+    #[token("%include")]
+    Include,
+    #[token("..")]
+    DotDot,
+    #[token("re", |lex| lex.extras = Context::Regex)]
     Re,
+    #[token("re_bytes", |lex| lex.extras = Context::Regex)]
+    ReBytes,
+    #[token("decl")]
+    Decl,
+    #[token("ref")]
+    Ref,
+    #[token("note")]
+    Note,
+    #[token("off")]
+    Off,
+    #[token("like")]
+    Like,
+    #[regex("@[a-zA-Z-_0-9]+", |lex| lex.slice()[1..].to_string())]
+    Label(String),
+    #[regex(r"\$[a-zA-Z_0-9]+", |lex| lex.slice()[1..].to_string())]
+    Builtin(String),
     #[rustfmt::skip]
     #[regex("[0-9]|[1-9][0-9]*", |lex| {
         match lex.slice().parse::<usize>() {
@@ -38,18 +79,26 @@ pub enum Token {
         }
     })]
     Int(usize),
+    #[regex(r"[0-9]+\.[0-9]+", |lex| lex.slice().parse::<f64>().unwrap())]
+    Float(f64),
     #[regex("[a-zA-Z-_0-9]*", |lex| lex.slice().to_string())]
     Id(String),
-    #[rustfmt::skip]
-    #[regex(r#""(\\["nrt\\]|[^"\\])*""#, |lex| {
+    #[regex(r#""(\\.|[^"\\])*""#, |lex| {
         let text = &lex.slice()[1..lex.slice().len() - 1];
-        text.replace("\\\"", "\"")
-            .replace("\\n", "\n")
-            .replace("\\t", "\t")
-            .replace("\\r", "\r")
-            .to_string()
+        let regex = std::mem::take(&mut lex.extras) == Context::RegexArg;
+        unescape(text, lex.span().start + 1, regex)
     })]
     Str(String),
+    #[regex(r"'(\\'|\\[^']+|[^'\\])'", |lex| {
+        let text = &lex.slice()[1..lex.slice().len() - 1];
+        let s = unescape(text, lex.span().start + 1, false)?;
+        let mut chars = s.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => Ok(c),
+            _ => Err(LexicalError::InvalidChar(lex.span().into())),
+        }
+    })]
+    Char(char),
 }
 
 #[derive(thiserror::Error, miette::Diagnostic, Default, Debug, Clone, PartialEq, Eq)]
@@ -58,8 +107,147 @@ pub enum LexicalError {
     InvalidInteger(ParseIntError, #[label("this int is invalid")] Span),
     #[error("Invalid token")]
     InvalidToken(#[label("this token is invalid")] Span),
+    #[error("Invalid escape sequence")]
+    InvalidEscape(#[label("this escape is invalid")] Span),
+    #[error("Invalid char literal")]
+    InvalidChar(#[label("this literal should contain exactly one char")] Span),
     // see: https://github.com/maciejhirsz/logos/issues/352
     #[default]
     #[error("Internal Error. Please file an issue if you see this")]
     InternalInvalidToken,
 }
+
+/// unescape the content of a string literal starting at `offset` of the source,
+/// supporting `\"`, `\'`, `\\`, `\n`, `\t`, `\r`, `\0`, `\xNN` and `\u{...}`,
+/// the other escapes are invalid unless `keep_unknown`, which keeps them as is, e.g. `\d`
+fn unescape(text: &str, offset: usize, keep_unknown: bool) -> Result<String, LexicalError> {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(pos) = rest.find('\\') {
+        out.push_str(&rest[..pos]);
+        let escape = &rest[pos..];
+        // the backslash and the char following it
+        let unknown = escape[1..].chars().next().map_or(1, |c| 1 + c.len_utf8());
+        match parse_escape(escape) {
+            Some((c, len)) => {
+                out.push(c);
+                rest = &escape[len..];
+            }
+            None if keep_unknown => {
+                out.push_str(&escape[..unknown]);
+                rest = &escape[unknown..];
+            }
+            None => {
+                let start = offset + text.len() - escape.len();
+                return Err(LexicalError::InvalidEscape(Span::new(
+                    start,
+                    start + unknown,
+                )));
+            }
+        }
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// parse the escape at the beginning of `s`, return the char and the length of the escape
+fn parse_escape(s: &str) -> Option<(char, usize)> {
+    let from_hex = |hex: &str| {
+        if hex.is_empty() || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        char::from_u32(u32::from_str_radix(hex, 16).ok()?)
+    };
+    let c = match s[1..].chars().next()? {
+        '"' => '"',
+        '\'' => '\'',
+        '\\' => '\\',
+        'n' => '\n',
+        't' => '\t',
+        'r' => '\r',
+        '0' => '\0',
+        'x' => return Some((from_hex(s.get(2..4)?)?, 4)),
+        'u' => {
+            let end = s.find('}')?;
+            let hex = s.strip_prefix("\\u{")?.get(..end - 3)?;
+            if hex.len() > 6 {
+                return None;
+            }
+            return Some((from_hex(hex)?, end + 1));
+        }
+        _ => return None,
+    };
+    Some((c, 2))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::span::Span;
+    use crate::token::{LexicalError, Token};
+    use logos::Logos;
+
+    fn lex_str(input: &str) -> Result<Token, LexicalError> {
+        Token::lexer(input).next().unwrap()
+    }
+
+    #[test]
+    fn unescape() {
+        assert_eq!(
+            lex_str(r#""\u{1F600}""#),
+            Ok(Token::Str("\u{1F600}".to_string()))
+        );
+        assert_eq!(
+            lex_str(r#""\x1b[0m\0\"\\\n""#),
+            Ok(Token::Str("\x1b[0m\0\"\\\n".to_string()))
+        );
+    }
+
+    #[test]
+    fn regex_escapes() {
+        let lex = |input: &str| {
+            Token::lexer(input)
+                .filter_map(|tok| match tok {
+                    Ok(Token::Str(s)) => Some(Ok(s)),
+                    Ok(_) => None,
+                    Err(e) => Some(Err(e)),
+                })
+                .collect::<Result<Vec<_>, _>>()
+        };
+        // escapes unknown to string literals are kept for the regex only
+        assert_eq!(lex(r#"re("\d+")"#), Ok(vec![r"\d+".to_string()]));
+        assert_eq!(lex(r#"re("\\d+")"#), Ok(vec![r"\d+".to_string()]));
+        assert_eq!(
+            lex(r#"re_bytes("\.\x41", max = 3) "\t""#),
+            Ok(vec![r"\.A".to_string(), "\t".to_string()])
+        );
+        assert!(lex(r#"re("\d") "\d""#).is_err());
+        assert!(lex(r#"note("\d")"#).is_err());
+    }
+
+    #[test]
+    fn char_literal() {
+        assert_eq!(lex_str("'a'"), Ok(Token::Char('a')));
+        assert_eq!(lex_str(r"'\''"), Ok(Token::Char('\'')));
+        assert_eq!(lex_str(r"'\u{1F600}'"), Ok(Token::Char('\u{1F600}')));
+        assert_eq!(
+            lex_str(r"'\x41\x42'"),
+            Err(LexicalError::InvalidChar(Span::new(0, 10)))
+        );
+    }
+
+    #[test]
+    fn invalid_escape() {
+        assert_eq!(
+            lex_str(r#""ab\u{110000}""#),
+            Err(LexicalError::InvalidEscape(Span::new(3, 5)))
+        );
+        assert_eq!(
+            lex_str(r#""\xZZ""#),
+            Err(LexicalError::InvalidEscape(Span::new(1, 3)))
+        );
+        assert_eq!(
+            lex_str(r#""\q""#),
+            Err(LexicalError::InvalidEscape(Span::new(1, 3)))
+        );
+    }
+}